@@ -0,0 +1,32 @@
+//! Receives from a queue of 100k messages where nearly all are in flight, the case a
+//! linear scan of the queue handles worst. Run with `cargo bench --bench receive`.
+
+use sqs_navbat::queue::{Message, Queue};
+use std::time::Instant;
+
+const MESSAGES: usize = 100_000;
+const RECEIVES: usize = 1_000;
+const BATCH: u32 = 10;
+
+fn main() {
+    let mut queue = Queue::new("bench", vec![], None);
+    for i in 0..MESSAGES {
+        queue.push(Message::new(i.to_string(), "body".to_string()));
+    }
+    // Leave just enough visible messages for the timed receives
+    let in_flight = MESSAGES - RECEIVES * BATCH as usize;
+    assert_eq!(queue.receive(in_flight as u32, Some(3600)).len(), in_flight);
+
+    let started = Instant::now();
+    for _ in 0..RECEIVES {
+        assert_eq!(queue.receive(BATCH, Some(3600)).len(), BATCH as usize);
+    }
+    let elapsed = started.elapsed();
+    println!(
+        "receive of {} from {} messages ({} in flight): {:?} per call",
+        BATCH,
+        MESSAGES,
+        in_flight,
+        elapsed / RECEIVES as u32
+    );
+}
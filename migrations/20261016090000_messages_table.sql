@@ -0,0 +1,17 @@
+-- Messages flushed on shutdown so a restart resumes where it left off.
+-- Times are unix epoch milliseconds.
+CREATE TABLE IF NOT EXISTS messages (
+	id INTEGER,
+	queue_id INTEGER NOT NULL,
+	message_id TEXT NOT NULL,
+	body TEXT NOT NULL,
+	receipt_handle TEXT,
+	receive_count INTEGER NOT NULL DEFAULT 0,
+	visible_at INTEGER NOT NULL,
+	first_received_at INTEGER,
+	message_group_id TEXT,
+	message_deduplication_id TEXT,
+	sequence_number TEXT,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY(id AUTOINCREMENT)
+);
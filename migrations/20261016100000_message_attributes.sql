@@ -0,0 +1,2 @@
+-- Message attributes as a JSON array
+ALTER TABLE messages ADD COLUMN message_attributes TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+-- Message system attributes (AWSTraceHeader) as a JSON array
+ALTER TABLE messages ADD COLUMN message_system_attributes TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+-- When the message was sent, as unix epoch millis; NULL for messages saved before this column
+ALTER TABLE messages ADD COLUMN sent_at INTEGER;
@@ -0,0 +1,513 @@
+//! Debugging endpoints under `/admin`, only registered when the server runs with `--admin`.
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::AppState;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_messages)
+        .service(delete_message)
+        .service(metrics_json)
+        .service(advance_time)
+        .service(reset);
+}
+
+#[derive(Debug, Deserialize)]
+struct ListMessagesQuery {
+    /// Also list delayed and in-flight messages
+    #[serde(default)]
+    include_invisible: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ListMessagesResponse {
+    queue: String,
+    /// Seconds since the oldest visible message was sent
+    approximate_age_of_oldest_message_secs: u64,
+    messages: Vec<MessageJson>,
+}
+
+#[derive(Debug, Serialize)]
+struct MessageJson {
+    id: String,
+    /// Arrival order within the queue
+    enqueue_sequence: u64,
+    body: String,
+    receive_count: u32,
+    /// Seconds until the message can be received; 0 if it can be now
+    visible_in_seconds: u64,
+    in_flight: bool,
+}
+
+/// One queue's entry in `/admin/metrics.json`, named after its CloudWatch metrics.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct QueueMetricsJson {
+    approximate_number_of_messages: usize,
+    approximate_number_of_messages_not_visible: usize,
+    approximate_number_of_messages_delayed: usize,
+    /// Seconds
+    approximate_age_of_oldest_message: u64,
+    number_of_messages_sent: u64,
+    number_of_messages_received: u64,
+    number_of_messages_deleted: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct MetricsJsonResponse {
+    queues: BTreeMap<String, QueueMetricsJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AdvanceTimeQuery {
+    secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ResetResponse {
+    queues_removed: usize,
+}
+
+/// Every message of a queue in queue order, e.g.
+/// `GET /admin/queues/myqueue/messages?include_invisible=true`.
+#[get("/admin/queues/{name}/messages")]
+async fn list_messages(
+    app_state: web::Data<AppState>,
+    name: web::Path<String>,
+    query: web::Query<ListMessagesQuery>,
+) -> HttpResponse {
+    let name = name.into_inner();
+    let queue = match app_state.get_queue(&name).await {
+        Some(queue) => queue,
+        None => return HttpResponse::NotFound().body(format!("No such queue: {}", name)),
+    };
+
+    let (approximate_age_of_oldest_message_secs, messages) = {
+        let queue = queue.lock().await;
+        let now = queue.now();
+        let messages = queue
+            .messages()
+            .filter(|m| query.include_invisible || m.visible_at <= now)
+            .map(|m| MessageJson {
+                id: m.id.clone(),
+                enqueue_sequence: m.enqueue_sequence,
+                body: m.message_body.clone(),
+                receive_count: m.receive_count,
+                visible_in_seconds: m.visible_at.saturating_duration_since(now).as_secs(),
+                in_flight: m.is_in_flight(now),
+            })
+            .collect();
+        (queue.approximate_age_of_oldest_message_secs(), messages)
+    };
+
+    HttpResponse::Ok().json(ListMessagesResponse {
+        queue: name,
+        approximate_age_of_oldest_message_secs,
+        messages,
+    })
+}
+
+/// Delete a message by id, in flight or not, e.g. to clean up after a test:
+/// `DELETE /admin/queues/myqueue/messages/<message id>`. Not an SQS API.
+#[delete("/admin/queues/{name}/messages/{id}")]
+async fn delete_message(
+    app_state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (name, id) = path.into_inner();
+    let queue = match app_state.get_queue(&name).await {
+        Some(queue) => queue,
+        None => return HttpResponse::NotFound().body(format!("No such queue: {}", name)),
+    };
+
+    if queue.lock().await.remove(&id) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().body(format!("No message {} in queue {}", id, name))
+    }
+}
+
+/// CloudWatch-style metrics of every queue as JSON, keyed by queue name. The
+/// `NumberOfMessages*` counters count from server start.
+#[get("/admin/metrics.json")]
+async fn metrics_json(app_state: web::Data<AppState>) -> HttpResponse {
+    let queues: Vec<_> = {
+        let queues = app_state.queues.lock().await;
+        queues
+            .iter()
+            .map(|(name, queue)| (name.clone(), queue.clone()))
+            .collect()
+    };
+
+    let mut metrics = BTreeMap::new();
+    for (name, queue) in queues {
+        let queue = queue.lock().await;
+        let counts = queue.counts();
+        let stats = queue.stats();
+        metrics.insert(
+            name,
+            QueueMetricsJson {
+                approximate_number_of_messages: counts.visible,
+                approximate_number_of_messages_not_visible: counts.not_visible,
+                approximate_number_of_messages_delayed: counts.delayed,
+                approximate_age_of_oldest_message: queue.approximate_age_of_oldest_message_secs(),
+                number_of_messages_sent: stats.sent,
+                number_of_messages_received: stats.received,
+                number_of_messages_deleted: stats.deleted,
+            },
+        );
+    }
+
+    HttpResponse::Ok().json(MetricsJsonResponse { queues: metrics })
+}
+
+/// Move the mock clock forward, e.g. `POST /admin/advance-time?secs=30` to run out a
+/// 30 second visibility timeout without waiting. Needs `--mock-clock`.
+#[post("/admin/advance-time")]
+async fn advance_time(
+    app_state: web::Data<AppState>,
+    query: web::Query<AdvanceTimeQuery>,
+) -> HttpResponse {
+    if !app_state.clock.advance(Duration::from_secs(query.secs)) {
+        return HttpResponse::BadRequest()
+            .body("The clock follows real time; start the server with --mock-clock");
+    }
+
+    // Long polls sleep until the next message should become visible, which may now be past
+    let queues: Vec<_> = app_state.queues.lock().await.values().cloned().collect();
+    for queue in queues {
+        queue.lock().await.notifier().notify_waiters();
+    }
+    HttpResponse::NoContent().finish()
+}
+
+/// Delete every queue and its messages, from memory and the database, so tests sharing
+/// a server can start from scratch: `POST /admin/reset`.
+#[post("/admin/reset")]
+async fn reset(app_state: web::Data<AppState>) -> HttpResponse {
+    // Held across the DB write, as CreateQueue does, so no queue is created halfway through
+    let mut queues = app_state.queues.lock().await;
+    let service = crate::service::queue::Queue::new(&app_state.db_pool);
+    if let Err(e) = service.delete_all_queues().await {
+        return HttpResponse::InternalServerError().body(format!("Failed to delete queues: {}", e));
+    }
+    let queues_removed = queues.len();
+    queues.clear();
+
+    HttpResponse::Ok().json(ResetResponse { queues_removed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_list_messages_shows_in_flight() {
+        let state = AppState::for_tests();
+        state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(crate::api::post_handler)
+                .configure(configure),
+        )
+        .await;
+
+        for body in ["first", "second"] {
+            let req = test::TestRequest::post()
+                .uri("/")
+                .set_payload(format!(
+                    "Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody={}",
+                    body
+                ))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload(
+                "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&VisibilityTimeout=60",
+            )
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get()
+            .uri("/admin/queues/q/messages?include_invisible=true")
+            .to_request();
+        let listed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let messages = listed["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["body"], "first");
+        assert_eq!(messages[0]["in_flight"], true);
+        assert_eq!(messages[0]["receive_count"], 1);
+        assert!(messages[0]["visible_in_seconds"].as_u64().unwrap() > 50);
+        assert_eq!(messages[1]["in_flight"], false);
+
+        let req = test::TestRequest::get()
+            .uri("/admin/queues/q/messages")
+            .to_request();
+        let listed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(listed["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(listed["messages"][0]["body"], "second");
+
+        let req = test::TestRequest::get()
+            .uri("/admin/queues/missing/messages")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_enqueue_sequence_follows_arrival_order() {
+        let state = AppState::for_tests();
+        state.add_test_queue("q").await;
+        let engine = crate::engine::SqsEngine::new(std::sync::Arc::new(state.clone()));
+
+        let mut sends = tokio::task::JoinSet::new();
+        for i in 0..50 {
+            let engine = engine.clone();
+            sends.spawn(async move {
+                let message = crate::queue::Message::new(i.to_string(), i.to_string());
+                engine
+                    .send("q", message, None)
+                    .await
+                    .unwrap()
+                    .enqueue_sequence
+            });
+        }
+        let mut assigned = Vec::new();
+        while let Some(sequence) = sends.join_next().await {
+            assigned.push(sequence.unwrap());
+        }
+        assigned.sort_unstable();
+        assert_eq!(assigned, (0..50).collect::<Vec<u64>>());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(configure),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/admin/queues/q/messages")
+            .to_request();
+        let listed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let sequences: Vec<u64> = listed["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["enqueue_sequence"].as_u64().unwrap())
+            .collect();
+        assert_eq!(sequences, (0..50).collect::<Vec<u64>>());
+    }
+
+    #[actix_web::test]
+    async fn test_delete_message_by_id() {
+        let state = AppState::for_tests();
+        let queue = state.add_test_queue("q").await;
+        for id in ["1", "2"] {
+            queue
+                .lock()
+                .await
+                .push(crate::queue::Message::new(id.to_owned(), "hi".to_owned()));
+        }
+        // In flight messages can be deleted too
+        queue.lock().await.receive(1, Some(60));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(configure),
+        )
+        .await;
+
+        let delete = |uri: &str| test::TestRequest::delete().uri(uri).to_request();
+        let resp = test::call_service(&app, delete("/admin/queues/q/messages/1")).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NO_CONTENT);
+        let ids: Vec<String> = queue
+            .lock()
+            .await
+            .messages()
+            .map(|m| m.id.clone())
+            .collect();
+        assert_eq!(ids, ["2"]);
+
+        let resp = test::call_service(&app, delete("/admin/queues/q/messages/1")).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let resp = test::call_service(&app, delete("/admin/queues/other/messages/2")).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_json_after_send_and_receive() {
+        let state = AppState::for_tests();
+        state.add_test_queue("q").await;
+        state.add_test_queue("idle").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(crate::api::post_handler)
+                .configure(configure),
+        )
+        .await;
+
+        for body in ["first", "second", "third"] {
+            let req = test::TestRequest::post()
+                .uri("/")
+                .set_payload(format!(
+                    "Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody={}",
+                    body
+                ))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload(
+                "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&MaxNumberOfMessages=2&VisibilityTimeout=60",
+            )
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8_lossy(&body);
+        let handle = body
+            .split("<ReceiptHandle>")
+            .nth(1)
+            .and_then(|rest| rest.split("</ReceiptHandle>").next())
+            .unwrap();
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload(
+                serde_urlencoded::to_string([
+                    ("Action", "DeleteMessage"),
+                    ("QueueUrl", "http://localhost:9090/q"),
+                    ("ReceiptHandle", handle),
+                ])
+                .unwrap(),
+            )
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get()
+            .uri("/admin/metrics.json")
+            .to_request();
+        let metrics: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let q = &metrics["queues"]["q"];
+        assert_eq!(q["ApproximateNumberOfMessages"], 1);
+        assert_eq!(q["ApproximateNumberOfMessagesNotVisible"], 1);
+        assert_eq!(q["ApproximateNumberOfMessagesDelayed"], 0);
+        assert_eq!(q["NumberOfMessagesSent"], 3);
+        assert_eq!(q["NumberOfMessagesReceived"], 2);
+        assert_eq!(q["NumberOfMessagesDeleted"], 1);
+        assert_eq!(metrics["queues"]["idle"]["NumberOfMessagesSent"], 0);
+    }
+
+    #[actix_web::test]
+    async fn test_advance_time_expires_visibility_timeout() {
+        let state = AppState {
+            clock: std::sync::Arc::new(crate::clock::MockClock::default()),
+            ..AppState::for_tests()
+        };
+        state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(crate::api::post_handler)
+                .configure(configure),
+        )
+        .await;
+        let receive = || {
+            test::TestRequest::post()
+                .uri("/")
+                .set_payload(
+                    "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&VisibilityTimeout=3600",
+                )
+                .to_request()
+        };
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload("Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi")
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let body = test::call_and_read_body(&app, receive()).await;
+        assert!(String::from_utf8_lossy(&body).contains("<Body>hi</Body>"));
+        let body = test::call_and_read_body(&app, receive()).await;
+        assert!(!String::from_utf8_lossy(&body).contains("<Body>"));
+
+        let req = test::TestRequest::post()
+            .uri("/admin/advance-time?secs=3600")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NO_CONTENT);
+        let body = test::call_and_read_body(&app, receive()).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Body>hi</Body>"));
+        assert!(body.contains("<Name>ApproximateReceiveCount</Name><Value>2</Value>"));
+    }
+
+    #[actix_web::test]
+    async fn test_advance_time_needs_mock_clock() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests()))
+                .configure(configure),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/admin/advance-time?secs=60")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_reset_removes_every_queue() {
+        let state = AppState::for_tests_with_db().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(crate::api::post_handler)
+                .configure(configure),
+        )
+        .await;
+        let post = |body: &str| {
+            test::TestRequest::post()
+                .uri("/")
+                .set_payload(body.to_string())
+                .to_request()
+        };
+
+        for name in ["a", "b"] {
+            let req = post(&format!(
+                "Action=CreateQueue&QueueName={}&Attribute.1.Name=DelaySeconds&Attribute.1.Value=5",
+                name
+            ));
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        let req = post("Action=SendMessage&QueueUrl=http://localhost:9090/a&MessageBody=hi");
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::post().uri("/admin/reset").to_request();
+        let summary: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(summary["queues_removed"], 2);
+
+        let body = test::call_and_read_body(&app, post("Action=ListQueues")).await;
+        assert!(!String::from_utf8_lossy(&body).contains("<QueueUrl>"));
+        let req = post("Action=GetQueueUrl&QueueName=a");
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        // The name is free again, without the old attributes
+        let body = test::call_and_read_body(&app, post("Action=CreateQueue&QueueName=a")).await;
+        assert!(String::from_utf8_lossy(&body).contains("<QueueUrl>"));
+        let req = post(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/a&AttributeName.1=DelaySeconds",
+        );
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Value>0</Value>"), "{}", body);
+    }
+}
@@ -0,0 +1,221 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+/// Actions a permission can grant: every action AWS lets a queue share with other accounts.
+/// Batch actions come with their single versions, and actions on the queue's setup, such
+/// as CreateQueue or SetQueueAttributes, can't be shared.
+const PERMISSION_ACTIONS: &[&str] = &[
+    "*",
+    "SendMessage",
+    "ReceiveMessage",
+    "DeleteMessage",
+    "ChangeMessageVisibility",
+    "GetQueueAttributes",
+    "GetQueueUrl",
+    "ListDeadLetterSourceQueues",
+    "PurgeQueue",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AddPermissionParams {
+    queue_url: String,
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct AddPermissionResponse {
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// Grant other accounts access to a queue. The request is validated like AWS does, but
+/// the mock doesn't check permissions, so nothing is stored.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<AddPermissionParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let queue_name = match helpers::extract_queue_name_from_url(&params.queue_url) {
+        Some(name) => name,
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
+    };
+
+    let Some(label) = params.label else {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter Label.".to_string(),
+        )
+        .error_response();
+    };
+    if !helpers::is_valid_permission_label(&label) {
+        return SqsError::InvalidParameterValue(format!(
+            "Value {} for parameter Label is invalid. Reason: Must be 1 to 80 alphanumeric characters, hyphens or underscores.",
+            label
+        ))
+        .error_response();
+    }
+
+    let account_ids = helpers::extract_indexed_values(payload, "AWSAccountId");
+    if account_ids.is_empty() {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter AWSAccountId.1.".to_string(),
+        )
+        .error_response();
+    }
+    if let Some(id) = account_ids
+        .iter()
+        .find(|id| id.len() != 12 || !id.chars().all(|c| c.is_ascii_digit()))
+    {
+        return SqsError::InvalidParameterValue(format!(
+            "Value {} for parameter AWSAccountId is invalid. Reason: Must be a 12-digit account id.",
+            id
+        ))
+        .error_response();
+    }
+
+    let action_names = helpers::extract_indexed_values(payload, "ActionName");
+    if action_names.is_empty() {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter ActionName.1.".to_string(),
+        )
+        .error_response();
+    }
+    if let Some(action) = action_names
+        .iter()
+        .find(|action| !PERMISSION_ACTIONS.contains(&action.as_str()))
+    {
+        return SqsError::InvalidParameterValue(format!(
+            "Value {} for parameter ActionName is invalid.",
+            action
+        ))
+        .error_response();
+    }
+
+    if app_state.get_queue(&queue_name).await.is_none() {
+        return SqsError::NonExistentQueue(queue_name).error_response();
+    }
+
+    let response = AddPermissionResponse {
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    async fn add_permission(state: Arc<AppState>, params: &[(&str, &str)]) -> (StatusCode, String) {
+        let mut all = vec![
+            ("Action", "AddPermission"),
+            ("QueueUrl", "http://localhost:9090/q"),
+        ];
+        all.extend_from_slice(params);
+        let payload = serde_urlencoded::to_string(all).unwrap();
+        let resp = process(state, &web::Bytes::from(payload), false).await;
+        let status = resp.status();
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_valid_request_succeeds() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let (status, body) = add_permission(
+            state,
+            &[
+                ("Label", "consumers"),
+                ("AWSAccountId.1", "111122223333"),
+                ("AWSAccountId.2", "444455556666"),
+                ("ActionName.1", "SendMessage"),
+                ("ActionName.2", "ReceiveMessage"),
+                ("ActionName.3", "PurgeQueue"),
+                ("ActionName.4", "ListDeadLetterSourceQueues"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body.contains("<AddPermissionResponse"));
+        assert!(body.contains("<RequestId>"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_requests() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        for (params, code) in [
+            (
+                vec![("AWSAccountId.1", "111122223333"), ("ActionName.1", "*")],
+                "MissingParameter",
+            ),
+            (
+                vec![("Label", "l"), ("ActionName.1", "*")],
+                "MissingParameter",
+            ),
+            (
+                vec![("Label", "l"), ("AWSAccountId.1", "111122223333")],
+                "MissingParameter",
+            ),
+            (
+                vec![
+                    ("Label", "not valid"),
+                    ("AWSAccountId.1", "111122223333"),
+                    ("ActionName.1", "*"),
+                ],
+                "InvalidParameterValue",
+            ),
+            (
+                vec![
+                    ("Label", "l"),
+                    ("AWSAccountId.1", "1234"),
+                    ("ActionName.1", "*"),
+                ],
+                "InvalidParameterValue",
+            ),
+            (
+                vec![
+                    ("Label", "l"),
+                    ("AWSAccountId.1", "111122223333"),
+                    ("ActionName.1", "CreateQueue"),
+                ],
+                "InvalidParameterValue",
+            ),
+        ] {
+            let (status, body) = add_permission(state.clone(), &params).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", params);
+            assert!(
+                body.contains(&format!("<Code>{}</Code>", code)),
+                "{:?}: {}",
+                params,
+                body
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CancelMessageMoveTaskParams {
+    task_handle: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CancelMessageMoveTaskResponse {
+    cancel_message_move_task_result: CancelMessageMoveTaskResult,
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CancelMessageMoveTaskResult {
+    approximate_number_of_messages_moved: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// Cancel a running message move task. The mock finishes moves before
+/// StartMessageMoveTask returns, so only tasks that never ran to completion qualify.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<CancelMessageMoveTaskParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let Some(task_handle) = params.task_handle else {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter TaskHandle.".to_string(),
+        )
+        .error_response();
+    };
+
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let task = match engine.cancel_message_move_task(&task_handle).await {
+        Ok(task) => task,
+        Err(e) => return e.error_response(),
+    };
+
+    let response = CancelMessageMoveTaskResponse {
+        cancel_message_move_task_result: CancelMessageMoveTaskResult {
+            approximate_number_of_messages_moved: task.approximate_number_of_messages_moved,
+        },
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_task::{MoveTask, MoveTaskStatus};
+    use actix_web::http::StatusCode;
+
+    async fn cancel(state: Arc<AppState>, handle: &str) -> (StatusCode, String) {
+        let payload = serde_urlencoded::to_string([
+            ("Action", "CancelMessageMoveTask"),
+            ("TaskHandle", handle),
+        ])
+        .unwrap();
+        let resp = process(state, &web::Bytes::from(payload), false).await;
+        let status = resp.status();
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_task() {
+        let state = Arc::new(AppState::for_tests());
+        // Moves finish straight away, so stand in for one still under way
+        let mut task = MoveTask::start("arn:aws:sqs:us-east-1:000000000000:dlq", None, None);
+        task.approximate_number_of_messages_moved = 3;
+        let handle = task.task_handle.clone();
+        state.move_tasks.lock().await.insert(handle.clone(), task);
+
+        let (status, body) = cancel(state.clone(), &handle).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(
+            body.contains("<ApproximateNumberOfMessagesMoved>3</ApproximateNumberOfMessagesMoved>")
+        );
+        assert_eq!(
+            state.move_tasks.lock().await[&handle].status,
+            MoveTaskStatus::Cancelled
+        );
+
+        // Only once
+        let (status, body) = cancel(state.clone(), &handle).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("<Code>InvalidParameterValue</Code>"));
+
+        let (status, body) = cancel(state, "unknown").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("<Code>ResourceNotFoundException</Code>"));
+    }
+}
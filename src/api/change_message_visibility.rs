@@ -2,6 +2,7 @@ use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -31,46 +32,94 @@ pub async fn process(
 ) -> HttpResponse {
     let params = match super::struct_from_url_encode::<ChangeMessageVisibilityParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
 
     let queue_name = match super::helpers::extract_queue_name_from_url(&params.queue_url) {
         Some(name) => name,
-        None => {
-            return HttpResponse::BadRequest()
-                .body("Invalid QueueUrl: could not extract queue name")
-        }
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
     };
 
-    let mut writer = app_state.queues.lock().await;
-    match writer.get_mut(&queue_name) {
-        Some(queue) => {
-            if !queue.change_visibility(&params.receipt_handle, params.visibility_timeout) {
-                return HttpResponse::BadRequest().body(
-                    "ReceiptHandleIsInvalid; The input receipt handle is not a valid receipt handle.",
-                );
-            }
-        }
-        None => {
-            return HttpResponse::BadRequest().body(format!(
-                "AWS.SimpleQueueService.NonExistentQueue; Queue: {}",
-                queue_name
-            ))
-        }
+    let engine = crate::engine::SqsEngine::new(app_state);
+    if let Err(e) = engine
+        .change_visibility(
+            &queue_name,
+            &params.receipt_handle,
+            params.visibility_timeout,
+        )
+        .await
+    {
+        return e.error_response();
     }
 
     let response = ChangeMessageVisibilityResponse {
         response_metadata: ResponseMetadata {
-            request_id: super::helpers::generate_random_uuid4(),
+            request_id: super::helpers::request_id(),
         },
     };
 
-    match quick_xml::se::to_string(&response) {
+    match super::helpers::to_xml(&response) {
         Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
-        }
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handle_from_another_queue_is_rejected() {
+        let state = Arc::new(AppState::for_tests());
+        let queue_a = state.add_test_queue("a").await;
+        state.add_test_queue("b").await;
+
+        queue_a.lock().await.push(crate::queue::Message::new(
+            "1".to_owned(),
+            "in a".to_owned(),
+        ));
+        let handle = queue_a.lock().await.receive(1, None)[0]
+            .receipt_handle
+            .clone()
+            .unwrap();
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "ChangeMessageVisibility"),
+            ("QueueUrl", "http://localhost:9090/b"),
+            ("ReceiptHandle", &handle),
+            ("VisibilityTimeout", "0"),
+        ])
+        .unwrap();
+        let resp = process(state, &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert_eq!(queue_a.lock().await.counts().not_visible, 1);
+    }
+
+    #[tokio::test]
+    async fn test_delayed_message_has_no_handle_to_change() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue.lock().await.push_with_delay(
+            crate::queue::Message::new("1".to_owned(), "later".to_owned()),
+            60,
+        );
+
+        // Well-formed and for the right queue, but never issued by a receive
+        let handle = super::super::helpers::encode_receipt_handle("q", "1");
+        let payload = serde_urlencoded::to_string([
+            ("Action", "ChangeMessageVisibility"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("ReceiptHandle", &handle),
+            ("VisibilityTimeout", "0"),
+        ])
+        .unwrap();
+        let resp = process(state, &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>ReceiptHandleIsInvalid</Code>"));
+
+        let counts = queue.lock().await.counts();
+        assert_eq!((counts.visible, counts.delayed), (0, 1));
     }
 }
@@ -1,4 +1,5 @@
 use super::helpers;
+use crate::error::SqsError;
 use crate::AppState;
 use actix_web::{web, HttpResponse};
 use regex::RegexBuilder;
@@ -7,28 +8,24 @@ use std::{collections::HashMap, sync::Arc};
 use tracing::warn;
 
 /// .fifo - for the FIFO queues
-const ATTR_LIST: [&str; 12] = [
+const ATTR_LIST: [&str; 15] = [
     "DelaySeconds",
     "MaximumMessageSize",
     "MessageRetentionPeriod",
     "Policy",
     "ReceiveMessageWaitTimeSeconds",
     "RedrivePolicy",
+    "RedriveAllowPolicy",
     "VisibilityTimeout",
     "FifoQueue",
     "ContentBasedDeduplication",
+    "DeduplicationScope",
+    "FifoThroughputLimit",
     "KmsMasterKeyId",
     "KmsDataKeyReusePeriodSeconds",
     "SqsManagedSseEnabled",
 ];
 
-const _ATTR_FIFO: [&str; 4] = [
-    "FifoQueue",
-    "ContentBasedDeduplication",
-    "DeduplicationScope",
-    "FifoThroughputLimit",
-];
-
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct CreateQueueParams {
@@ -73,12 +70,16 @@ impl CreateQueueParams {
                 }
             }
         }
+        crate::engine::validate_queue_attributes(&helpers::get_attrbutes_hashmap(
+            self.attributes.clone(),
+        ))
+        .map_err(|e| anyhow::anyhow!(e.message()))?;
 
         Ok(())
     }
 
     fn create_tags(&mut self) {
-        let re = RegexBuilder::new(r"^(tags|tag)\.(.+)$")
+        let re = RegexBuilder::new(r"^Tag\.(\d+)\.(.+)$")
             .case_insensitive(true)
             .build()
             .unwrap();
@@ -103,62 +104,40 @@ pub async fn process(
 ) -> HttpResponse {
     let mut payload = match super::struct_from_url_encode::<CreateQueueParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
     match payload.create_validate_attributes() {
         Ok(_) => (),
         Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to validate attributes: {}", e))
+            return SqsError::InvalidAttributeValue(format!("Failed to validate attributes: {}", e))
+                .error_response()
         }
     };
     payload.create_tags();
 
-    let service = crate::service::queue::Queue::new(&app_state.db_pool, &app_state.host_name);
-    let db_result = service
-        .create_queue(crate::service::queue::QueueEntity {
-            id: None,
-            name: payload.queue_name.clone(),
-            queue_type: "Standard".to_string(),
-            attributes: Some(payload.clone().get_attrbutes_hashmap()),
-            tags: Some(payload.clone().get_tags_hashmap()),
-            created_at: None,
-            updated_at: None,
-        })
-        .await;
-
-    warn!("db_result: {:?}", db_result);
-    match db_result {
-        Ok(_) => {
-            let response = CreateQueueResponse {
-                create_queue_result: CreateQueueResult {
-                    queue_url: format!("{}/{}", &app_state.host_name, payload.queue_name),
-                },
-                reponse_metadata: HashMap::new(),
-            };
-
-            let visibility_timeout = payload
-                .clone()
-                .get_attrbutes_hashmap()
-                .get("VisibilityTimeout")
-                .and_then(|v| v.parse::<u32>().ok());
-
-            let mut writer = app_state.queues.lock().await;
-            (*writer).insert(
-                payload.queue_name.clone(),
-                crate::queue::Queue::new(&payload.queue_name.clone(), vec![], visibility_timeout),
-            );
-
-            match quick_xml::se::to_string(&response) {
-                Ok(resp) => HttpResponse::Ok().body(resp),
-                Err(e) => HttpResponse::InternalServerError()
-                    .body(format!("Failed to serialize response: {}", e)),
-            }
-        }
+    let tags = payload.clone().get_tags_hashmap();
+    let attributes = payload.clone().get_attrbutes_hashmap();
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let queue_url = match engine
+        .create_queue(&payload.queue_name, attributes, tags)
+        .await
+    {
+        Ok(url) => url,
         Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to create queue: {}", e))
+            warn!("Failed to create queue {}: {:?}", payload.queue_name, e);
+            return e.error_response();
         }
+    };
+
+    let response = CreateQueueResponse {
+        create_queue_result: CreateQueueResult { queue_url },
+        reponse_metadata: HashMap::new(),
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
     }
 }
 
@@ -208,4 +187,141 @@ mod tests {
 
         assert!(params.create_validate_attributes().is_err());
     }
+
+    #[test]
+    fn test_create_validate_attributes_bad_values() {
+        for (name, value) in [
+            ("VisibilityTimeout", "abc"),
+            ("FifoQueue", "yes"),
+            ("RedrivePolicy", "{\"maxReceiveCount\":\"5\"}"),
+        ] {
+            let mut extra = HashMap::new();
+            extra.insert("Attribute.1.Name".to_string(), name.to_string());
+            extra.insert("Attribute.1.Value".to_string(), value.to_string());
+
+            let mut params = CreateQueueParams {
+                queue_name: "myqueue".to_string(),
+                extra,
+                attributes: None,
+                tags: None,
+            };
+
+            assert!(params.create_validate_attributes().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_fifo_only_attributes_need_a_fifo_queue() {
+        let params = |attrs: &[(&str, &str)]| {
+            let mut extra = HashMap::new();
+            for (i, (name, value)) in attrs.iter().enumerate() {
+                extra.insert(format!("Attribute.{}.Name", i + 1), name.to_string());
+                extra.insert(format!("Attribute.{}.Value", i + 1), value.to_string());
+            }
+            CreateQueueParams {
+                queue_name: "myqueue".to_string(),
+                extra,
+                attributes: None,
+                tags: None,
+            }
+        };
+
+        let mut standard = params(&[("FifoThroughputLimit", "perQueue")]);
+        assert!(standard.create_validate_attributes().is_err());
+        let mut fifo = params(&[
+            ("FifoQueue", "true"),
+            ("FifoThroughputLimit", "perMessageGroupId"),
+            ("DeduplicationScope", "messageGroup"),
+        ]);
+        assert!(fifo.create_validate_attributes().is_ok());
+        let mut bad_value = params(&[("FifoQueue", "true"), ("DeduplicationScope", "perQueue")]);
+        assert!(bad_value.create_validate_attributes().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_with_redrive_policy() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let redrive = r#"{"deadLetterTargetArn":"arn:aws:sqs:us-east-1:000000000000:dlq","maxReceiveCount":"5"}"#;
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "CreateQueue"),
+            ("QueueName", "myqueue"),
+            ("Attribute.1.Name", "RedrivePolicy"),
+            ("Attribute.1.Value", redrive),
+            ("Attribute.2.Name", "VisibilityTimeout"),
+            ("Attribute.2.Value", "45"),
+        ])
+        .unwrap();
+        let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/myqueue&AttributeName.1=All",
+        );
+        let resp = super::super::get_queue_attributes::process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Name>RedrivePolicy</Name>"));
+        assert!(body.contains("deadLetterTargetArn"));
+        assert!(body.contains("<Name>VisibilityTimeout</Name><Value>45</Value>"));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_creates_make_one_queue() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+
+        let mut creates = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let state = state.clone();
+            creates.spawn(async move {
+                let payload = web::Bytes::from("Action=CreateQueue&QueueName=q");
+                process(state, &payload, false).await.status()
+            });
+        }
+        while let Some(status) = creates.join_next().await {
+            assert_eq!(status.unwrap(), actix_web::http::StatusCode::OK);
+        }
+
+        assert_eq!(state.queues.lock().await.len(), 1);
+        let service = crate::service::queue::Queue::new(&state.db_pool);
+        let (names, _) = service.list_queue(1000, None, None).await.unwrap();
+        assert_eq!(names, vec!["q".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_fifo_name_and_attribute_must_agree() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+
+        for payload in [
+            "Action=CreateQueue&QueueName=orders.fifo",
+            "Action=CreateQueue&QueueName=orders&Attribute.1.Name=FifoQueue&Attribute.1.Value=true",
+        ] {
+            let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            assert!(
+                String::from_utf8_lossy(&body).contains("<Code>InvalidParameterValue</Code>"),
+                "{}",
+                payload
+            );
+        }
+        assert!(state.queues.lock().await.is_empty());
+
+        let payload = web::Bytes::from(
+            "Action=CreateQueue&QueueName=orders.fifo&Attribute.1.Name=FifoQueue&Attribute.1.Value=true",
+        );
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert!(
+            state
+                .get_queue("orders.fifo")
+                .await
+                .unwrap()
+                .lock()
+                .await
+                .is_fifo
+        );
+    }
 }
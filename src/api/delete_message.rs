@@ -2,6 +2,7 @@ use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -30,46 +31,138 @@ pub async fn process(
 ) -> HttpResponse {
     let params = match super::struct_from_url_encode::<DeleteMessageParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
 
     let queue_name = match super::helpers::extract_queue_name_from_url(&params.queue_url) {
         Some(name) => name,
-        None => {
-            return HttpResponse::BadRequest()
-                .body("Invalid QueueUrl: could not extract queue name")
-        }
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
     };
 
-    let mut writer = app_state.queues.lock().await;
-    match writer.get_mut(&queue_name) {
-        Some(queue) => {
-            if !queue.delete_by_receipt_handle(&params.receipt_handle) {
-                return HttpResponse::BadRequest().body(
-                    "ReceiptHandleIsInvalid; The input receipt handle is not a valid receipt handle.",
-                );
-            }
-        }
-        None => {
-            return HttpResponse::BadRequest().body(format!(
-                "AWS.SimpleQueueService.NonExistentQueue; Queue: {}",
-                queue_name
-            ))
-        }
+    let engine = crate::engine::SqsEngine::new(app_state);
+    if let Err(e) = engine.delete(&queue_name, &params.receipt_handle).await {
+        return e.error_response();
     }
 
     let response = DeleteMessageResponse {
         response_metadata: ResponseMetadata {
-            request_id: super::helpers::generate_random_uuid4(),
+            request_id: super::helpers::request_id(),
         },
     };
 
-    match quick_xml::se::to_string(&response) {
+    match super::helpers::to_xml(&response) {
         Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handle_from_another_queue_is_rejected() {
+        let state = Arc::new(AppState::for_tests());
+        let queue_a = state.add_test_queue("a").await;
+        let queue_b = state.add_test_queue("b").await;
+
+        queue_a.lock().await.push(crate::queue::Message::new(
+            "1".to_owned(),
+            "in a".to_owned(),
+        ));
+        let handle = queue_a.lock().await.receive(1, None)[0]
+            .receipt_handle
+            .clone()
+            .unwrap();
+
+        // Even if queue b somehow held a message with a colliding handle, it is left alone
+        {
+            let mut queue_b = queue_b.lock().await;
+            queue_b.push(crate::queue::Message::new(
+                "1".to_owned(),
+                "in b".to_owned(),
+            ));
+            queue_b.receive(1, None);
         }
+
+        let delete = |queue: &str| {
+            web::Bytes::from(
+                serde_urlencoded::to_string([
+                    ("Action", "DeleteMessage"),
+                    ("QueueUrl", &format!("http://localhost:9090/{}", queue)),
+                    ("ReceiptHandle", &handle),
+                ])
+                .unwrap(),
+            )
+        };
+
+        let resp = process(state.clone(), &delete("b"), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>ReceiptHandleIsInvalid</Code>"));
+        assert_eq!(queue_b.lock().await.counts().not_visible, 1);
+
+        let resp = process(state, &delete("a"), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(queue_a.lock().await.counts().not_visible, 0);
+    }
+
+    #[tokio::test]
+    async fn test_repeat_delete_succeeds() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+        let handle = queue.lock().await.receive(1, None)[0]
+            .receipt_handle
+            .clone()
+            .unwrap();
+
+        let payload = web::Bytes::from(
+            serde_urlencoded::to_string([
+                ("Action", "DeleteMessage"),
+                ("QueueUrl", "http://localhost:9090/q"),
+                ("ReceiptHandle", handle.as_str()),
+            ])
+            .unwrap(),
+        );
+        for _ in 0..2 {
+            let resp = process(state.clone(), &payload, false).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        }
+        assert_eq!(queue.lock().await.counts(), Default::default());
+    }
+
+    #[tokio::test]
+    async fn test_common_query_parameters_are_ignored() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+        let handle = queue.lock().await.receive(1, None)[0]
+            .receipt_handle
+            .clone()
+            .unwrap();
+
+        // As sent by legacy SDKs on every query request
+        let payload = web::Bytes::from(
+            serde_urlencoded::to_string([
+                ("Action", "DeleteMessage"),
+                ("QueueUrl", "http://localhost:9090/q"),
+                ("ReceiptHandle", handle.as_str()),
+                ("Version", "2012-11-05"),
+                ("Timestamp", "2026-10-16T12:00:00Z"),
+                ("Expires", "2026-10-16T12:15:00Z"),
+            ])
+            .unwrap(),
+        );
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(queue.lock().await.counts(), Default::default());
     }
 }
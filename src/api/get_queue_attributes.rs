@@ -2,6 +2,7 @@ use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
 
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -46,17 +47,12 @@ pub async fn process(
 ) -> HttpResponse {
     let params = match super::struct_from_url_encode::<GetQueueAttributesParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
 
     let queue_name = match super::helpers::extract_queue_name_from_url(&params.queue_url) {
         Some(name) => name,
-        None => {
-            return HttpResponse::BadRequest()
-                .body("Invalid QueueUrl: could not extract queue name")
-        }
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
     };
 
     // Collect requested attribute names from AttributeName.N params
@@ -67,83 +63,230 @@ pub async fn process(
         .map(|(_, v)| v.clone())
         .collect();
 
-    let want_all = requested.is_empty() || requested.contains(&"All".to_string());
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let attrs = match engine.get_attributes(&queue_name, &requested).await {
+        Ok(attrs) => attrs
+            .into_iter()
+            .map(|(name, value)| AttributeXml { name, value })
+            .collect(),
+        Err(e) => return e.error_response(),
+    };
 
-    // Get DB-stored attributes
-    let service = crate::service::queue::Queue::new(&app_state.db_pool, &app_state.host_name);
-    let db_attrs = match service.get_queue_attributes(&queue_name).await {
-        Ok(attrs) => attrs,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to load queue attributes: {}", e));
-        }
+    let response = GetQueueAttributesResponse {
+        get_queue_attributes_result: GetQueueAttributesResult { attributes: attrs },
+        response_metadata: ResponseMetadata {
+            request_id: super::helpers::request_id(),
+        },
     };
 
-    // Get in-memory computed attributes
-    let reader = app_state.queues.lock().await;
-    let queue = match reader.get(&queue_name) {
-        Some(q) => q,
-        None => {
-            return HttpResponse::BadRequest().body(format!(
-                "AWS.SimpleQueueService.NonExistentQueue; Queue: {}",
-                queue_name
-            ))
+    match super::helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_db_failure_is_internal_error() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        state.add_test_queue("q").await;
+        state.db_pool.close().await;
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q&AttributeName.1=All",
+        );
+        let resp = process(state, &payload, false).await;
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InternalFailure</Code>"));
+    }
+
+    #[tokio::test]
+    async fn test_nonexistent_queue() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+
+        let payload =
+            web::Bytes::from("Action=GetQueueAttributes&QueueUrl=http://localhost:9090/missing");
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body)
+            .contains("<Code>AWS.SimpleQueueService.NonExistentQueue</Code>"));
+    }
+
+    #[tokio::test]
+    async fn test_delayed_messages_are_not_visible() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let queue = state.add_test_queue("q").await;
+        queue.lock().await.push_with_delay(
+            crate::queue::Message::new("1".to_string(), "later".to_string()),
+            60,
+        );
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q&AttributeName.1=All",
+        );
+        let resp = process(state.clone(), &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        for (name, value) in [
+            ("ApproximateNumberOfMessages", "0"),
+            ("ApproximateNumberOfMessagesNotVisible", "0"),
+            ("ApproximateNumberOfMessagesDelayed", "1"),
+        ] {
+            let attr = format!("<Name>{}</Name><Value>{}</Value>", name, value);
+            assert!(body.contains(&attr), "{} in {}", attr, body);
         }
-    };
 
-    let mut attrs = Vec::new();
+        let payload = web::Bytes::from("Action=ReceiveMessage&QueueUrl=http://localhost:9090/q");
+        let resp = super::super::receive_message::process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("<Message>"));
+    }
 
-    // Always-available computed attributes
-    let computed = vec![
-        (
-            "ApproximateNumberOfMessages",
-            queue.approximate_number_of_messages().to_string(),
-        ),
-        (
-            "ApproximateNumberOfMessagesNotVisible",
-            queue
-                .approximate_number_of_messages_not_visible()
-                .to_string(),
-        ),
-        (
-            "VisibilityTimeout",
-            queue.default_visibility_timeout.to_string(),
-        ),
-    ];
-
-    for (name, value) in &computed {
-        if want_all || requested.contains(&name.to_string()) {
-            attrs.push(AttributeXml {
-                name: name.to_string(),
-                value: value.clone(),
-            });
+    #[tokio::test]
+    async fn test_new_queue_reports_defaults() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let payload = web::Bytes::from(
+            "Action=CreateQueue&QueueName=q&Attribute.1.Name=DelaySeconds&Attribute.1.Value=5",
+        );
+        let resp = super::super::create_queue::process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q&AttributeName.1=All",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        for (name, value) in [
+            ("DelaySeconds", "5"),
+            ("MessageRetentionPeriod", "345600"),
+            ("MaximumMessageSize", "262144"),
+            ("ReceiveMessageWaitTimeSeconds", "0"),
+        ] {
+            let attr = format!("<Name>{}</Name><Value>{}</Value>", name, value);
+            assert_eq!(body.matches(&attr).count(), 1, "{} in {}", attr, body);
         }
+        assert_eq!(body.matches("<Name>DelaySeconds</Name>").count(), 1);
     }
 
-    for (name, value) in &db_attrs {
-        if want_all || requested.contains(name) {
-            // Don't duplicate VisibilityTimeout if already added from computed
-            if name == "VisibilityTimeout" {
-                continue;
-            }
-            attrs.push(AttributeXml {
-                name: name.clone(),
-                value: value.clone(),
-            });
+    #[tokio::test]
+    async fn test_all_includes_arn_and_redrive_attributes() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let redrive = r#"{"deadLetterTargetArn":"arn:aws:sqs:us-east-1:000000000000:dlq.fifo","maxReceiveCount":"4"}"#;
+        let redrive_allow = r#"{"redrivePermission":"allowAll"}"#;
+        let policy = r#"{"Version":"2012-10-17","Statement":[]}"#;
+        let payload = serde_urlencoded::to_string([
+            ("Action", "CreateQueue"),
+            ("QueueName", "jobs.fifo"),
+            ("Attribute.1.Name", "FifoQueue"),
+            ("Attribute.1.Value", "true"),
+            ("Attribute.2.Name", "ContentBasedDeduplication"),
+            ("Attribute.2.Value", "true"),
+            ("Attribute.3.Name", "RedrivePolicy"),
+            ("Attribute.3.Value", redrive),
+            ("Attribute.4.Name", "RedriveAllowPolicy"),
+            ("Attribute.4.Value", redrive_allow),
+            ("Attribute.5.Name", "Policy"),
+            ("Attribute.5.Value", policy),
+        ])
+        .unwrap();
+        let resp =
+            super::super::create_queue::process(state.clone(), &web::Bytes::from(payload), false)
+                .await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/jobs.fifo&AttributeName.1=All",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        let escape = |json: &str| json.replace('"', "&quot;");
+        for (name, value) in [
+            (
+                "QueueArn",
+                "arn:aws:sqs:us-east-1:000000000000:jobs.fifo".to_string(),
+            ),
+            ("FifoQueue", "true".to_string()),
+            ("ContentBasedDeduplication", "true".to_string()),
+            ("RedrivePolicy", escape(redrive)),
+            ("RedriveAllowPolicy", escape(redrive_allow)),
+            ("Policy", escape(policy)),
+            ("ApproximateNumberOfMessages", "0".to_string()),
+            ("ApproximateNumberOfMessagesNotVisible", "0".to_string()),
+            ("ApproximateNumberOfMessagesDelayed", "0".to_string()),
+        ] {
+            let attr = format!("<Name>{}</Name><Value>{}</Value>", name, value);
+            assert_eq!(body.matches(&attr).count(), 1, "{} in {}", attr, body);
         }
     }
 
-    let response = GetQueueAttributesResponse {
-        get_queue_attributes_result: GetQueueAttributesResult { attributes: attrs },
-        response_metadata: ResponseMetadata {
-            request_id: super::helpers::generate_random_uuid4(),
-        },
-    };
+    #[tokio::test]
+    async fn test_dot_star_asks_for_all_attributes() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let payload = web::Bytes::from("Action=CreateQueue&QueueName=q");
+        super::super::create_queue::process(state.clone(), &payload, false).await;
 
-    match quick_xml::se::to_string(&response) {
-        Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q&AttributeName.1=.*",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        for name in [
+            "ApproximateNumberOfMessages",
+            "VisibilityTimeout",
+            "DelaySeconds",
+            "MessageRetentionPeriod",
+        ] {
+            assert!(body.contains(&format!("<Name>{}</Name>", name)), "{}", name);
         }
     }
+
+    #[tokio::test]
+    async fn test_many_requests_do_not_exhaust_pool() {
+        // The test pool has a single connection, so one that isn't given back stalls
+        // every later request
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let payload = web::Bytes::from("Action=CreateQueue&QueueName=q");
+        let resp = super::super::create_queue::process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let idle_services: Vec<_> = (0..100)
+            .map(|_| crate::service::queue::Queue::new(&state.db_pool))
+            .collect();
+
+        let mut requests = tokio::task::JoinSet::new();
+        for _ in 0..500 {
+            let state = state.clone();
+            requests.spawn(async move {
+                let payload = web::Bytes::from(
+                    "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q&AttributeName.1=All",
+                );
+                process(state, &payload, false).await.status()
+            });
+        }
+        let all_done = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            while let Some(status) = requests.join_next().await {
+                assert_eq!(status.unwrap(), actix_web::http::StatusCode::OK);
+            }
+        });
+        assert!(
+            all_done.await.is_ok(),
+            "requests stalled waiting for a connection"
+        );
+
+        assert!(idle_services[0].queue_exists("q").await.unwrap());
+        assert_eq!(state.db_pool.size(), 1);
+    }
 }
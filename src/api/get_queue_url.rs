@@ -2,6 +2,7 @@ use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,39 +37,61 @@ pub async fn process(
 ) -> HttpResponse {
     let params = match super::struct_from_url_encode::<GetQueueUrlParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
 
-    let service = crate::service::queue::Queue::new(&app_state.db_pool, &app_state.host_name);
+    let service = crate::service::queue::Queue::new(&app_state.db_pool);
     match service.queue_exists(&params.queue_name).await {
         Ok(false) => {
-            return HttpResponse::BadRequest().body(format!(
-                "AWS.SimpleQueueService.NonExistentQueue; Queue: {}",
-                params.queue_name
-            ));
+            return SqsError::NonExistentQueue(params.queue_name).error_response();
         }
         Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to query database: {}", e));
+            return SqsError::InternalFailure(format!("Failed to query database: {}", e))
+                .error_response();
         }
         Ok(true) => {}
     }
 
     let response = GetQueueUrlResponse {
         get_queue_url_result: GetQueueUrlResult {
-            queue_url: format!("{}/{}", app_state.host_name, params.queue_name),
+            queue_url: app_state.queue_url(&params.queue_name),
         },
         response_metadata: ResponseMetadata {
-            request_id: super::helpers::generate_random_uuid4(),
+            request_id: super::helpers::request_id(),
         },
     };
 
-    match quick_xml::se::to_string(&response) {
+    match super::helpers::to_xml(&response) {
         Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
-        }
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::helpers::{UrlForm, UrlScheme, UrlStyle};
+
+    #[tokio::test]
+    async fn test_queue_url_follows_url_style() {
+        let mut state = AppState::for_tests_with_db().await;
+        state.host_name = "http://localhost:9090".to_string();
+        state.url_style = UrlStyle {
+            scheme: Some(UrlScheme::Https),
+            form: UrlForm::VirtualHost,
+        };
+        let state = Arc::new(state);
+        let payload = web::Bytes::from("Action=CreateQueue&QueueName=q");
+        let resp = super::super::create_queue::process(state.clone(), &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let expected = "<QueueUrl>https://sqs.localhost:9090/000000000000/q</QueueUrl>";
+        assert!(String::from_utf8_lossy(&body).contains(expected));
+
+        let payload = web::Bytes::from("Action=GetQueueUrl&QueueName=q");
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(expected));
     }
 }
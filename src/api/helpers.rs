@@ -1,8 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use actix_web::{http::StatusCode, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::error::SqsError;
+pub use crate::queue::{decode_receipt_handle, encode_receipt_handle, ReceiptHandle};
+use crate::queue::{MessageAttribute, MessageAttributeValue};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ParamValues {
@@ -10,32 +16,35 @@ pub struct ParamValues {
     pub value: String,
 }
 
+/// Collect `<Prefix>.N.Name`/`<Prefix>.N.Value` pairs, or `.Key`/`.Value` for tags, from
+/// the parameters matching `re`, whose first group is the index and second the field.
+/// Pairs come back in index order; those without a name are dropped.
 pub fn extract_from_extra(re: Regex, extra: HashMap<String, String>) -> Option<Vec<ParamValues>> {
-    let mut attrs: Vec<ParamValues> = Vec::new();
-    for _i in 0..extra.len() {
-        attrs.push(ParamValues {
+    let mut attrs: BTreeMap<usize, ParamValues> = BTreeMap::new();
+    for (key, value) in extra.iter() {
+        let Some(caps) = re.captures(key) else {
+            continue;
+        };
+        let Some(index) = caps.get(1).and_then(|i| i.as_str().parse::<usize>().ok()) else {
+            continue;
+        };
+        let attr = attrs.entry(index).or_insert_with(|| ParamValues {
             name: "".to_string(),
             value: "".to_string(),
         });
-    }
-
-    for (key, value) in extra.iter() {
-        if let Some(caps) = re.captures(key) {
-            let index = caps.get(1).unwrap().as_str().parse::<usize>().unwrap();
-            let attr_name = caps.get(2).unwrap().as_str().to_string();
-
-            match attr_name.as_str() {
-                "Name" => attrs[index - 1].name = value.to_string(),
-                "Value" => attrs[index - 1].value = value.to_string(),
-                _ => (),
-            }
+        match caps.get(2).map_or("", |field| field.as_str()) {
+            "Name" | "Key" => attr.name = value.to_string(),
+            "Value" => attr.value = value.to_string(),
+            _ => (),
         }
     }
 
-    // Cleanup empty attributes
-    attrs.retain(|attr| !attr.name.is_empty());
-
-    Some(attrs)
+    Some(
+        attrs
+            .into_values()
+            .filter(|attr| !attr.name.is_empty())
+            .collect(),
+    )
 }
 
 pub fn get_attrbutes_hashmap(attributes: Option<Vec<ParamValues>>) -> HashMap<String, String> {
@@ -49,25 +58,662 @@ pub fn get_attrbutes_hashmap(attributes: Option<Vec<ParamValues>>) -> HashMap<St
     map
 }
 
+/// Collect the values of indexed list parameters such as `AttributeName.1`, `AttributeName.2`.
+pub fn extract_indexed_values(payload: &[u8], prefix: &str) -> Vec<String> {
+    let params: Vec<(String, String)> = serde_urlencoded::from_bytes(payload).unwrap_or_default();
+    let prefix = format!("{}.", prefix);
+    params
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(&prefix))
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// Group indexed entry parameters such as `SendMessageBatchRequestEntry.1.Id` by index.
+/// Each entry maps the field name (`Id`, `MessageBody`, ...) to its value; entries come
+/// back in index order.
+pub fn extract_batch_entries(payload: &[u8], prefix: &str) -> Vec<HashMap<String, String>> {
+    let params: Vec<(String, String)> = serde_urlencoded::from_bytes(payload).unwrap_or_default();
+    let prefix = format!("{}.", prefix);
+    let mut entries: std::collections::BTreeMap<u32, HashMap<String, String>> =
+        std::collections::BTreeMap::new();
+    for (key, value) in params {
+        let Some(rest) = key.strip_prefix(&prefix) else {
+            continue;
+        };
+        let Some((index, field)) = rest.split_once('.') else {
+            continue;
+        };
+        if let Ok(index) = index.parse::<u32>() {
+            entries
+                .entry(index)
+                .or_default()
+                .insert(field.to_string(), value);
+        }
+    }
+    entries.into_values().collect()
+}
+
+/// Most entries a single batch request may carry.
+pub const MAX_BATCH_ENTRIES: usize = 10;
+
+/// Check the entry ids of a `*Batch` request before any entry is processed.
+pub fn validate_batch_entries(ids: &[String]) -> Result<(), SqsError> {
+    if ids.is_empty() {
+        return Err(SqsError::EmptyBatchRequest);
+    }
+    if ids.len() > MAX_BATCH_ENTRIES {
+        return Err(SqsError::TooManyEntriesInBatchRequest(ids.len()));
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    if let Some(dup) = ids.iter().find(|id| !seen.insert(id.as_str())) {
+        return Err(SqsError::BatchEntryIdsNotDistinct(dup.clone()));
+    }
+
+    Ok(())
+}
+
 pub fn generate_random_uuid4() -> String {
     let uuid = uuid::Uuid::new_v4();
     uuid.to_string()
 }
 
+/// Header carrying the request id, which SDKs log and attach to errors.
+pub const REQUEST_ID_HEADER: &str = "x-amzn-requestid";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Handle a request with `request_id` as the id its response reports.
+pub async fn with_request_id<F: std::future::Future>(request_id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(request_id, f).await
+}
+
+/// The id of the request being handled, so the response body, the `x-amzn-RequestId`
+/// header and the logs agree. Outside [`with_request_id`], e.g. when tests call a
+/// handler's `process` directly, every call makes a new one.
+pub fn request_id() -> String {
+    REQUEST_ID
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| generate_random_uuid4())
+}
+
 pub fn compute_md5(input: &str) -> String {
     let digest = md5::compute(input);
     format!("{:x}", digest)
 }
 
-/// Extract the queue name from a QueueUrl like "http://localhost:9090/myqueue".
-/// Returns the last path segment.
+/// Whether a list of `AttributeName.N` or `MessageAttributeName.N` values asks for
+/// everything, which SQS spells `All` or `.*`.
+pub fn wants_all(requested: &[String]) -> bool {
+    requested.iter().any(|r| r == "All" || r == ".*")
+}
+
+/// Check that a message body only has characters XML 1.0 can carry, so it can be sent
+/// back escaped in a ReceiveMessage response. Markup characters are fine; control
+/// characters other than tab, CR and LF are not.
+/// On failure returns the message for an `InvalidMessageContents` error.
+pub fn validate_message_body(body: &str) -> Result<(), String> {
+    let is_xml_char = |c: char| {
+        matches!(c, '\t' | '\n' | '\r')
+            || ('\u{20}'..='\u{D7FF}').contains(&c)
+            || ('\u{E000}'..='\u{FFFD}').contains(&c)
+            || c >= '\u{10000}'
+    };
+    match body.chars().find(|c| !is_xml_char(*c)) {
+        Some(c) => Err(format!(
+            "Invalid binary character '#x{:X}' was found in the message body.",
+            c as u32
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Check a message attribute name against the SQS rules: at most 256 characters out of
+/// letters, digits, `-`, `_` and `.`, with no leading, trailing or doubled periods, and no
+/// reserved `AWS.` or `Amazon.` prefix in any case.
+/// On failure returns the message for an `InvalidParameterValue` error.
+pub fn validate_attribute_name(name: &str) -> Result<(), String> {
+    let invalid = |reason: &str| {
+        Err(format!(
+            "Message attribute name '{}' is invalid. Reason: {}",
+            name, reason
+        ))
+    };
+    if name.chars().count() > 256 {
+        return invalid("must be at most 256 characters long.");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return invalid(&format!("contains the invalid character '{}'.", c));
+    }
+    if name.starts_with('.') || name.ends_with('.') {
+        return invalid("must not start or end with a period.");
+    }
+    if name.contains("..") {
+        return invalid("must not contain consecutive periods.");
+    }
+    let lower = name.to_ascii_lowercase();
+    if lower.starts_with("aws.") || lower.starts_with("amazon.") {
+        return invalid("the prefixes AWS. and Amazon. are reserved.");
+    }
+    Ok(())
+}
+
+/// Parse `MessageAttribute.N.Name` / `MessageAttribute.N.Value.*` parameters into attributes
+/// sorted by name. Binary values are decoded from base64.
+/// On failure returns the message for an `InvalidParameterValue` error.
+pub fn parse_message_attributes<'a>(
+    params: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> Result<Vec<MessageAttribute>, String> {
+    let attributes = parse_attributes_with_prefix(params, "MessageAttribute.")?;
+    for attribute in &attributes {
+        validate_attribute_name(&attribute.name)?;
+    }
+    Ok(attributes)
+}
+
+/// Parse `MessageSystemAttribute.N.*` parameters, laid out like message attributes.
+/// SQS only knows the `AWSTraceHeader` system attribute, which must be a string.
+pub fn parse_message_system_attributes<'a>(
+    params: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> Result<Vec<MessageAttribute>, String> {
+    let attributes = parse_attributes_with_prefix(params, "MessageSystemAttribute.")?;
+    for attribute in &attributes {
+        if attribute.name != "AWSTraceHeader" {
+            return Err(format!(
+                "Message system attribute name '{}' is invalid.",
+                attribute.name
+            ));
+        }
+        if attribute.data_type != "String" {
+            return Err(format!(
+                "The type of message system attribute '{}' is invalid: {}.",
+                attribute.name, attribute.data_type
+            ));
+        }
+    }
+    Ok(attributes)
+}
+
+fn parse_attributes_with_prefix<'a>(
+    params: impl IntoIterator<Item = (&'a String, &'a String)>,
+    prefix: &str,
+) -> Result<Vec<MessageAttribute>, String> {
+    let mut entries: std::collections::BTreeMap<u32, HashMap<&str, &str>> =
+        std::collections::BTreeMap::new();
+    for (key, value) in params {
+        let Some((index, field)) = key
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.split_once('.'))
+        else {
+            continue;
+        };
+        if let Ok(index) = index.parse::<u32>() {
+            entries.entry(index).or_default().insert(field, value);
+        }
+    }
+
+    let mut attributes = Vec::new();
+    for fields in entries.into_values() {
+        let name = fields.get("Name").copied().unwrap_or_default();
+        if name.is_empty() {
+            return Err("A message attribute is missing its name.".to_string());
+        }
+        let data_type = fields.get("Value.DataType").copied().unwrap_or_default();
+        let value = if data_type.starts_with("Binary") {
+            let encoded = fields.get("Value.BinaryValue").ok_or_else(|| {
+                format!(
+                    "The message attribute '{}' must contain a binary value.",
+                    name
+                )
+            })?;
+            let decoded = STANDARD.decode(encoded).map_err(|_| {
+                format!(
+                    "The message attribute '{}' has an invalid base64 binary value.",
+                    name
+                )
+            })?;
+            MessageAttributeValue::Binary(decoded)
+        } else if data_type.starts_with("String") || data_type.starts_with("Number") {
+            let value = fields.get("Value.StringValue").ok_or_else(|| {
+                format!(
+                    "The message attribute '{}' must contain a string value.",
+                    name
+                )
+            })?;
+            MessageAttributeValue::String(value.to_string())
+        } else if data_type.is_empty() {
+            return Err(format!(
+                "The message attribute '{}' must contain non-empty message attribute type.",
+                name
+            ));
+        } else {
+            return Err(format!(
+                "The type of message attribute '{}' is invalid: {}.",
+                name, data_type
+            ));
+        };
+        attributes.push(MessageAttribute {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            value,
+        });
+    }
+
+    attributes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(attributes)
+}
+
+/// The `MD5OfMessageAttributes` digest, computed as the SDKs verify it: for each
+/// attribute in name order, the length-prefixed name, data type, a transport byte
+/// (1 for string values, 2 for binary) and the length-prefixed value.
+pub fn compute_message_attributes_md5(attributes: &[MessageAttribute]) -> String {
+    fn push_len_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+        buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        buf.extend_from_slice(bytes);
+    }
+
+    let mut sorted: Vec<&MessageAttribute> = attributes.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut buf = Vec::new();
+    for attr in sorted {
+        push_len_prefixed(&mut buf, attr.name.as_bytes());
+        push_len_prefixed(&mut buf, attr.data_type.as_bytes());
+        match &attr.value {
+            MessageAttributeValue::String(value) => {
+                buf.push(1);
+                push_len_prefixed(&mut buf, value.as_bytes());
+            }
+            MessageAttributeValue::Binary(value) => {
+                buf.push(2);
+                push_len_prefixed(&mut buf, value);
+            }
+        }
+    }
+    format!("{:x}", md5::compute(buf))
+}
+
+/// `<MessageAttribute>` element of a `ReceiveMessage` result.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MessageAttributeXml {
+    name: String,
+    value: MessageAttributeValueXml,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageAttributeValueXml {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    string_value: Option<String>,
+    /// Base64 encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary_value: Option<String>,
+    data_type: String,
+}
+
+impl From<&MessageAttribute> for MessageAttributeXml {
+    fn from(attr: &MessageAttribute) -> Self {
+        let (string_value, binary_value) = match &attr.value {
+            MessageAttributeValue::String(value) => (Some(value.clone()), None),
+            MessageAttributeValue::Binary(value) => (None, Some(STANDARD.encode(value))),
+        };
+        MessageAttributeXml {
+            name: attr.name.clone(),
+            value: MessageAttributeValueXml {
+                string_value,
+                binary_value,
+                data_type: attr.data_type.clone(),
+            },
+        }
+    }
+}
+
+/// Scheme of the queue URLs handed out to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UrlScheme {
+    Http,
+    Https,
+}
+
+/// Shape of the queue URLs handed out to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum UrlForm {
+    /// `http://localhost:9090/000000000000/myqueue`
+    #[default]
+    Path,
+    /// `http://sqs.localhost:9090/000000000000/myqueue`, the host prefixed like an
+    /// AWS regional endpoint
+    VirtualHost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UrlStyle {
+    /// Overrides the scheme given with the host; `http` if neither sets one
+    pub scheme: Option<UrlScheme>,
+    pub form: UrlForm,
+}
+
+/// Build a queue URL. `host` may carry its own scheme, as in `http://localhost:9090`.
+pub fn build_queue_url(host: &str, account_id: &str, queue_name: &str, style: UrlStyle) -> String {
+    let (host_scheme, host) = match host.split_once("://") {
+        Some((scheme, host)) => (Some(scheme), host),
+        None => (None, host),
+    };
+    let scheme = match (style.scheme, host_scheme) {
+        (Some(UrlScheme::Http), _) => "http",
+        (Some(UrlScheme::Https), _) => "https",
+        (None, Some(scheme)) => scheme,
+        (None, None) => "http",
+    };
+    let host = host.trim_end_matches('/');
+    match style.form {
+        UrlForm::Path => format!("{}://{}/{}/{}", scheme, host, account_id, queue_name),
+        UrlForm::VirtualHost => {
+            format!("{}://sqs.{}/{}/{}", scheme, host, account_id, queue_name)
+        }
+    }
+}
+
+/// Extract the queue name from a QueueUrl like "http://localhost:9090/myqueue" or
+/// "http://localhost:9090/000000000000/myqueue".
+/// Returns the last non-empty path segment, ignoring any query string or trailing slash.
+///
+/// Some tools pass just the queue name, so a QueueUrl without a scheme is taken as a path
+/// whose last segment is the name, as long as it is a valid queue name; that keeps a bare
+/// `localhost:9090` from being read as one.
 pub fn extract_queue_name_from_url(url: &str) -> Option<String> {
-    url.split('/')
-        .next_back()
-        .filter(|s| !s.is_empty())
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    let (has_scheme, path) = match url.split_once("://") {
+        // Without a path there is only the host, which is no queue name
+        Some((_, rest)) => (true, rest.split_once('/')?.1),
+        None => (false, url),
+    };
+    path.split('/')
+        .rfind(|s| !s.is_empty())
+        .filter(|name| has_scheme || is_valid_queue_name(name))
         .map(|s| s.to_string())
 }
 
+/// Region the mock reports in queue ARNs.
+pub const REGION: &str = "us-east-1";
+
+/// The ARN of a queue, e.g. `arn:aws:sqs:us-east-1:000000000000:orders`.
+pub fn queue_arn(account_id: &str, queue_name: &str) -> String {
+    format!("arn:aws:sqs:{}:{}:{}", REGION, account_id, queue_name)
+}
+
+/// The queue name in a queue ARN such as `arn:aws:sqs:us-east-1:000000000000:orders`.
+pub fn queue_name_from_arn(arn: &str) -> Option<&str> {
+    match arn.split(':').collect::<Vec<_>>()[..] {
+        ["arn", _, "sqs", _, _, name] if is_valid_queue_name(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Queue names are 1 to 80 alphanumeric characters, hyphens or underscores, plus the
+/// `.fifo` suffix of FIFO queues.
+pub fn is_valid_queue_name(name: &str) -> bool {
+    let base = name.strip_suffix(".fifo").unwrap_or(name);
+    !base.is_empty()
+        && name.len() <= 80
+        && base
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Permission labels are 1 to 80 alphanumeric characters, hyphens or underscores.
+pub fn is_valid_permission_label(label: &str) -> bool {
+    (1..=80).contains(&label.len())
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Legal (inclusive) ranges for the numeric queue attributes
+const NUMERIC_ATTRIBUTE_RANGES: [(&str, i64, i64); 6] = [
+    ("DelaySeconds", 0, 900),
+    ("MaximumMessageSize", 1024, 262144),
+    ("MessageRetentionPeriod", 60, 1209600),
+    ("ReceiveMessageWaitTimeSeconds", 0, 20),
+    ("VisibilityTimeout", 0, 43200),
+    ("KmsDataKeyReusePeriodSeconds", 60, 86400),
+];
+
+/// Queue attributes whose value must be `true` or `false`
+const BOOLEAN_ATTRIBUTES: [&str; 3] = [
+    "FifoQueue",
+    "ContentBasedDeduplication",
+    "SqsManagedSseEnabled",
+];
+
+/// Queue attributes that only take one of a fixed set of values
+const ENUM_ATTRIBUTES: [(&str, &[&str]); 2] = [
+    ("DeduplicationScope", &["messageGroup", "queue"]),
+    ("FifoThroughputLimit", &["perQueue", "perMessageGroupId"]),
+];
+
+/// Queue attributes that are only valid on FIFO queues
+pub const FIFO_ONLY_ATTRIBUTES: [&str; 3] = [
+    "ContentBasedDeduplication",
+    "DeduplicationScope",
+    "FifoThroughputLimit",
+];
+
+/// Check that a queue attribute value has the type and range the attribute expects.
+/// Attributes without a known shape are accepted as-is.
+pub fn validate_attribute_value(name: &str, value: &str) -> anyhow::Result<()> {
+    if let Some((_, min, max)) = NUMERIC_ATTRIBUTE_RANGES.iter().find(|(n, _, _)| *n == name) {
+        let parsed = value
+            .parse::<i64>()
+            .map_err(|_| anyhow::anyhow!("Invalid value for the parameter {}: {}", name, value))?;
+        if parsed < *min || parsed > *max {
+            return Err(anyhow::anyhow!(
+                "Invalid value for the parameter {}: {} (must be between {} and {})",
+                name,
+                value,
+                min,
+                max
+            ));
+        }
+    } else if BOOLEAN_ATTRIBUTES.contains(&name) {
+        if value != "true" && value != "false" {
+            return Err(anyhow::anyhow!(
+                "Invalid value for the parameter {}: {}",
+                name,
+                value
+            ));
+        }
+    } else if let Some((_, allowed)) = ENUM_ATTRIBUTES.iter().find(|(n, _)| *n == name) {
+        if !allowed.contains(&value) {
+            return Err(anyhow::anyhow!(
+                "Invalid value for the parameter {}: {} (must be one of {})",
+                name,
+                value,
+                allowed.join(", ")
+            ));
+        }
+    } else if name == "RedrivePolicy" {
+        validate_redrive_policy(value)?;
+    } else if name == "RedriveAllowPolicy" {
+        validate_redrive_allow_policy(value)?;
+    }
+
+    Ok(())
+}
+
+/// Most source queues a `byQueue` RedriveAllowPolicy may list
+const MAX_REDRIVE_SOURCE_QUEUES: usize = 10;
+
+/// RedriveAllowPolicy is a JSON object like `{"redrivePermission":"allowAll"}`, or
+/// `{"redrivePermission":"byQueue","sourceQueueArns":["arn:..."]}` listing 1 to 10 queues.
+fn validate_redrive_allow_policy(value: &str) -> anyhow::Result<()> {
+    let invalid = |reason: &str| {
+        Err(anyhow::anyhow!(
+            "Invalid value for the parameter RedriveAllowPolicy: {}",
+            reason
+        ))
+    };
+    let Ok(policy) = serde_json::from_str::<serde_json::Value>(value) else {
+        return invalid("not a JSON object");
+    };
+    let source_queue_arns = &policy["sourceQueueArns"];
+    match policy["redrivePermission"].as_str() {
+        Some("allowAll" | "denyAll") if source_queue_arns.is_null() => Ok(()),
+        Some("allowAll" | "denyAll") => invalid("sourceQueueArns is only valid with byQueue"),
+        Some("byQueue") => {
+            let arns = source_queue_arns.as_array().map_or(&[][..], Vec::as_slice);
+            if !(1..=MAX_REDRIVE_SOURCE_QUEUES).contains(&arns.len()) {
+                return invalid("byQueue needs 1 to 10 sourceQueueArns");
+            }
+            if !arns
+                .iter()
+                .all(|arn| arn.as_str().and_then(queue_name_from_arn).is_some())
+            {
+                return invalid("sourceQueueArns must be queue ARNs");
+            }
+            Ok(())
+        }
+        _ => invalid("redrivePermission must be allowAll, denyAll or byQueue"),
+    }
+}
+
+/// Whether a dead-letter queue's RedriveAllowPolicy lets the queue `source_arn` redrive
+/// into it. A policy that can't be read allows everything, like having none.
+pub fn redrive_allows(redrive_allow_policy: &str, source_arn: &str) -> bool {
+    let Ok(policy) = serde_json::from_str::<serde_json::Value>(redrive_allow_policy) else {
+        return true;
+    };
+    match policy["redrivePermission"].as_str() {
+        Some("denyAll") => false,
+        Some("byQueue") => policy["sourceQueueArns"]
+            .as_array()
+            .is_some_and(|arns| arns.iter().any(|arn| arn.as_str() == Some(source_arn))),
+        _ => true,
+    }
+}
+
+/// RedrivePolicy is a JSON object like `{"deadLetterTargetArn":"arn:...","maxReceiveCount":"5"}`
+fn validate_redrive_policy(value: &str) -> anyhow::Result<()> {
+    let policy: serde_json::Value = serde_json::from_str(value)
+        .map_err(|e| anyhow::anyhow!("Invalid value for the parameter RedrivePolicy: {}", e))?;
+
+    if !policy["deadLetterTargetArn"].is_string() {
+        return Err(anyhow::anyhow!(
+            "Invalid value for the parameter RedrivePolicy: missing deadLetterTargetArn"
+        ));
+    }
+
+    let max_receive_count = match &policy["maxReceiveCount"] {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.parse::<u64>().ok(),
+        _ => None,
+    };
+    if !max_receive_count.is_some_and(|n| (1..=1000).contains(&n)) {
+        return Err(anyhow::anyhow!(
+            "Invalid value for the parameter RedrivePolicy: invalid maxReceiveCount"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Content type of Query protocol (XML) responses.
+pub const XML_CONTENT_TYPE: &str = "text/xml";
+
+/// Content type of JSON protocol responses.
+pub const JSON_CONTENT_TYPE: &str = "application/x-amz-json-1.0";
+
+/// Namespace carried by the root element of every Query protocol response.
+pub const XML_NAMESPACE: &str = "http://queue.amazonaws.com/doc/2012-11-05/";
+
+/// Serialize a response struct to XML, tagging the root element with the SQS namespace.
+pub fn to_xml<T: Serialize>(response: &T) -> Result<String, quick_xml::DeError> {
+    let xml = quick_xml::se::to_string(response)?;
+    Ok(add_xml_namespace(&xml))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    error: ErrorDetail,
+    request_id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorDetail {
+    #[serde(rename = "Type")]
+    error_type: String,
+    code: String,
+    message: String,
+}
+
+/// Build an SQS-style XML `ErrorResponse`. 5xx errors are blamed on the receiver,
+/// everything else on the sender.
+pub fn error_response(status: StatusCode, code: &str, message: &str) -> HttpResponse {
+    let response = ErrorResponse {
+        error: ErrorDetail {
+            error_type: if status.is_server_error() {
+                "Receiver"
+            } else {
+                "Sender"
+            }
+            .to_string(),
+            code: code.to_string(),
+            message: message.to_string(),
+        },
+        request_id: request_id(),
+    };
+
+    let mut builder = HttpResponse::build(status);
+    builder.insert_header((REQUEST_ID_HEADER, response.request_id.as_str()));
+    match to_xml(&response) {
+        Ok(body) => builder.body(body),
+        Err(_) => builder.body(format!("{}; {}", code, message)),
+    }
+}
+
+/// The JSON protocol form of an error, for requests that came with `x-amz-target`.
+pub fn json_error_response(status: StatusCode, code: &str, message: &str) -> HttpResponse {
+    let fault = if status.is_server_error() {
+        "Receiver"
+    } else {
+        "Sender"
+    };
+    let body = serde_json::json!({
+        "__type": format!("com.amazonaws.sqs#{}", code),
+        "message": message,
+    });
+    HttpResponse::build(status)
+        .insert_header((REQUEST_ID_HEADER, request_id()))
+        .insert_header(("x-amzn-query-error", format!("{};{}", code, fault)))
+        .content_type(JSON_CONTENT_TYPE)
+        .body(body.to_string())
+}
+
+/// Insert the `xmlns` attribute into the root element of an XML document.
+pub fn add_xml_namespace(xml: &str) -> String {
+    let name_end = xml
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '>' || *c == '/' || c.is_whitespace())
+        .map(|(i, _)| i);
+
+    match name_end {
+        Some(i) if xml.starts_with('<') => {
+            format!(r#"{} xmlns="{}"{}"#, &xml[..i], XML_NAMESPACE, &xml[i..])
+        }
+        _ => xml.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use regex::RegexBuilder;
@@ -99,4 +745,352 @@ mod tests {
         assert_eq!(attrs[1].name, "MaximumMessageSize");
         assert_eq!(attrs[1].value, "262144");
     }
+
+    #[test]
+    fn test_extract_from_extra_tags_with_odd_indexes() {
+        // Indexes that a list sized by the parameter count couldn't hold
+        let extra = HashMap::from([
+            ("Tag.0.Key".to_string(), "zero".to_string()),
+            ("Tag.0.Value".to_string(), "0".to_string()),
+            ("Tag.7.Key".to_string(), "seven".to_string()),
+            ("Tag.7.Value".to_string(), "7".to_string()),
+            ("Tag.9.Value".to_string(), "no key".to_string()),
+        ]);
+        let re = RegexBuilder::new(r"^Tag\.(\d+)\.(.+)$")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let tags = super::extract_from_extra(re, extra).unwrap();
+        let tags: Vec<_> = tags
+            .iter()
+            .map(|t| (t.name.as_str(), t.value.as_str()))
+            .collect();
+        assert_eq!(tags, [("zero", "0"), ("seven", "7")]);
+    }
+
+    #[test]
+    fn test_add_xml_namespace() {
+        assert_eq!(
+            add_xml_namespace("<Foo><Bar/></Foo>"),
+            format!(r#"<Foo xmlns="{}"><Bar/></Foo>"#, XML_NAMESPACE)
+        );
+        assert_eq!(
+            add_xml_namespace("<Foo/>"),
+            format!(r#"<Foo xmlns="{}"/>"#, XML_NAMESPACE)
+        );
+    }
+
+    #[test]
+    fn test_validate_attribute_value() {
+        assert!(validate_attribute_value("VisibilityTimeout", "30").is_ok());
+        assert!(validate_attribute_value("VisibilityTimeout", "abc").is_err());
+        assert!(validate_attribute_value("VisibilityTimeout", "43201").is_err());
+        assert!(validate_attribute_value("DelaySeconds", "-5").is_err());
+        assert!(validate_attribute_value("DelaySeconds", "900").is_ok());
+        assert!(validate_attribute_value("MaximumMessageSize", "100").is_err());
+        assert!(validate_attribute_value("FifoQueue", "true").is_ok());
+        assert!(validate_attribute_value("FifoQueue", "1").is_err());
+        assert!(validate_attribute_value("Policy", "anything").is_ok());
+        assert!(validate_attribute_value("DeduplicationScope", "messageGroup").is_ok());
+        assert!(validate_attribute_value("DeduplicationScope", "perQueue").is_err());
+        assert!(validate_attribute_value("FifoThroughputLimit", "perMessageGroupId").is_ok());
+        assert!(validate_attribute_value("FifoThroughputLimit", "queue").is_err());
+    }
+
+    #[test]
+    fn test_redrive_allow_policy() {
+        let source = "arn:aws:sqs:us-east-1:000000000000:orders";
+        for value in [
+            r#"{"redrivePermission":"allowAll"}"#,
+            r#"{"redrivePermission":"denyAll"}"#,
+            r#"{"redrivePermission":"byQueue","sourceQueueArns":["arn:aws:sqs:us-east-1:000000000000:orders"]}"#,
+        ] {
+            assert!(
+                validate_attribute_value("RedriveAllowPolicy", value).is_ok(),
+                "{}",
+                value
+            );
+        }
+        for value in [
+            "allowAll",
+            r#"{"redrivePermission":"maybe"}"#,
+            r#"{"redrivePermission":"byQueue"}"#,
+            r#"{"redrivePermission":"byQueue","sourceQueueArns":[]}"#,
+            r#"{"redrivePermission":"byQueue","sourceQueueArns":["orders"]}"#,
+            r#"{"redrivePermission":"allowAll","sourceQueueArns":["arn:aws:sqs:us-east-1:000000000000:orders"]}"#,
+        ] {
+            assert!(
+                validate_attribute_value("RedriveAllowPolicy", value).is_err(),
+                "{}",
+                value
+            );
+        }
+
+        assert!(redrive_allows(
+            r#"{"redrivePermission":"allowAll"}"#,
+            source
+        ));
+        assert!(!redrive_allows(
+            r#"{"redrivePermission":"denyAll"}"#,
+            source
+        ));
+        let by_queue = r#"{"redrivePermission":"byQueue","sourceQueueArns":["arn:aws:sqs:us-east-1:000000000000:orders"]}"#;
+        assert!(redrive_allows(by_queue, source));
+        assert!(!redrive_allows(
+            by_queue,
+            "arn:aws:sqs:us-east-1:000000000000:other"
+        ));
+    }
+
+    #[test]
+    fn test_extract_batch_entries() {
+        let payload = b"Action=SendMessageBatch&SendMessageBatchRequestEntry.2.Id=b&SendMessageBatchRequestEntry.1.Id=a&SendMessageBatchRequestEntry.1.MessageBody=hi&SendMessageBatchRequestEntry.10.Id=c";
+        let entries = extract_batch_entries(payload, "SendMessageBatchRequestEntry");
+        let ids: Vec<&str> = entries.iter().map(|e| e["Id"].as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(entries[0]["MessageBody"], "hi");
+    }
+
+    #[test]
+    fn test_parse_message_attributes() {
+        let params: Vec<(String, String)> = serde_urlencoded::from_str(
+            "MessageAttribute.1.Name=s&MessageAttribute.1.Value.DataType=String&MessageAttribute.1.Value.StringValue=hi&MessageAttribute.2.Name=blob&MessageAttribute.2.Value.DataType=Binary&MessageAttribute.2.Value.BinaryValue=AAEC%2Fw%3D%3D",
+        )
+        .unwrap();
+        let attrs = parse_message_attributes(params.iter().map(|(k, v)| (k, v))).unwrap();
+        assert_eq!(
+            attrs,
+            vec![
+                MessageAttribute {
+                    name: "blob".to_string(),
+                    data_type: "Binary".to_string(),
+                    value: MessageAttributeValue::Binary(vec![0, 1, 2, 255]),
+                },
+                MessageAttribute {
+                    name: "s".to_string(),
+                    data_type: "String".to_string(),
+                    value: MessageAttributeValue::String("hi".to_string()),
+                },
+            ]
+        );
+        assert_eq!(
+            compute_message_attributes_md5(&attrs),
+            "a7aeba3079ca36141c391e5d738dce6a"
+        );
+
+        for bad in [
+            "MessageAttribute.1.Name=a&MessageAttribute.1.Value.StringValue=x",
+            "MessageAttribute.1.Name=a&MessageAttribute.1.Value.DataType=Binary&MessageAttribute.1.Value.BinaryValue=***",
+            "MessageAttribute.1.Name=a&MessageAttribute.1.Value.DataType=Blob&MessageAttribute.1.Value.StringValue=x",
+        ] {
+            let params: Vec<(String, String)> = serde_urlencoded::from_str(bad).unwrap();
+            assert!(parse_message_attributes(params.iter().map(|(k, v)| (k, v))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_attribute_name() {
+        for good in [
+            "color",
+            "my-attr_1.v2",
+            "AWSish",
+            "amazonian.x",
+            &"a".repeat(256),
+        ] {
+            assert!(validate_attribute_name(good).is_ok(), "{}", good);
+        }
+        for bad in [
+            "AWS.trace",
+            "amazon.thing",
+            ".leading",
+            "trailing.",
+            "two..dots",
+            "has space",
+            "ünicode",
+            &"a".repeat(257),
+        ] {
+            assert!(validate_attribute_name(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_wants_all() {
+        let requested = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(wants_all(&requested(&["All"])));
+        assert!(wants_all(&requested(&["SenderId", ".*"])));
+        assert!(!wants_all(&requested(&["SenderId", "foo.*"])));
+        assert!(!wants_all(&[]));
+    }
+
+    #[test]
+    fn test_validate_message_body() {
+        assert!(validate_message_body("a ]]> b & <c> \t\r\n \u{1F600}").is_ok());
+        assert!(validate_message_body("bell \u{7}").is_err());
+        assert!(validate_message_body("\u{FFFF}").is_err());
+    }
+
+    #[test]
+    fn test_parse_message_system_attributes() {
+        let params: Vec<(String, String)> = serde_urlencoded::from_str(
+            "MessageAttribute.1.Name=user&MessageAttribute.1.Value.DataType=String&MessageAttribute.1.Value.StringValue=u&MessageSystemAttribute.1.Name=AWSTraceHeader&MessageSystemAttribute.1.Value.DataType=String&MessageSystemAttribute.1.Value.StringValue=Root%3D1-5759e988-bd862e3fe1be46a994272793",
+        )
+        .unwrap();
+        let params = || params.iter().map(|(k, v)| (k, v));
+
+        let user = parse_message_attributes(params()).unwrap();
+        assert_eq!(user.len(), 1);
+        assert_eq!(user[0].name, "user");
+
+        let system = parse_message_system_attributes(params()).unwrap();
+        assert_eq!(
+            system,
+            vec![MessageAttribute {
+                name: "AWSTraceHeader".to_string(),
+                data_type: "String".to_string(),
+                value: MessageAttributeValue::String(
+                    "Root=1-5759e988-bd862e3fe1be46a994272793".to_string()
+                ),
+            }]
+        );
+
+        for bad in [
+            "MessageSystemAttribute.1.Name=Other&MessageSystemAttribute.1.Value.DataType=String&MessageSystemAttribute.1.Value.StringValue=x",
+            "MessageSystemAttribute.1.Name=AWSTraceHeader&MessageSystemAttribute.1.Value.DataType=Number&MessageSystemAttribute.1.Value.StringValue=1",
+        ] {
+            let params: Vec<(String, String)> = serde_urlencoded::from_str(bad).unwrap();
+            assert!(parse_message_system_attributes(params.iter().map(|(k, v)| (k, v))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_extract_queue_name_from_url() {
+        for url in [
+            "http://localhost:9324/queue1",
+            "http://localhost:9324/queue1/",
+            "http://localhost:9324/000000000000/queue1",
+            "http://localhost:9324/queue1?foo=bar",
+            "https://sqs.us-east-1.amazonaws.com/000000000000/queue1/?foo=bar",
+            "queue1",
+            "000000000000/queue1",
+        ] {
+            assert_eq!(
+                extract_queue_name_from_url(url).as_deref(),
+                Some("queue1"),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(extract_queue_name_from_url("http://localhost:9324"), None);
+        assert_eq!(extract_queue_name_from_url("http://localhost:9324/"), None);
+        assert_eq!(extract_queue_name_from_url(""), None);
+
+        // Bare queue names, but not hosts or other tokens that can't be one
+        assert_eq!(
+            extract_queue_name_from_url("my-queue_1.fifo").as_deref(),
+            Some("my-queue_1.fifo")
+        );
+        for url in [
+            "localhost:9324",
+            "localhost:9324/",
+            "my queue",
+            &"q".repeat(81),
+        ] {
+            assert_eq!(extract_queue_name_from_url(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_queue_name_from_arn() {
+        assert_eq!(
+            queue_name_from_arn("arn:aws:sqs:us-east-1:000000000000:orders"),
+            Some("orders")
+        );
+        assert_eq!(
+            queue_name_from_arn("arn:aws:sqs:eu-west-1:123456789012:jobs.fifo"),
+            Some("jobs.fifo")
+        );
+        assert_eq!(
+            queue_name_from_arn("arn:aws:sns:us-east-1:000000000000:t"),
+            None
+        );
+        assert_eq!(queue_name_from_arn("orders"), None);
+        assert_eq!(
+            queue_name_from_arn("arn:aws:sqs:us-east-1:000000000000:"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_build_queue_url() {
+        let style = |scheme, form| UrlStyle { scheme, form };
+        let cases = [
+            (
+                "http://localhost:9090",
+                style(None, UrlForm::Path),
+                "http://localhost:9090/000000000000/q",
+            ),
+            (
+                "localhost:9090/",
+                style(None, UrlForm::Path),
+                "http://localhost:9090/000000000000/q",
+            ),
+            (
+                "http://localhost:9090",
+                style(Some(UrlScheme::Https), UrlForm::Path),
+                "https://localhost:9090/000000000000/q",
+            ),
+            (
+                "https://localhost:9090",
+                style(Some(UrlScheme::Http), UrlForm::VirtualHost),
+                "http://sqs.localhost:9090/000000000000/q",
+            ),
+            (
+                "localhost:9090",
+                style(Some(UrlScheme::Https), UrlForm::VirtualHost),
+                "https://sqs.localhost:9090/000000000000/q",
+            ),
+        ];
+        for (host, style, expected) in cases {
+            let url = build_queue_url(host, "000000000000", "q", style);
+            assert_eq!(url, expected);
+            assert_eq!(extract_queue_name_from_url(&url).as_deref(), Some("q"));
+        }
+    }
+
+    #[test]
+    fn test_validate_batch_entries() {
+        let ids = |n: usize| (1..=n).map(|i| i.to_string()).collect::<Vec<_>>();
+
+        assert!(validate_batch_entries(&ids(1)).is_ok());
+        assert!(validate_batch_entries(&ids(10)).is_ok());
+
+        assert_eq!(
+            validate_batch_entries(&[]),
+            Err(SqsError::EmptyBatchRequest)
+        );
+        assert_eq!(
+            validate_batch_entries(&ids(11)),
+            Err(SqsError::TooManyEntriesInBatchRequest(11))
+        );
+
+        let dup = vec!["a".to_string(), "b".to_string(), "a".to_string()];
+        assert_eq!(
+            validate_batch_entries(&dup),
+            Err(SqsError::BatchEntryIdsNotDistinct("a".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_response_shape() {
+        let resp = error_response(StatusCode::BAD_REQUEST, "InvalidParameterValue", "bad");
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(&format!(r#"<ErrorResponse xmlns="{}">"#, XML_NAMESPACE)));
+        assert!(body.contains(
+            "<Error><Type>Sender</Type><Code>InvalidParameterValue</Code><Message>bad</Message></Error>"
+        ));
+        assert!(body.contains("<RequestId>"));
+    }
 }
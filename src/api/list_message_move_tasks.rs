@@ -0,0 +1,180 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::move_task::{MoveTask, MoveTaskStatus};
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListMessageMoveTasksParams {
+    source_arn: Option<String>,
+    max_results: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListMessageMoveTasksResponse {
+    list_message_move_tasks_result: ListMessageMoveTasksResult,
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListMessageMoveTasksResult {
+    #[serde(rename = "Result")]
+    results: Vec<MoveTaskXml>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MoveTaskXml {
+    /// Only for running tasks, the ones that can still be cancelled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task_handle: Option<String>,
+    status: String,
+    source_arn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination_arn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_number_of_messages_per_second: Option<u32>,
+    approximate_number_of_messages_moved: u64,
+    approximate_number_of_messages_to_move: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_reason: Option<String>,
+    started_timestamp: u64,
+}
+
+impl From<MoveTask> for MoveTaskXml {
+    fn from(task: MoveTask) -> Self {
+        MoveTaskXml {
+            task_handle: (task.status == MoveTaskStatus::Running).then_some(task.task_handle),
+            status: task.status.as_str().to_string(),
+            source_arn: task.source_arn,
+            destination_arn: task.destination_arn,
+            max_number_of_messages_per_second: task.max_number_of_messages_per_second,
+            approximate_number_of_messages_moved: task.approximate_number_of_messages_moved,
+            approximate_number_of_messages_to_move: task.approximate_number_of_messages_to_move,
+            failure_reason: task.failure_reason,
+            started_timestamp: task.started_timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// List the most recent message move tasks out of a dead-letter queue, newest first.
+/// `MaxResults` defaults to 1, as in AWS.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<ListMessageMoveTasksParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let Some(source_arn) = params.source_arn else {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter SourceArn.".to_string(),
+        )
+        .error_response();
+    };
+
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let tasks = match engine
+        .list_message_move_tasks(&source_arn, params.max_results.unwrap_or(1))
+        .await
+    {
+        Ok(tasks) => tasks,
+        Err(e) => return e.error_response(),
+    };
+
+    let response = ListMessageMoveTasksResponse {
+        list_message_move_tasks_result: ListMessageMoveTasksResult {
+            results: tasks.into_iter().map(MoveTaskXml::from).collect(),
+        },
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SqsEngine;
+    use crate::queue::Message;
+    use actix_web::http::StatusCode;
+    use std::collections::HashMap;
+
+    const DLQ_ARN: &str = "arn:aws:sqs:us-east-1:000000000000:dlq";
+
+    #[tokio::test]
+    async fn test_lists_task_after_start() {
+        let engine = SqsEngine::new(Arc::new(AppState::for_tests_with_db().await));
+        let redrive = format!(
+            r#"{{"deadLetterTargetArn":"{}","maxReceiveCount":"3"}}"#,
+            DLQ_ARN
+        );
+        engine
+            .create_queue("dlq", HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+        engine
+            .create_queue(
+                "orders",
+                HashMap::from([("RedrivePolicy".to_string(), redrive)]),
+                HashMap::new(),
+            )
+            .await
+            .unwrap();
+        engine
+            .send("dlq", Message::new("1".to_owned(), "hi".to_owned()), None)
+            .await
+            .unwrap();
+        engine
+            .start_message_move_task(DLQ_ARN, None, Some(10))
+            .await
+            .unwrap();
+
+        let payload = web::Bytes::from(format!(
+            "Action=ListMessageMoveTasks&SourceArn={}&MaxResults=10",
+            DLQ_ARN
+        ));
+        let resp = process(engine.state().clone(), &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert_eq!(body.matches("<Result>").count(), 1, "{}", body);
+        for part in [
+            "<Status>COMPLETED</Status>",
+            &format!("<SourceArn>{}</SourceArn>", DLQ_ARN),
+            "<MaxNumberOfMessagesPerSecond>10</MaxNumberOfMessagesPerSecond>",
+            "<ApproximateNumberOfMessagesMoved>1</ApproximateNumberOfMessagesMoved>",
+            "<ApproximateNumberOfMessagesToMove>1</ApproximateNumberOfMessagesToMove>",
+        ] {
+            assert!(body.contains(part), "{} in {}", part, body);
+        }
+        // A finished task can't be cancelled, so its handle isn't listed
+        assert!(!body.contains("<TaskHandle>"));
+        assert!(!body.contains("<DestinationArn>"));
+
+        let payload = web::Bytes::from("Action=ListMessageMoveTasks");
+        let resp = process(engine.state().clone(), &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
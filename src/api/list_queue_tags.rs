@@ -0,0 +1,134 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListQueueTagsParams {
+    queue_url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListQueueTagsResponse {
+    list_queue_tags_result: ListQueueTagsResult,
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListQueueTagsResult {
+    #[serde(rename = "Tag")]
+    tags: Vec<TagXml>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct TagXml {
+    key: String,
+    value: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// List the tags a queue was created with, sorted by key.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<ListQueueTagsParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let queue_name = match helpers::extract_queue_name_from_url(&params.queue_url) {
+        Some(name) => name,
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
+    };
+
+    let queue = match app_state.get_queue(&queue_name).await {
+        Some(q) => q,
+        None => return SqsError::NonExistentQueue(queue_name).error_response(),
+    };
+
+    let tags = queue
+        .lock()
+        .await
+        .tags()
+        .iter()
+        .map(|tag| TagXml {
+            key: tag.key.clone(),
+            value: tag.value.clone(),
+        })
+        .collect();
+    let response = ListQueueTagsResponse {
+        list_queue_tags_result: ListQueueTagsResult { tags },
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_tags_set_at_creation() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let payload = serde_urlencoded::to_string([
+            ("Action", "CreateQueue"),
+            ("QueueName", "tagged"),
+            ("Tag.1.Key", "team"),
+            ("Tag.1.Value", "billing"),
+            ("Tag.2.Key", "env"),
+            ("Tag.2.Value", "dev & test"),
+        ])
+        .unwrap();
+        let resp =
+            super::super::create_queue::process(state.clone(), &web::Bytes::from(payload), false)
+                .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let payload =
+            web::Bytes::from("Action=ListQueueTags&QueueUrl=http://localhost:9090/tagged");
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(
+            body.contains("<ListQueueTagsResult><Tag><Key>env</Key><Value>dev &amp; test</Value></Tag><Tag><Key>team</Key><Value>billing</Value></Tag></ListQueueTagsResult>"),
+            "{}",
+            body
+        );
+
+        // And they were stored for the next start
+        let stored = crate::service::queue::Queue::new(&state.db_pool)
+            .get_queue_tags("tagged")
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored["team"], "billing");
+
+        let payload =
+            web::Bytes::from("Action=ListQueueTags&QueueUrl=http://localhost:9090/missing");
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,8 +1,8 @@
 use actix_web::{web, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use serde::{Deserialize, Serialize};
-use tracing::error;
-use uuid::Uuid;
 
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -14,11 +14,11 @@ struct RequestParams {
     next_token: Option<String>,
 }
 
-/// Default max results is 1000
-/// The code is not dead
-#[allow(dead_code)]
+/// Most queue URLs returned in one page, and the default page size
+const MAX_RESULTS: i32 = 1000;
+
 fn default_max_results() -> i32 {
-    1000
+    MAX_RESULTS
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -32,6 +32,9 @@ struct ListQueuesResponse {
 #[serde(rename_all = "PascalCase")]
 struct ListQueuesResult {
     queue_url: Vec<String>,
+    /// Set when more queues follow; pass it back to get the next page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,43 +43,61 @@ struct ResponseMetadata {
     request_id: String,
 }
 
+/// List queue URLs a page at a time, straight from the database. The queue map is never
+/// touched, and a page holds at most `MAX_RESULTS` URLs, so the response stays small
+/// however many queues there are and is simply built in memory rather than streamed.
 pub async fn process(app_state: &AppState, payload: &web::Bytes, is_json: bool) -> HttpResponse {
     let params = match get_params(payload, is_json) {
         Some(params) => params,
-        None => return HttpResponse::BadRequest().finish(),
+        None => {
+            return SqsError::MalformedQueryString("Failed to parse payload".to_string())
+                .error_response()
+        }
+    };
+
+    let after_id = match params.next_token.as_deref().map(decode_next_token) {
+        Some(Some(id)) => Some(id),
+        Some(None) => {
+            return SqsError::InvalidParameterValue("Invalid NextToken value.".to_string())
+                .error_response()
+        }
+        None => None,
     };
 
-    let service = crate::service::queue::Queue::new(&app_state.db_pool, &app_state.host_name);
-    let queue_urls = match service
+    let service = crate::service::queue::Queue::new(&app_state.db_pool);
+    let (queue_names, last_id) = match service
         .list_queue(
-            params.max_results as u32,
+            params.max_results.clamp(1, MAX_RESULTS) as u32,
             params.queue_name_prefix,
-            params.next_token,
+            after_id,
         )
         .await
     {
-        Ok(queue_urls) => queue_urls,
+        Ok(page) => page,
         Err(e) => {
-            error!("Failed to list queues: {}", e);
-            return HttpResponse::InternalServerError().finish();
+            return SqsError::InternalFailure(format!("Failed to list queues: {}", e))
+                .error_response();
         }
     };
 
-    let id = Uuid::new_v4();
     let response = ListQueuesResponse {
         list_queues_result: ListQueuesResult {
-            queue_url: queue_urls,
+            queue_url: queue_names
+                .iter()
+                .map(|name| app_state.queue_url(name))
+                .collect(),
+            next_token: last_id.map(encode_next_token),
         },
         response_metadata: ResponseMetadata {
-            request_id: id.to_string(),
+            request_id: super::helpers::request_id(),
         },
     };
 
-    let response = match quick_xml::se::to_string(&response) {
+    let response = match super::helpers::to_xml(&response) {
         Ok(response) => response,
         Err(e) => {
-            error!("Failed to serialize response: {}", e);
-            return HttpResponse::InternalServerError().finish();
+            return SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+                .error_response();
         }
     };
 
@@ -92,3 +113,123 @@ fn get_params(payload: &web::Bytes, _is_json: bool) -> Option<RequestParams> {
 
     Some(params)
 }
+
+/// Page tokens are the opaque-looking id of the last queue on the previous page.
+fn encode_next_token(last_id: i64) -> String {
+    URL_SAFE_NO_PAD.encode(last_id.to_string())
+}
+
+fn decode_next_token(token: &str) -> Option<i64> {
+    let decoded = URL_SAFE_NO_PAD.decode(token).ok()?;
+    String::from_utf8(decoded).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::queue::QueueEntity;
+
+    #[tokio::test]
+    async fn test_paging_through_many_queues() {
+        let state = AppState::for_tests_with_db().await;
+        let service = crate::service::queue::Queue::new(&state.db_pool);
+        for i in 0..2500 {
+            service
+                .create_queue(QueueEntity {
+                    id: None,
+                    name: format!("queue-{:04}", i),
+                    queue_type: "Standard".to_owned(),
+                    attributes: None,
+                    tags: None,
+                    created_at: None,
+                    updated_at: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let started = std::time::Instant::now();
+        let mut urls = Vec::new();
+        let mut pages = 0;
+        let mut payload = "Action=ListQueues".to_string();
+        loop {
+            let resp = process(&state, &web::Bytes::from(payload.clone()), false).await;
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            // A full page is about 60 KiB, whatever the total number of queues
+            assert!(body.len() < 100 * 1024, "{} bytes", body.len());
+            let body = String::from_utf8_lossy(&body).into_owned();
+            pages += 1;
+            urls.extend(
+                body.split("<QueueUrl>")
+                    .skip(1)
+                    .filter_map(|rest| rest.split("</QueueUrl>").next())
+                    .map(str::to_string),
+            );
+            match body
+                .split("<NextToken>")
+                .nth(1)
+                .and_then(|rest| rest.split("</NextToken>").next())
+            {
+                Some(token) => payload = format!("Action=ListQueues&NextToken={}", token),
+                None => break,
+            }
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        assert_eq!(pages, 3);
+        assert_eq!(urls.len(), 2500);
+        let expected: Vec<String> = (0..2500)
+            .map(|i| state.queue_url(&format!("queue-{:04}", i)))
+            .collect();
+        assert_eq!(urls, expected);
+    }
+
+    #[tokio::test]
+    async fn test_first_page_is_capped_at_1000() {
+        let state = AppState::for_tests_with_db().await;
+        let service = crate::service::queue::Queue::new(&state.db_pool);
+        for i in 0..1001 {
+            service
+                .create_queue(QueueEntity {
+                    id: None,
+                    name: format!("q{}", i),
+                    queue_type: "Standard".to_owned(),
+                    attributes: None,
+                    tags: None,
+                    created_at: None,
+                    updated_at: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let resp = process(&state, &web::Bytes::from("Action=ListQueues"), false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert_eq!(body.matches("<QueueUrl>").count(), 1000);
+        let token = body
+            .split("<NextToken>")
+            .nth(1)
+            .and_then(|rest| rest.split("</NextToken>").next())
+            .expect("truncated page has a NextToken");
+
+        let payload = format!("Action=ListQueues&NextToken={}", token);
+        let resp = process(&state, &web::Bytes::from(payload), false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert_eq!(body.matches("<QueueUrl>").count(), 1);
+        assert!(body.contains("/q1000</QueueUrl>"));
+        assert!(!body.contains("NextToken"));
+
+        let resp = process(
+            &state,
+            &web::Bytes::from("Action=ListQueues&MaxResults=10"),
+            false,
+        )
+        .await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert_eq!(body.matches("<QueueUrl>").count(), 10);
+        assert!(body.contains("<NextToken>"));
+    }
+}
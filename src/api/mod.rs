@@ -1,17 +1,48 @@
+use crate::error::SqsError;
 use crate::AppState;
-use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse};
 use serde::{de::DeserializeOwned, Deserialize};
+use tracing::Instrument;
 
+mod add_permission;
+mod cancel_message_move_task;
 mod change_message_visibility;
 mod create_queue;
 mod delete_message;
 mod get_queue_attributes;
 mod get_queue_url;
-mod helpers;
+pub mod helpers;
+mod list_message_move_tasks;
+mod list_queue_tags;
 mod list_queues;
+mod purge_queue;
 mod receive_message;
+mod remove_permission;
 mod send_message;
+mod send_message_batch;
 mod set_queue_attributes;
+mod start_message_move_task;
+
+/// Every action `route` handles, as `chaos::action_key` spells them.
+const ACTIONS: &[&str] = &[
+    "createqueue",
+    "listqueues",
+    "sendmessage",
+    "sendmessagebatch",
+    "receivemessage",
+    "deletemessage",
+    "changemessagevisibility",
+    "getqueueurl",
+    "getqueueattributes",
+    "purgequeue",
+    "setqueueattributes",
+    "startmessagemovetask",
+    "listmessagemovetasks",
+    "listqueuetags",
+    "cancelmessagemovetask",
+    "addpermission",
+    "removepermission",
+];
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
@@ -19,51 +50,238 @@ struct RequestPayload {
     action: String,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct QueueUrlPayload {
+    queue_url: Option<String>,
+}
+
+/// The `web::Bytes` extractor decodes gzip/deflate/br/zstd request bodies according to
+/// `Content-Encoding`, so handlers always see the plain form body.
 #[post("/")]
 pub async fn post_handler(
     app_state: web::Data<AppState>,
     payload: web::Bytes,
     req: HttpRequest,
 ) -> HttpResponse {
+    let payload = with_query_params(payload, &req);
+    let action = match get_action_name(&payload, &req) {
+        Some(a) => a,
+        None => return missing_action(&req),
+    };
+
+    dispatch(app_state, &action, &payload).await
+}
+
+/// Query protocol clients may put parameters such as `Action` and `Version` in the URL
+/// rather than the form body. Append those the body doesn't already have, so handlers see
+/// one set of parameters. JSON protocol requests are left alone.
+fn with_query_params(payload: web::Bytes, req: &HttpRequest) -> web::Bytes {
+    if req.query_string().is_empty() || req.headers().contains_key("x-amz-target") {
+        return payload;
+    }
+    let (Ok(body), Ok(query)) = (
+        serde_urlencoded::from_bytes::<Vec<(String, String)>>(&payload),
+        serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string()),
+    ) else {
+        return payload;
+    };
+    let extra: Vec<_> = query
+        .into_iter()
+        .filter(|(name, _)| !body.iter().any(|(body_name, _)| body_name == name))
+        .collect();
+    let Ok(extra) = serde_urlencoded::to_string(extra) else {
+        return payload;
+    };
+    if extra.is_empty() {
+        return payload;
+    }
+    let mut merged = payload.to_vec();
+    if !merged.is_empty() {
+        merged.push(b'&');
+    }
+    merged.extend_from_slice(extra.as_bytes());
+    web::Bytes::from(merged)
+}
+
+/// The error for a request naming no action, in the protocol the request used.
+fn missing_action(req: &HttpRequest) -> HttpResponse {
+    if req.headers().contains_key("x-amz-target") {
+        return SqsError::MissingAction.json_error_response();
+    }
+    let mut resp = SqsError::MissingAction.error_response();
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(helpers::XML_CONTENT_TYPE),
+    );
+    resp
+}
+
+/// Any other method on `/`, which only takes `POST`.
+pub async fn method_not_allowed(req: HttpRequest) -> HttpResponse {
+    let mut resp = SqsError::MethodNotAllowed(req.method().to_string()).error_response();
+    resp.headers_mut()
+        .insert(header::ALLOW, header::HeaderValue::from_static("POST"));
+    resp
+}
+
+/// Requests sent to a queue URL used as the endpoint, e.g. `POST /000000000000/myqueue`.
+/// The queue in the path is passed on as `QueueUrl`, and must agree with any `QueueUrl` in the body.
+#[post("/{account}/{queue_name}")]
+pub async fn queue_path_handler(
+    app_state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    payload: web::Bytes,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (account, queue_name) = path.into_inner();
+    let payload = with_query_params(payload, &req);
     let action = match get_action_name(&payload, &req) {
         Some(a) => a,
-        None => return HttpResponse::BadRequest().body("Invalid action"),
+        None => return missing_action(&req),
     };
 
+    let body_queue_url = struct_from_url_encode::<QueueUrlPayload>(&payload)
+        .ok()
+        .and_then(|p| p.queue_url);
+    let payload = match body_queue_url {
+        Some(url) => {
+            if helpers::extract_queue_name_from_url(&url).as_deref() != Some(queue_name.as_str()) {
+                return SqsError::InvalidParameterValue(format!(
+                    "QueueUrl {} does not match the queue in the request path: {}",
+                    url, queue_name
+                ))
+                .error_response();
+            }
+            payload
+        }
+        None => {
+            let queue_url = helpers::build_queue_url(
+                &app_state.host_name,
+                &account,
+                &queue_name,
+                app_state.url_style,
+            );
+            let extra = serde_urlencoded::to_string([("QueueUrl", queue_url)]).unwrap_or_default();
+            let mut merged = payload.to_vec();
+            if !merged.is_empty() {
+                merged.push(b'&');
+            }
+            merged.extend_from_slice(extra.as_bytes());
+            web::Bytes::from(merged)
+        }
+    };
+
+    dispatch(app_state, &action, &payload).await
+}
+
+async fn dispatch(
+    app_state: web::Data<AppState>,
+    action: &str,
+    payload: &web::Bytes,
+) -> HttpResponse {
     let is_json = action.starts_with("AmazonSQS");
+    let request_id = helpers::generate_random_uuid4();
+    let span = tracing::info_span!("request", %request_id, action);
+    let mut resp = helpers::with_request_id(
+        request_id.clone(),
+        route(app_state, action, payload, is_json).instrument(span),
+    )
+    .await;
+
+    // Errors carry the same content type as successes, so SDKs parse them with the right protocol
+    let content_type = if is_json {
+        helpers::JSON_CONTENT_TYPE
+    } else {
+        helpers::XML_CONTENT_TYPE
+    };
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type),
+    );
+    if let Ok(value) = header::HeaderValue::from_str(&request_id) {
+        resp.headers_mut().insert(
+            header::HeaderName::from_static(helpers::REQUEST_ID_HEADER),
+            value,
+        );
+    }
+    resp
+}
+
+async fn route(
+    app_state: web::Data<AppState>,
+    action: &str,
+    payload: &web::Bytes,
+    is_json: bool,
+) -> HttpResponse {
+    if let Some(delay) = crate::chaos::latency_for(&app_state.latency, action) {
+        tokio::time::sleep(delay).await;
+    }
+
+    if let Some(error) = crate::chaos::roll(&app_state.chaos, &app_state.chaos_rng, action) {
+        return error.error_response();
+    }
+
     if is_json {
+        if !ACTIONS.contains(&crate::chaos::action_key(action).as_str()) {
+            return SqsError::InvalidAction(action.to_string()).json_error_response();
+        }
         return HttpResponse::BadRequest().body("JSON is not supported yet");
     }
 
     match action.to_lowercase().as_str() {
         "amazonsqs.createqueue" | "createqueue" => {
-            create_queue::process(app_state.into_inner(), &payload, is_json).await
+            create_queue::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.listqueues" | "listqueues" => {
-            list_queues::process(&app_state, &payload, is_json).await
+            list_queues::process(&app_state, payload, is_json).await
         }
         "amazonsqs.sendmessage" | "sendmessage" => {
-            send_message::process(app_state.into_inner(), &payload, is_json).await
+            send_message::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.sendmessagebatch" | "sendmessagebatch" => {
+            send_message_batch::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.receivemessage" | "receivemessage" => {
-            receive_message::process(app_state.into_inner(), &payload, is_json).await
+            receive_message::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.deletemessage" | "deletemessage" => {
-            delete_message::process(app_state.into_inner(), &payload, is_json).await
+            delete_message::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.changemessagevisibility" | "changemessagevisibility" => {
-            change_message_visibility::process(app_state.into_inner(), &payload, is_json).await
+            change_message_visibility::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.getqueueurl" | "getqueueurl" => {
-            get_queue_url::process(app_state.into_inner(), &payload, is_json).await
+            get_queue_url::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.getqueueattributes" | "getqueueattributes" => {
-            get_queue_attributes::process(app_state.into_inner(), &payload, is_json).await
+            get_queue_attributes::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.purgequeue" | "purgequeue" => {
+            purge_queue::process(app_state.into_inner(), payload, is_json).await
         }
         "amazonsqs.setqueueattributes" | "setqueueattributes" => {
-            set_queue_attributes::process(app_state.into_inner(), &payload, is_json).await
+            set_queue_attributes::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.startmessagemovetask" | "startmessagemovetask" => {
+            start_message_move_task::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.listmessagemovetasks" | "listmessagemovetasks" => {
+            list_message_move_tasks::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.cancelmessagemovetask" | "cancelmessagemovetask" => {
+            cancel_message_move_task::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.listqueuetags" | "listqueuetags" => {
+            list_queue_tags::process(app_state.into_inner(), payload, is_json).await
+        }
+        "amazonsqs.addpermission" | "addpermission" => {
+            add_permission::process(app_state.into_inner(), payload, is_json).await
         }
-        _ => HttpResponse::BadRequest().body("Invalid action"),
+        "amazonsqs.removepermission" | "removepermission" => {
+            remove_permission::process(app_state.into_inner(), payload, is_json).await
+        }
+        _ => SqsError::InvalidAction(action.to_string()).error_response(),
     }
 }
 
@@ -82,15 +300,313 @@ where
     Ok(result)
 }
 
+/// The action from the `x-amz-target` header (JSON protocol) or the `Action` parameter.
+/// None if neither is usable, including a target header that isn't valid text and an
+/// empty action.
 fn get_action_name(payload: &web::Bytes, req: &HttpRequest) -> Option<String> {
-    match req.headers().get("x-amz-target") {
-        Some(target) => Some(target.to_str().unwrap().to_string()),
+    let action = match req.headers().get("x-amz-target") {
+        Some(target) => target.to_str().ok()?.to_string(),
         None => {
-            let act = struct_from_url_encode::<RequestPayload>(payload);
-            if act.is_err() {
-                return None;
-            }
-            Some(act.unwrap().action.to_string())
+            struct_from_url_encode::<RequestPayload>(payload)
+                .ok()?
+                .action
         }
+    };
+    let name = action.strip_prefix("AmazonSQS.").unwrap_or(&action);
+    (!name.trim().is_empty()).then_some(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_request_id_header_matches_body() {
+        let state = AppState::for_tests();
+        state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(post_handler),
+        )
+        .await;
+
+        for payload in [
+            "Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi",
+            "Action=SendMessage&QueueUrl=http://localhost:9090/missing&MessageBody=hi",
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/")
+                .set_payload(payload)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let header = resp
+                .headers()
+                .get(helpers::REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = test::read_body(resp).await;
+            let body = String::from_utf8_lossy(&body);
+            assert!(
+                body.contains(&format!("<RequestId>{}</RequestId>", header)),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_path_style_queue_url() {
+        let state = AppState::for_tests();
+        let queue = state.add_test_queue("myqueue").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(post_handler)
+                .service(queue_path_handler),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/000000000000/myqueue")
+            .set_payload("Action=SendMessage&MessageBody=hello")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(queue.lock().await.counts().visible, 1);
+
+        // A QueueUrl naming a different queue is rejected
+        let req = test::TestRequest::post()
+            .uri("/000000000000/myqueue")
+            .set_payload(
+                "Action=SendMessage&MessageBody=hello&QueueUrl=http://localhost:9090/other",
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_bare_queue_name_as_queue_url() {
+        let state = AppState::for_tests();
+        let queue = state.add_test_queue("myqueue").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(post_handler),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload("Action=SendMessage&QueueUrl=myqueue&MessageBody=hello")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(queue.lock().await.counts().visible, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_gzip_request_and_response() {
+        use std::io::{Read, Write};
+
+        let state = AppState::for_tests();
+        let queue = state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(post_handler)
+                .wrap(actix_web::middleware::Compress::default()),
+        )
+        .await;
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(b"Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=zipped")
+            .unwrap();
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("Content-Encoding", "gzip"))
+            .insert_header(("Accept-Encoding", "gzip"))
+            .set_payload(encoder.finish().unwrap())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "gzip");
+        assert_eq!(queue.lock().await.counts().visible, 1);
+
+        let body = test::read_body(resp).await;
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert!(decoded.contains("<SendMessageResponse"));
+    }
+
+    #[actix_web::test]
+    async fn test_content_type_matches_protocol() {
+        let state = AppState::for_tests();
+        state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(post_handler),
+        )
+        .await;
+
+        let content_type = |resp: &actix_web::dev::ServiceResponse| {
+            resp.headers()
+                .get(header::CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_owned()
+        };
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload("Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(content_type(&resp), "text/xml");
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .set_payload("Action=SendMessage&QueueUrl=http://localhost:9090/missing&MessageBody=hi")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+        assert_eq!(content_type(&resp), "text/xml");
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("X-Amz-Target", "AmazonSQS.SendMessage"))
+            .set_payload(r#"{"QueueUrl":"http://localhost:9090/q","MessageBody":"hi"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(content_type(&resp), "application/x-amz-json-1.0");
+    }
+
+    #[actix_web::test]
+    async fn test_missing_and_invalid_action() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests()))
+                .service(post_handler),
+        )
+        .await;
+
+        for (payload, code) in [
+            ("", "MissingAction"),
+            ("Action=", "MissingAction"),
+            ("QueueUrl=http://localhost:9090/q", "MissingAction"),
+            ("Action=Frobnicate", "InvalidAction"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/")
+                .set_payload(payload)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/xml"
+            );
+            let body = test::read_body(resp).await;
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains(&format!("<Code>{}</Code>", code)), "{}", body);
+        }
+
+        for (target, code) in [
+            ("AmazonSQS.", "MissingAction"),
+            ("AmazonSQS.Frobnicate", "InvalidAction"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("x-amz-target", target))
+                .set_payload("{}")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            assert_eq!(
+                resp.headers().get("x-amzn-query-error").unwrap(),
+                &format!("{};Sender", code)
+            );
+            let body: serde_json::Value =
+                serde_json::from_slice(&test::read_body(resp).await).unwrap();
+            assert_eq!(body["__type"], format!("com.amazonaws.sqs#{}", code));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_action_in_query_string() {
+        let state = AppState::for_tests_with_db().await;
+        let queue = state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(post_handler)
+                .service(queue_path_handler),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/?Action=ListQueues&Version=2012-11-05")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("<ListQueuesResponse"));
+
+        // Parameters split between the URL and the body; the body wins where both have one
+        let req = test::TestRequest::post()
+            .uri("/000000000000/q?Action=SendMessage&MessageBody=from-query")
+            .set_payload("MessageBody=from-body")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let queue = queue.lock().await;
+        let sent: Vec<_> = queue.messages().collect();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message_body, "from-body");
+    }
+
+    #[actix_web::test]
+    async fn test_actions_list_matches_routes() {
+        let app_state = web::Data::new(AppState::for_tests());
+        for action in ACTIONS {
+            let resp = route(app_state.clone(), action, &web::Bytes::new(), false).await;
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            assert!(
+                !String::from_utf8_lossy(&body).contains("<Code>InvalidAction</Code>"),
+                "{}",
+                action
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_undecodable_target_header() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests()))
+                .service(post_handler),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header((
+                "x-amz-target",
+                header::HeaderValue::from_bytes(b"AmazonSQS.\xffSend").unwrap(),
+            ))
+            .set_payload("{}")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,94 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PurgeQueueParams {
+    queue_url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PurgeQueueResponse {
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// Delete every message in a queue. As in AWS, a queue can only be purged once a minute.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<PurgeQueueParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let queue_name = match helpers::extract_queue_name_from_url(&params.queue_url) {
+        Some(name) => name,
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
+    };
+
+    let queue = match app_state.get_queue(&queue_name).await {
+        Some(q) => q,
+        None => return SqsError::NonExistentQueue(queue_name).error_response(),
+    };
+
+    if !queue.lock().await.purge() {
+        return SqsError::PurgeQueueInProgress(queue_name).error_response();
+    }
+
+    let response = PurgeQueueResponse {
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_second_purge_within_cooldown_fails() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+
+        let payload = web::Bytes::from("Action=PurgeQueue&QueueUrl=http://localhost:9090/q");
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(queue.lock().await.counts().visible, 0);
+
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body)
+            .contains("<Code>AWS.SimpleQueueService.PurgeQueueInProgress</Code>"));
+
+        queue.lock().await.purge_cooldown = std::time::Duration::ZERO;
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -1,8 +1,9 @@
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::AppState;
+use crate::error::SqsError;
+use crate::{engine::ReceiveOptions, AppState};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -13,8 +14,15 @@ struct ReceiveMessageParams {
     #[serde(default)]
     wait_time_seconds: u32,
     visibility_timeout: Option<u32>,
+    /// FIFO only: retries with the same id return the same messages
+    receive_request_attempt_id: Option<String>,
 }
 
+/// Mock-only attribute with the seconds left until a received message becomes visible
+/// again. Only returned when asked for by name and the server runs with `--admin`; `All`
+/// leaves it out, so responses match AWS's.
+pub const VISIBILITY_REMAINING_ATTRIBUTE: &str = "X-Mock-VisibilitySecondsRemaining";
+
 fn default_max_number() -> u32 {
     1
 }
@@ -38,10 +46,19 @@ struct ReceiveMessageResult {
 struct MessageXml {
     message_id: String,
     receipt_handle: String,
+    #[serde(rename = "MD5OfBody")]
     md5_of_body: String,
+    /// Escaped by the serializer; the MD5 is of the raw body
     body: String,
     #[serde(rename = "Attribute")]
     attributes: Vec<AttributeXml>,
+    #[serde(
+        rename = "MD5OfMessageAttributes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    md5_of_message_attributes: Option<String>,
+    #[serde(rename = "MessageAttribute")]
+    message_attributes: Vec<super::helpers::MessageAttributeXml>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -64,48 +81,78 @@ pub async fn process(
 ) -> HttpResponse {
     let params = match super::struct_from_url_encode::<ReceiveMessageParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
 
     let queue_name = match super::helpers::extract_queue_name_from_url(&params.queue_url) {
         Some(name) => name,
-        None => {
-            return HttpResponse::BadRequest()
-                .body("Invalid QueueUrl: could not extract queue name")
-        }
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
     };
 
-    let max = params.max_number_of_messages.clamp(1, 10);
-    let deadline = tokio::time::Instant::now()
-        + tokio::time::Duration::from_secs(params.wait_time_seconds as u64);
+    // Newer SDKs ask for attributes with MessageSystemAttributeName.N, older ones with
+    // AttributeName.N
+    let mut attribute_names = super::helpers::extract_indexed_values(payload, "AttributeName");
+    attribute_names.extend(super::helpers::extract_indexed_values(
+        payload,
+        "MessageSystemAttributeName",
+    ));
+    let message_attribute_names =
+        super::helpers::extract_indexed_values(payload, "MessageAttributeName");
 
-    let messages = loop {
-        {
-            let mut writer = app_state.queues.lock().await;
-            match writer.get_mut(&queue_name) {
-                Some(queue) => {
-                    let received = queue.receive(max, params.visibility_timeout);
-                    if !received.is_empty() {
-                        break received;
-                    }
-                }
-                None => {
-                    return HttpResponse::BadRequest().body(format!(
-                        "AWS.SimpleQueueService.NonExistentQueue; Queue: {}",
-                        queue_name
-                    ))
-                }
-            }
-            // Lock is dropped here before sleeping
-        }
-
-        if tokio::time::Instant::now() >= deadline {
-            break Vec::new();
-        }
-        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    let options = ReceiveOptions {
+        max_number_of_messages: params.max_number_of_messages,
+        visibility_timeout: params.visibility_timeout,
+        wait_time: Duration::from_secs(params.wait_time_seconds as u64),
+        receive_request_attempt_id: params.receive_request_attempt_id,
     };
+    let account_id = app_state.account_id.clone();
+    let clock = app_state.clock.clone();
+    let debug_attributes = app_state.debug_attributes;
+    let receive_latency = app_state.receive_latency.clone();
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let received = engine.receive(&queue_name, &options).await;
+    // After the fetch, so the delay looks like a slow response rather than a slow queue
+    if let Some(latency) = receive_latency {
+        tokio::time::sleep(latency.sample()).await;
+    }
+    match received {
+        Ok(messages) => build_response(
+            &messages,
+            &attribute_names,
+            &message_attribute_names,
+            &account_id,
+            clock.now(),
+            debug_attributes,
+        ),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Whether a message attribute was asked for by `MessageAttributeName.N`, which takes
+/// exact names, `All`/`.*`, or prefixes such as `bar.*`.
+fn wants_message_attribute(requested: &[String], name: &str) -> bool {
+    super::helpers::wants_all(requested)
+        || requested.iter().any(|r| {
+            r == name
+                || r.strip_suffix(".*")
+                    .is_some_and(|prefix| name.starts_with(&format!("{}.", prefix)))
+        })
+}
+
+fn build_response(
+    messages: &[crate::queue::Message],
+    attribute_names: &[String],
+    message_attribute_names: &[String],
+    sender_id: &str,
+    now: std::time::Instant,
+    debug_attributes: bool,
+) -> HttpResponse {
+    let wants_all = super::helpers::wants_all(attribute_names);
+    let wants = |name: &str| wants_all || attribute_names.iter().any(|n| n == name);
+    let wants_visibility_remaining = debug_attributes
+        && attribute_names
+            .iter()
+            .any(|n| n == VISIBILITY_REMAINING_ATTRIBUTE);
 
     let xml_messages: Vec<MessageXml> = messages
         .iter()
@@ -117,7 +164,7 @@ pub async fn process(
             });
             if let Some(first) = msg.first_received_at {
                 // Convert monotonic Instant to wall-clock time
-                let elapsed_since_first = std::time::Instant::now() - first;
+                let elapsed_since_first = now - first;
                 let first_receive_time = std::time::SystemTime::now() - elapsed_since_first;
                 attrs.push(AttributeXml {
                     name: "ApproximateFirstReceiveTimestamp".to_string(),
@@ -129,34 +176,541 @@ pub async fn process(
                 });
             }
 
+            if wants_visibility_remaining {
+                // Rounded, so a message received with VisibilityTimeout=N reports N
+                let remaining = msg.visible_at.saturating_duration_since(now);
+                attrs.push(AttributeXml {
+                    name: VISIBILITY_REMAINING_ATTRIBUTE.to_string(),
+                    value: ((remaining.as_millis() + 500) / 1000).to_string(),
+                });
+            }
+
+            if wants("SenderId") {
+                attrs.push(AttributeXml {
+                    name: "SenderId".to_string(),
+                    value: sender_id.to_string(),
+                });
+            }
+
+            // System attributes come back as plain attributes, e.g. AWSTraceHeader
+            for attribute in &msg.message_system_attributes {
+                if let crate::queue::MessageAttributeValue::String(value) = &attribute.value {
+                    if wants(&attribute.name) {
+                        attrs.push(AttributeXml {
+                            name: attribute.name.clone(),
+                            value: value.clone(),
+                        });
+                    }
+                }
+            }
+
+            // Only set on messages in FIFO queues
+            let fifo_attrs = [
+                ("MessageGroupId", &msg.message_group_id),
+                ("MessageDeduplicationId", &msg.message_deduplication_id),
+                ("SequenceNumber", &msg.sequence_number),
+            ];
+            for (name, value) in fifo_attrs {
+                if let Some(value) = value {
+                    if wants(name) {
+                        attrs.push(AttributeXml {
+                            name: name.to_string(),
+                            value: value.clone(),
+                        });
+                    }
+                }
+            }
+
+            let message_attributes: Vec<crate::queue::MessageAttribute> = msg
+                .message_attributes
+                .iter()
+                .filter(|a| wants_message_attribute(message_attribute_names, &a.name))
+                .cloned()
+                .collect();
+
             MessageXml {
                 message_id: msg.id.clone(),
                 receipt_handle: msg.receipt_handle.clone().unwrap_or_default(),
                 md5_of_body: super::helpers::compute_md5(&msg.message_body),
                 body: msg.message_body.clone(),
                 attributes: attrs,
+                md5_of_message_attributes: (!message_attributes.is_empty())
+                    .then(|| super::helpers::compute_message_attributes_md5(&message_attributes)),
+                message_attributes: message_attributes.iter().map(Into::into).collect(),
             }
         })
         .collect();
 
-    if xml_messages.is_empty() {
-        return HttpResponse::Ok()
-            .body("<ReceiveMessageResponse><ReceiveMessageResult/></ReceiveMessageResponse>");
-    }
-
     let response = ReceiveMessageResponse {
         receive_message_result: ReceiveMessageResult {
             messages: xml_messages,
         },
         response_metadata: ResponseMetadata {
-            request_id: super::helpers::generate_random_uuid4(),
+            request_id: super::helpers::request_id(),
         },
     };
 
-    match quick_xml::se::to_string(&response) {
+    match super::helpers::to_xml(&response) {
         Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_short_poll_on_empty_queue_returns_promptly() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = web::Bytes::from("Action=ReceiveMessage&QueueUrl=http://localhost:9090/q");
+        let started = std::time::Instant::now();
+        let resp = process(state, &payload, false).await;
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<ReceiveMessageResult/>"), "{}", body);
+        assert!(!body.contains("<Message>"));
+    }
+
+    #[tokio::test]
+    async fn test_empty_receive_has_request_id() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = web::Bytes::from("Action=ReceiveMessage&QueueUrl=http://localhost:9090/q");
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(&format!(
+            r#"<ReceiveMessageResponse xmlns="{}">"#,
+            super::super::helpers::XML_NAMESPACE
+        )));
+        assert!(body.contains("<ResponseMetadata><RequestId>"));
+    }
+
+    #[tokio::test]
+    async fn test_fifo_group_id_round_trip() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q.fifo").await.lock().await.is_fifo = true;
+
+        let payload = web::Bytes::from(
+            "Action=SendMessage&QueueUrl=http://localhost:9090/q.fifo&MessageBody=hi&MessageGroupId=g1&MessageDeduplicationId=d1",
+        );
+        let resp = super::super::send_message::process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q.fifo&AttributeName.1=MessageGroupId&AttributeName.2=SequenceNumber",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Name>MessageGroupId</Name><Value>g1</Value>"));
+        assert!(body.contains("<Name>SequenceNumber</Name>"));
+        // Not requested, so not returned
+        assert!(!body.contains("MessageDeduplicationId"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_wakes_blocked_fifo_receiver() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q.fifo").await;
+        {
+            let mut queue = queue.lock().await;
+            queue.is_fifo = true;
+            for id in ["1", "2"] {
+                let mut msg = crate::queue::Message::new(id.to_owned(), format!("body{}", id));
+                msg.message_group_id = Some("g".to_owned());
+                queue.push(msg);
+            }
+        }
+
+        let held = queue.lock().await.receive(1, None);
+        let handle = held[0].receipt_handle.clone().unwrap();
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q.fifo&WaitTimeSeconds=10",
+        );
+        let deleter = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let payload = serde_urlencoded::to_string([
+                ("Action", "DeleteMessage"),
+                ("QueueUrl", "http://localhost:9090/q.fifo"),
+                ("ReceiptHandle", handle.as_str()),
+            ])
+            .unwrap();
+            let resp = super::super::delete_message::process(
+                state.clone(),
+                &web::Bytes::from(payload),
+                false,
+            )
+            .await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+            std::time::Instant::now()
+        };
+
+        let (resp, deleted_at) = tokio::join!(process(state.clone(), &payload, false), deleter);
+        assert!(deleted_at.elapsed() < Duration::from_millis(200));
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Body>body2</Body>"));
+    }
+
+    #[tokio::test]
+    async fn test_long_poll_wakes_on_send() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&WaitTimeSeconds=10",
+        );
+        let sender = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            queue.lock().await.push(crate::queue::Message::new(
+                "1".to_owned(),
+                "hello".to_owned(),
+            ));
+            std::time::Instant::now()
+        };
+
+        let (resp, sent_at) = tokio::join!(process(state.clone(), &payload, false), sender);
+        assert!(sent_at.elapsed() < Duration::from_millis(200));
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Body>hello</Body>"));
+    }
+
+    #[tokio::test]
+    async fn test_long_polls_on_different_queues_are_independent() {
+        let state = Arc::new(AppState::for_tests());
+        let queue_a = state.add_test_queue("a").await;
+        let queue_b = state.add_test_queue("b").await;
+
+        let poll = |name: &str| {
+            web::Bytes::from(format!(
+                "Action=ReceiveMessage&QueueUrl=http://localhost:9090/{}&WaitTimeSeconds=5",
+                name
+            ))
+        };
+        let (payload_a, payload_b) = (poll("a"), poll("b"));
+        let started = std::time::Instant::now();
+
+        let receive_a = async {
+            let resp = process(state.clone(), &payload_a, false).await;
+            (resp, started.elapsed())
+        };
+        let receive_b = async {
+            let resp = process(state.clone(), &payload_b, false).await;
+            (resp, started.elapsed())
+        };
+        let sender = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            queue_b.lock().await.push(crate::queue::Message::new(
+                "1".to_owned(),
+                "for b".to_owned(),
+            ));
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            queue_a.lock().await.push(crate::queue::Message::new(
+                "2".to_owned(),
+                "for a".to_owned(),
+            ));
+        };
+
+        let ((resp_a, elapsed_a), (resp_b, elapsed_b), ()) =
+            tokio::join!(receive_a, receive_b, sender);
+
+        // b returns as soon as its message arrives, while a is still waiting
+        assert!(elapsed_b < Duration::from_millis(250), "{:?}", elapsed_b);
+        assert!(elapsed_a >= Duration::from_millis(300), "{:?}", elapsed_a);
+        assert!(elapsed_a < Duration::from_millis(1000), "{:?}", elapsed_a);
+
+        let body = actix_web::body::to_bytes(resp_a.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Body>for a</Body>"));
+        let body = actix_web::body::to_bytes(resp_b.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Body>for b</Body>"));
+    }
+
+    #[tokio::test]
+    async fn test_binary_message_attribute_round_trip() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", "hi"),
+            ("MessageAttribute.1.Name", "blob"),
+            ("MessageAttribute.1.Value.DataType", "Binary"),
+            ("MessageAttribute.1.Value.BinaryValue", "AAEC/w=="),
+            ("MessageAttribute.2.Name", "s"),
+            ("MessageAttribute.2.Value.DataType", "String"),
+            ("MessageAttribute.2.Value.StringValue", "hi"),
+        ])
+        .unwrap();
+        let resp =
+            super::super::send_message::process(state.clone(), &web::Bytes::from(payload), false)
+                .await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let md5 =
+            "<MD5OfMessageAttributes>a7aeba3079ca36141c391e5d738dce6a</MD5OfMessageAttributes>";
+        assert!(String::from_utf8_lossy(&body).contains(md5));
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&MessageAttributeName.1=All",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(md5));
+        assert!(body.contains("<MessageAttribute><Name>blob</Name><Value><BinaryValue>AAEC/w==</BinaryValue><DataType>Binary</DataType></Value></MessageAttribute>"));
+        assert!(body.contains("<MessageAttribute><Name>s</Name><Value><StringValue>hi</StringValue><DataType>String</DataType></Value></MessageAttribute>"));
+    }
+
+    #[test]
+    fn test_wants_message_attribute() {
+        let requested = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(wants_message_attribute(&requested(&["All"]), "a"));
+        assert!(wants_message_attribute(&requested(&[".*"]), "a"));
+        assert!(wants_message_attribute(&requested(&["a"]), "a"));
+        assert!(wants_message_attribute(&requested(&["foo.*"]), "foo.bar"));
+        assert!(!wants_message_attribute(&requested(&["foo.*"]), "foobar"));
+        assert!(!wants_message_attribute(&[], "a"));
+    }
+
+    #[tokio::test]
+    async fn test_max_number_of_messages_out_of_range() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+
+        for max in [0, 11] {
+            let payload = web::Bytes::from(format!(
+                "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&MaxNumberOfMessages={}",
+                max
+            ));
+            let resp = process(state.clone(), &payload, false).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            let body = String::from_utf8_lossy(&body);
+            assert!(
+                body.contains("<Code>InvalidParameterValue</Code>"),
+                "{}",
+                body
+            );
+            assert!(body.contains(&format!(
+                "Value {} for parameter MaxNumberOfMessages is invalid. Reason: must be between 1 and 10.",
+                max
+            )));
         }
+        // Nothing was received
+        assert_eq!(queue.lock().await.counts().visible, 1);
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&MaxNumberOfMessages=10",
+        );
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_sender_id_is_account_id() {
+        let state = Arc::new(AppState {
+            account_id: "123456789012".to_string(),
+            ..AppState::for_tests()
+        });
+        state
+            .add_test_queue("q")
+            .await
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&AttributeName.1=SenderId",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Name>SenderId</Name><Value>123456789012</Value>"));
+        assert!(!body.contains("SequenceNumber"));
+    }
+
+    #[tokio::test]
+    async fn test_body_with_markup_round_trips() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+        let original = "a ]]> b & <c attr=\"'x'\"> \t tab";
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", original),
+        ])
+        .unwrap();
+        let resp =
+            super::super::send_message::process(state.clone(), &web::Bytes::from(payload), false)
+                .await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from("Action=ReceiveMessage&QueueUrl=http://localhost:9090/q");
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml = String::from_utf8_lossy(&body);
+
+        // Walk the whole document so any malformed markup fails the test
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let (mut path, mut decoded, mut md5) = (Vec::new(), None, None);
+        loop {
+            match reader.read_event().unwrap() {
+                quick_xml::events::Event::Start(e) => {
+                    path.push(String::from_utf8_lossy(e.name().as_ref()).into_owned())
+                }
+                quick_xml::events::Event::End(_) => {
+                    path.pop();
+                }
+                quick_xml::events::Event::Text(text) => {
+                    let text = text.unescape().unwrap().into_owned();
+                    match path.last().map(String::as_str) {
+                        Some("Body") => decoded = Some(text),
+                        Some("MD5OfBody") => md5 = Some(text),
+                        _ => {}
+                    }
+                }
+                quick_xml::events::Event::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(decoded.as_deref(), Some(original));
+        assert_eq!(md5, Some(super::super::helpers::compute_md5(original)));
+    }
+
+    #[tokio::test]
+    async fn test_dot_star_asks_for_everything() {
+        let state = Arc::new(AppState::for_tests());
+        let mut message = crate::queue::Message::new("1".to_owned(), "hi".to_owned());
+        message.message_attributes = vec![crate::queue::MessageAttribute {
+            name: "color".to_owned(),
+            data_type: "String".to_owned(),
+            value: crate::queue::MessageAttributeValue::String("blue".to_owned()),
+        }];
+        state.add_test_queue("q").await.lock().await.push(message);
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&AttributeName.1=.*&MessageAttributeName.1=.*",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Name>SenderId</Name>"));
+        assert!(body.contains("<Name>color</Name>"));
+    }
+
+    #[tokio::test]
+    async fn test_over_in_flight_limit() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        {
+            let mut queue = queue.lock().await;
+            queue.in_flight_limit = 1;
+            for id in ["1", "2"] {
+                queue.push(crate::queue::Message::new(id.to_owned(), "hi".to_owned()));
+            }
+        }
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&MaxNumberOfMessages=10",
+        );
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body).matches("<Message>").count(),
+            1
+        );
+
+        // Fails straight away rather than long polling for room
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&WaitTimeSeconds=20",
+        );
+        let started = std::time::Instant::now();
+        let resp = process(state, &payload, false).await;
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>OverLimit</Code>"));
+    }
+
+    #[tokio::test]
+    async fn test_receive_latency() {
+        use crate::chaos::{LatencyDistribution, ReceiveLatency};
+
+        let state = AppState {
+            receive_latency: Some(ReceiveLatency::new(LatencyDistribution::Fixed(150), None)),
+            ..AppState::for_tests()
+        };
+        let queue = state.add_test_queue("q").await;
+        queue
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+
+        let payload = web::Bytes::from("Action=ReceiveMessage&QueueUrl=http://localhost:9090/q");
+        let started = std::time::Instant::now();
+        let resp = process(Arc::new(state), &payload, false).await;
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Body>hi</Body>"));
+    }
+
+    #[tokio::test]
+    async fn test_visibility_seconds_remaining() {
+        let receive = |debug_attributes: bool, attribute_name: &'static str| async move {
+            let state = AppState {
+                debug_attributes,
+                ..AppState::for_tests()
+            };
+            let queue = state.add_test_queue("q").await;
+            queue
+                .lock()
+                .await
+                .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+            let payload = web::Bytes::from(format!(
+                "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&VisibilityTimeout=30&AttributeName.1={}",
+                attribute_name
+            ));
+            let resp = process(Arc::new(state), &payload, false).await;
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            String::from_utf8_lossy(&body).into_owned()
+        };
+
+        let body = receive(true, VISIBILITY_REMAINING_ATTRIBUTE).await;
+        let remaining: u64 = body
+            .split("<Name>X-Mock-VisibilitySecondsRemaining</Name><Value>")
+            .nth(1)
+            .and_then(|rest| rest.split("</Value>").next())
+            .unwrap_or_else(|| panic!("{}", body))
+            .parse()
+            .unwrap();
+        assert!((29..=30).contains(&remaining), "{}", remaining);
+
+        // Not AWS's, so left out without --admin and of All
+        let body = receive(false, VISIBILITY_REMAINING_ATTRIBUTE).await;
+        assert!(body.contains("<Body>hi</Body>"), "{}", body);
+        assert!(!body.contains(VISIBILITY_REMAINING_ATTRIBUTE), "{}", body);
+        let body = receive(true, "All").await;
+        assert!(body.contains("<Name>SenderId</Name>"), "{}", body);
+        assert!(!body.contains(VISIBILITY_REMAINING_ATTRIBUTE), "{}", body);
     }
 }
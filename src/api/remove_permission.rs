@@ -0,0 +1,100 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RemovePermissionParams {
+    queue_url: String,
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct RemovePermissionResponse {
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// Revoke a permission added with AddPermission. Permissions aren't stored, so any
+/// well-formed label on an existing queue succeeds.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<RemovePermissionParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let queue_name = match helpers::extract_queue_name_from_url(&params.queue_url) {
+        Some(name) => name,
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
+    };
+
+    let Some(label) = params.label else {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter Label.".to_string(),
+        )
+        .error_response();
+    };
+    if !helpers::is_valid_permission_label(&label) {
+        return SqsError::InvalidParameterValue(format!(
+            "Value {} for parameter Label is invalid. Reason: Must be 1 to 80 alphanumeric characters, hyphens or underscores.",
+            label
+        ))
+        .error_response();
+    }
+
+    if app_state.get_queue(&queue_name).await.is_none() {
+        return SqsError::NonExistentQueue(queue_name).error_response();
+    }
+
+    let response = RemovePermissionResponse {
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_remove_permission() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = web::Bytes::from(
+            "Action=RemovePermission&QueueUrl=http://localhost:9090/q&Label=consumers",
+        );
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<RemovePermissionResponse"));
+
+        let payload = web::Bytes::from("Action=RemovePermission&QueueUrl=http://localhost:9090/q");
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>MissingParameter</Code>"));
+    }
+}
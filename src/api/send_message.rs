@@ -1,9 +1,9 @@
 use actix_web::{web, HttpResponse};
-use regex::RegexBuilder;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
 
 use super::helpers;
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -11,14 +11,12 @@ use crate::AppState;
 struct SendMessageParams {
     queue_url: String,
     message_body: String,
-    #[allow(dead_code)]
     delay_seconds: Option<i32>,
+    message_group_id: Option<String>,
+    message_deduplication_id: Option<String>,
+    /// `MessageAttribute.N.*` and `MessageSystemAttribute.N.*`
     #[serde(flatten)]
     extra: HashMap<String, String>,
-
-    #[serde(skip)]
-    /// This will be populated when you call populate attributes method
-    attributes: Option<Vec<helpers::ParamValues>>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -32,7 +30,21 @@ struct SendMessageResponse {
 #[serde(rename_all = "PascalCase")]
 struct SendMessageResult {
     message_id: String,
+    #[serde(rename = "MD5OfMessageBody")]
     md5_of_message_body: String,
+    #[serde(
+        rename = "MD5OfMessageAttributes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    md5_of_message_attributes: Option<String>,
+    #[serde(
+        rename = "MD5OfMessageSystemAttributes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    md5_of_message_system_attributes: Option<String>,
+    /// FIFO queues only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence_number: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -41,70 +53,384 @@ struct ResponseMetadata {
     request_id: String,
 }
 
-impl SendMessageParams {
-    /// Populate the attributes from the extra hashmap
-    fn populate_attributes(&mut self) {
-        let re = RegexBuilder::new(r"^Attribute\.(\d+)\.(.+)$")
-            .case_insensitive(true)
-            .build()
-            .unwrap();
-
-        self.attributes = helpers::extract_from_extra(re, self.extra.clone());
-    }
-}
-
 pub async fn process(
     app_state: Arc<AppState>,
     payload: &web::Bytes,
     _is_json: bool,
 ) -> HttpResponse {
-    let mut payload = match super::struct_from_url_encode::<SendMessageParams>(payload) {
+    let payload = match super::struct_from_url_encode::<SendMessageParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
-    payload.populate_attributes();
 
     let queue_name = match helpers::extract_queue_name_from_url(&payload.queue_url) {
         Some(name) => name,
-        None => {
-            return HttpResponse::BadRequest()
-                .body("Invalid QueueUrl: could not extract queue name")
-        }
+        None => return SqsError::InvalidAddress(payload.queue_url.clone()).error_response(),
     };
 
-    let msg_id = helpers::generate_random_uuid4();
-    let mut writer = app_state.queues.lock().await;
-    match writer.get_mut(&queue_name) {
-        Some(queue) => {
-            queue.push(crate::queue::Message::new(
-                msg_id.clone(),
-                payload.message_body.clone(),
-            ));
-        }
-        None => {
-            return HttpResponse::BadRequest().body(format!(
-                "AWS.SimpleQueueService.NonExistentQueue; see the SQS docs. Queue: {}",
-                queue_name
-            ))
+    // The engine checks the upper bound
+    let delay_seconds = match payload.delay_seconds {
+        Some(delay) if delay < 0 => {
+            return SqsError::InvalidParameterValue(format!(
+            "Value {} for parameter DelaySeconds is invalid. Reason: must be between 0 and 900.",
+            delay
+        ))
+            .error_response()
         }
-    }
+        delay => delay.map(|d| d as u32),
+    };
+
+    let message_attributes = match helpers::parse_message_attributes(&payload.extra) {
+        Ok(attrs) => attrs,
+        Err(e) => return SqsError::InvalidParameterValue(e).error_response(),
+    };
+    let md5_of_message_attributes = (!message_attributes.is_empty())
+        .then(|| helpers::compute_message_attributes_md5(&message_attributes));
+    let message_system_attributes = match helpers::parse_message_system_attributes(&payload.extra) {
+        Ok(attrs) => attrs,
+        Err(e) => return SqsError::InvalidParameterValue(e).error_response(),
+    };
+    let md5_of_message_system_attributes = (!message_system_attributes.is_empty())
+        .then(|| helpers::compute_message_attributes_md5(&message_system_attributes));
+
+    let mut message = crate::queue::Message::new(
+        helpers::generate_random_uuid4(),
+        payload.message_body.clone(),
+    );
+    message.message_attributes = message_attributes;
+    message.message_system_attributes = message_system_attributes;
+    message.message_group_id = payload.message_group_id.clone();
+    message.message_deduplication_id = payload.message_deduplication_id.clone();
+
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let stored = match engine.send(&queue_name, message, delay_seconds).await {
+        Ok(stored) => stored,
+        Err(e) => return e.error_response(),
+    };
 
     let response = SendMessageResponse {
         send_message_result: SendMessageResult {
-            message_id: msg_id.clone(),
+            message_id: stored.id,
             md5_of_message_body: helpers::compute_md5(payload.message_body.as_str()),
+            md5_of_message_attributes,
+            md5_of_message_system_attributes,
+            sequence_number: stored.sequence_number,
         },
         reponse_metadata: ResponseMetadata {
-            request_id: helpers::generate_random_uuid4(),
+            request_id: helpers::request_id(),
         },
     };
 
-    match quick_xml::se::to_string(&response) {
+    match helpers::to_xml(&response) {
         Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    #[tokio::test]
+    async fn test_send_message_response_has_namespace() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = web::Bytes::from(
+            "Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hello",
+        );
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(&format!(
+            r#"<SendMessageResponse xmlns="{}">"#,
+            helpers::XML_NAMESPACE
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_send_message_with_delay() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let payload = web::Bytes::from(
+            "Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hello&DelaySeconds=60",
+        );
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let queue = queue.lock().await;
+        assert_eq!(queue.counts().visible, 0);
+        assert_eq!(queue.counts().delayed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_delay_applies_without_message_delay() {
+        let clock = Arc::new(crate::clock::MockClock::default());
+        let state = Arc::new(AppState {
+            clock: clock.clone(),
+            ..AppState::for_tests_with_db().await
+        });
+        let payload = web::Bytes::from(
+            "Action=CreateQueue&QueueName=q&Attribute.1.Name=DelaySeconds&Attribute.1.Value=2",
+        );
+        let resp = super::super::create_queue::process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let send = |extra: &str| {
+            web::Bytes::from(format!(
+                "Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hello{}",
+                extra
+            ))
+        };
+        process(state.clone(), &send(""), false).await;
+        // An explicit DelaySeconds, even 0, overrides the queue's
+        process(state.clone(), &send("&DelaySeconds=0"), false).await;
+
+        let queue = state.get_queue("q").await.unwrap();
+        let counts = queue.lock().await.counts();
+        assert_eq!((counts.visible, counts.delayed), (1, 1));
+
+        clock.advance(std::time::Duration::from_secs(2));
+        assert_eq!(queue.lock().await.counts().visible, 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_message_throttled() {
+        let clock = Arc::new(crate::clock::MockClock::default());
+        let state = Arc::new(AppState {
+            clock: clock.clone(),
+            ..AppState::for_tests()
+        });
+        let queue = state.add_test_queue("q").await;
+        queue.lock().await.rate_limiter = Some(crate::queue::RateLimiter::new(2));
+
+        let payload =
+            web::Bytes::from("Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi");
+        for _ in 0..2 {
+            let resp = process(state.clone(), &payload, false).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        }
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+
+        // Half a second refills one of the two tokens
+        clock.advance(std::time::Duration::from_millis(500));
+        let resp = process(state.clone(), &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_number_only_for_fifo() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+        state.add_test_queue("q.fifo").await.lock().await.is_fifo = true;
+
+        let payload =
+            web::Bytes::from("Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi");
+        let resp = process(state.clone(), &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<MessageId>"));
+        assert!(!body.contains("SequenceNumber"));
+
+        let payload = web::Bytes::from(
+            "Action=SendMessage&QueueUrl=http://localhost:9090/q.fifo&MessageBody=hi&MessageGroupId=g",
+        );
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<SequenceNumber>00000000000000000001</SequenceNumber>"));
+    }
+
+    #[tokio::test]
+    async fn test_fifo_rejects_per_message_delay() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q.fifo").await;
+        queue.lock().await.is_fifo = true;
+
+        let payload = web::Bytes::from(
+            "Action=SendMessage&QueueUrl=http://localhost:9090/q.fifo&MessageBody=hi&MessageGroupId=g&DelaySeconds=5",
+        );
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidParameterValue</Code>"));
+        assert_eq!(queue.lock().await.counts(), Default::default());
+    }
+
+    #[tokio::test]
+    async fn test_trace_header_round_trip() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let trace_header =
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", "hi"),
+            ("MessageSystemAttribute.1.Name", "AWSTraceHeader"),
+            ("MessageSystemAttribute.1.Value.DataType", "String"),
+            ("MessageSystemAttribute.1.Value.StringValue", trace_header),
+        ])
+        .unwrap();
+        let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        // Not asked for, not returned
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&VisibilityTimeout=0",
+        );
+        let resp = super::super::receive_message::process(state.clone(), &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("AWSTraceHeader"));
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&MessageSystemAttributeName.1=AWSTraceHeader",
+        );
+        let resp = super::super::receive_message::process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let attribute = format!("<Name>AWSTraceHeader</Name><Value>{}</Value>", trace_header);
+        assert!(String::from_utf8_lossy(&body).contains(&attribute));
+    }
+
+    #[tokio::test]
+    async fn test_message_and_system_attributes_are_kept_apart() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", "hi"),
+            ("MessageAttribute.1.Name", "color"),
+            ("MessageAttribute.1.Value.DataType", "String"),
+            ("MessageAttribute.1.Value.StringValue", "blue"),
+            ("MessageSystemAttribute.1.Name", "AWSTraceHeader"),
+            ("MessageSystemAttribute.1.Value.DataType", "String"),
+            ("MessageSystemAttribute.1.Value.StringValue", "Root=1-abc"),
+        ])
+        .unwrap();
+        let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<MD5OfMessageAttributes>"));
+        assert!(body.contains("<MD5OfMessageSystemAttributes>"));
+
+        let queue = queue.lock().await;
+        let message = queue.messages().next().unwrap();
+        assert_eq!(message.message_attributes.len(), 1);
+        assert_eq!(message.message_attributes[0].name, "color");
+        assert_eq!(message.message_system_attributes.len(), 1);
+        assert_eq!(message.message_system_attributes[0].name, "AWSTraceHeader");
+        drop(queue);
+
+        let payload = web::Bytes::from(
+            "Action=ReceiveMessage&QueueUrl=http://localhost:9090/q&AttributeName.1=AWSTraceHeader",
+        );
+        let resp = super::super::receive_message::process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body)
+            .contains("<Name>AWSTraceHeader</Name><Value>Root=1-abc</Value>"));
+    }
+
+    #[tokio::test]
+    async fn test_md5_of_message_system_attributes_only_when_present() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", "hi"),
+            ("MessageSystemAttribute.1.Name", "AWSTraceHeader"),
+            ("MessageSystemAttribute.1.Value.DataType", "String"),
+            (
+                "MessageSystemAttribute.1.Value.StringValue",
+                "Root=1-5759e988-bd862e3fe1be46a994272793",
+            ),
+        ])
+        .unwrap();
+        let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(
+            "<MD5OfMessageSystemAttributes>62a56dd927315f2b2e12832b84617ea5</MD5OfMessageSystemAttributes>"
+        ));
+        assert!(!body.contains("<MD5OfMessageAttributes>"));
+
+        let payload =
+            web::Bytes::from("Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi");
+        let resp = process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("MD5OfMessageSystemAttributes"));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unknown_system_attribute() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", "hi"),
+            ("MessageSystemAttribute.1.Name", "color"),
+            ("MessageSystemAttribute.1.Value.DataType", "String"),
+            ("MessageSystemAttribute.1.Value.StringValue", "blue"),
+        ])
+        .unwrap();
+        let resp = process(state, &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_body_with_invalid_xml_characters() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", "null \u{0} byte"),
+        ])
+        .unwrap();
+        let resp = process(state, &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidMessageContents</Code>"));
+        assert_eq!(queue.lock().await.counts(), Default::default());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_attribute_names_are_rejected() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let long_name = "a".repeat(257);
+        for name in ["AWS.reserved", long_name.as_str()] {
+            let payload = serde_urlencoded::to_string([
+                ("Action", "SendMessage"),
+                ("QueueUrl", "http://localhost:9090/q"),
+                ("MessageBody", "hi"),
+                ("MessageAttribute.1.Name", name),
+                ("MessageAttribute.1.Value.DataType", "String"),
+                ("MessageAttribute.1.Value.StringValue", "x"),
+            ])
+            .unwrap();
+            let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidParameterValue</Code>"));
         }
+        assert_eq!(queue.lock().await.counts(), Default::default());
     }
 }
@@ -0,0 +1,352 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+const ENTRY_PREFIX: &str = "SendMessageBatchRequestEntry";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMessageBatchParams {
+    queue_url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMessageBatchResponse {
+    send_message_batch_result: SendMessageBatchResult,
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMessageBatchResult {
+    #[serde(rename = "SendMessageBatchResultEntry")]
+    successful: Vec<ResultEntry>,
+    #[serde(rename = "BatchResultErrorEntry")]
+    failed: Vec<ErrorEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResultEntry {
+    id: String,
+    message_id: String,
+    #[serde(rename = "MD5OfMessageBody")]
+    md5_of_message_body: String,
+    #[serde(
+        rename = "MD5OfMessageAttributes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    md5_of_message_attributes: Option<String>,
+    #[serde(
+        rename = "MD5OfMessageSystemAttributes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    md5_of_message_system_attributes: Option<String>,
+    /// FIFO queues only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence_number: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorEntry {
+    id: String,
+    sender_fault: bool,
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+impl ErrorEntry {
+    fn new(id: &str, error: &SqsError) -> Self {
+        ErrorEntry {
+            id: id.to_string(),
+            sender_fault: !error.status().is_server_error(),
+            code: error.code().to_string(),
+            message: error.message(),
+        }
+    }
+}
+
+/// Send up to ten messages in one request.
+///
+/// Each entry may set its own `DelaySeconds`; entries without one use the queue's
+/// `DelaySeconds`. Entries fail independently and are reported in `BatchResultErrorEntry`.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<SendMessageBatchParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let queue_name = match helpers::extract_queue_name_from_url(&params.queue_url) {
+        Some(name) => name,
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
+    };
+
+    let entries = helpers::extract_batch_entries(payload, ENTRY_PREFIX);
+    let ids: Vec<String> = entries
+        .iter()
+        .map(|e| e.get("Id").cloned().unwrap_or_default())
+        .collect();
+    if let Err(e) = helpers::validate_batch_entries(&ids) {
+        return e.error_response();
+    }
+
+    // Entries that can't be parsed fail here; the engine checks the rest
+    let mut failed = Vec::new();
+    let mut sent_ids = Vec::new();
+    let mut messages = Vec::new();
+    for (id, entry) in ids.iter().zip(&entries) {
+        match parse_entry(entry) {
+            Ok(message) => {
+                sent_ids.push(id);
+                messages.push(message);
+            }
+            Err(e) => failed.push(ErrorEntry::new(id, &e)),
+        }
+    }
+
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let results = match engine.send_batch(&queue_name, messages).await {
+        Ok(results) => results,
+        Err(e) => return e.error_response(),
+    };
+    let mut successful = Vec::new();
+    for (id, result) in sent_ids.into_iter().zip(results) {
+        match result {
+            Ok(stored) => successful.push(result_entry(id, &stored)),
+            Err(e) => failed.push(ErrorEntry::new(id, &e)),
+        }
+    }
+
+    let response = SendMessageBatchResponse {
+        send_message_batch_result: SendMessageBatchResult { successful, failed },
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+/// The message and DelaySeconds of one entry.
+fn parse_entry(
+    entry: &HashMap<String, String>,
+) -> Result<(crate::queue::Message, Option<u32>), SqsError> {
+    let body = entry.get("MessageBody").ok_or_else(|| {
+        SqsError::MissingParameter(
+            "The request must contain the parameter MessageBody.".to_string(),
+        )
+    })?;
+    // The engine checks the upper bound
+    let delay_seconds = entry
+        .get("DelaySeconds")
+        .map(|value| {
+            value.parse::<u32>().map_err(|_| {
+                SqsError::InvalidParameterValue(format!(
+                    "Value {} for parameter DelaySeconds is invalid. Reason: must be between 0 and 900.",
+                    value
+                ))
+            })
+        })
+        .transpose()?;
+
+    let mut message = crate::queue::Message::new(helpers::generate_random_uuid4(), body.clone());
+    message.message_attributes =
+        helpers::parse_message_attributes(entry).map_err(SqsError::InvalidParameterValue)?;
+    message.message_system_attributes =
+        helpers::parse_message_system_attributes(entry).map_err(SqsError::InvalidParameterValue)?;
+    message.message_group_id = entry.get("MessageGroupId").cloned();
+    message.message_deduplication_id = entry.get("MessageDeduplicationId").cloned();
+    Ok((message, delay_seconds))
+}
+
+fn result_entry(id: &str, message: &crate::queue::Message) -> ResultEntry {
+    ResultEntry {
+        id: id.to_string(),
+        message_id: message.id.clone(),
+        md5_of_message_body: helpers::compute_md5(&message.message_body),
+        md5_of_message_attributes: (!message.message_attributes.is_empty())
+            .then(|| helpers::compute_message_attributes_md5(&message.message_attributes)),
+        md5_of_message_system_attributes: (!message.message_system_attributes.is_empty())
+            .then(|| helpers::compute_message_attributes_md5(&message.message_system_attributes)),
+        sequence_number: message.sequence_number.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    fn batch_payload(entries: &[(&str, Option<&str>)]) -> web::Bytes {
+        let mut params = vec![
+            ("Action".to_string(), "SendMessageBatch".to_string()),
+            (
+                "QueueUrl".to_string(),
+                "http://localhost:9090/q".to_string(),
+            ),
+        ];
+        for (i, (id, delay)) in entries.iter().enumerate() {
+            let prefix = format!("{}.{}", ENTRY_PREFIX, i + 1);
+            params.push((format!("{}.Id", prefix), id.to_string()));
+            params.push((format!("{}.MessageBody", prefix), format!("body {}", id)));
+            if let Some(delay) = delay {
+                params.push((format!("{}.DelaySeconds", prefix), delay.to_string()));
+            }
+        }
+        web::Bytes::from(serde_urlencoded::to_string(params).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_per_entry_delay() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let payload = batch_payload(&[("a", None), ("b", Some("60")), ("c", Some("0"))]);
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body)
+                .matches("<SendMessageBatchResultEntry>")
+                .count(),
+            3
+        );
+
+        let received = queue.lock().await.receive(10, None);
+        let bodies: Vec<&str> = received.iter().map(|m| m.message_body.as_str()).collect();
+        assert_eq!(bodies, ["body a", "body c"]);
+    }
+
+    #[tokio::test]
+    async fn test_entries_fall_back_to_queue_delay() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue.lock().await.default_delay_seconds = 30;
+
+        let payload = batch_payload(&[("a", None), ("b", Some("0"))]);
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let mut queue = queue.lock().await;
+        assert_eq!(queue.counts().delayed, 1);
+        let received = queue.receive(10, None);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].message_body, "body b");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_entry_is_reported_separately() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let payload = batch_payload(&[("a", None), ("b", Some("901"))]);
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<BatchResultErrorEntry><Id>b</Id><SenderFault>true</SenderFault><Code>InvalidParameterValue</Code>"));
+        assert_eq!(queue.lock().await.counts().visible, 1);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_entry_is_reported_separately() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+        queue.lock().await.maximum_message_size = 8;
+
+        // "body a" fits in 8 bytes, "body long" doesn't
+        let payload = batch_payload(&[("a", None), ("long", None)]);
+        let resp = process(state, &payload, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<BatchResultErrorEntry><Id>long</Id><SenderFault>true</SenderFault><Code>InvalidParameterValue</Code>"));
+        assert_eq!(queue.lock().await.counts().visible, 1);
+    }
+
+    #[tokio::test]
+    async fn test_md5s_match_single_send() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+        let body = "naïve <body> & 🚀";
+        let attribute = [
+            ("MessageAttribute.1.Name", "colour"),
+            ("MessageAttribute.1.Value.DataType", "String"),
+            ("MessageAttribute.1.Value.StringValue", "blue"),
+        ];
+        let md5s = |xml: &str| {
+            ["MD5OfMessageBody", "MD5OfMessageAttributes"].map(|tag| {
+                xml.split(&format!("<{}>", tag))
+                    .nth(1)
+                    .and_then(|rest| rest.split(&format!("</{}>", tag)).next())
+                    .unwrap()
+                    .to_string()
+            })
+        };
+
+        let mut single = vec![
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("MessageBody", body),
+        ];
+        single.extend(attribute);
+        let payload = web::Bytes::from(serde_urlencoded::to_string(single).unwrap());
+        let resp = crate::api::send_message::process(state.clone(), &payload, false).await;
+        let single = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+
+        let mut batch = vec![
+            ("Action".to_string(), "SendMessageBatch".to_string()),
+            (
+                "QueueUrl".to_string(),
+                "http://localhost:9090/q".to_string(),
+            ),
+            (format!("{}.1.Id", ENTRY_PREFIX), "a".to_string()),
+            (format!("{}.1.MessageBody", ENTRY_PREFIX), body.to_string()),
+        ];
+        batch.extend(
+            attribute
+                .map(|(name, value)| (format!("{}.1.{}", ENTRY_PREFIX, name), value.to_string())),
+        );
+        let payload = web::Bytes::from(serde_urlencoded::to_string(batch).unwrap());
+        let resp = process(state, &payload, false).await;
+        let batch = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+
+        let single = md5s(&String::from_utf8_lossy(&single));
+        assert_eq!(single[0], helpers::compute_md5(body));
+        assert_eq!(single, md5s(&String::from_utf8_lossy(&batch)));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_entries() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let ids: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+        let entries: Vec<(&str, Option<&str>)> = ids.iter().map(|id| (id.as_str(), None)).collect();
+        let resp = process(state, &batch_payload(&entries), false).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body)
+            .contains("AWS.SimpleQueueService.TooManyEntriesInBatchRequest"));
+    }
+}
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
 
 use super::helpers;
+use crate::error::SqsError;
 use crate::AppState;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -26,6 +27,11 @@ struct ResponseMetadata {
     request_id: String,
 }
 
+/// Set queue attributes.
+///
+/// An attribute set to an empty value is cleared: its stored value is removed so the
+/// attribute reverts to its default. For `RedrivePolicy` this removes the DLQ wiring,
+/// for `VisibilityTimeout` the queue goes back to the 30 second default.
 pub async fn process(
     app_state: Arc<AppState>,
     payload: &web::Bytes,
@@ -33,17 +39,12 @@ pub async fn process(
 ) -> HttpResponse {
     let params = match super::struct_from_url_encode::<SetQueueAttributesParams>(payload) {
         Ok(p) => p,
-        Err(e) => {
-            return HttpResponse::BadRequest().body(format!("Failed to parse payload: {}", e))
-        }
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
     };
 
     let queue_name = match helpers::extract_queue_name_from_url(&params.queue_url) {
         Some(name) => name,
-        None => {
-            return HttpResponse::BadRequest()
-                .body("Invalid QueueUrl: could not extract queue name")
-        }
+        None => return SqsError::InvalidAddress(params.queue_url.clone()).error_response(),
     };
 
     // Parse Attribute.N.Name / Attribute.N.Value pairs
@@ -55,47 +56,274 @@ pub async fn process(
     let attrs = helpers::get_attrbutes_hashmap(param_values);
 
     if attrs.is_empty() {
-        return HttpResponse::BadRequest().body("No attributes provided");
+        return SqsError::MissingParameter(
+            "The request must contain the parameter Attribute.1.Name.".to_string(),
+        )
+        .error_response();
+    }
+
+    let (cleared, attrs): (HashMap<String, String>, HashMap<String, String>) =
+        attrs.into_iter().partition(|(_, value)| value.is_empty());
+
+    for (name, value) in &attrs {
+        if let Err(e) = helpers::validate_attribute_value(name, value) {
+            return SqsError::InvalidAttributeValue(e.to_string()).error_response();
+        }
     }
 
     // Check queue exists
-    {
-        let reader = app_state.queues.lock().await;
-        if !reader.contains_key(&queue_name) {
-            return HttpResponse::BadRequest().body(format!(
-                "AWS.SimpleQueueService.NonExistentQueue; Queue: {}",
-                queue_name
-            ));
+    let queue = match app_state.get_queue(&queue_name).await {
+        Some(q) => q,
+        None => {
+            return SqsError::NonExistentQueue(queue_name).error_response();
+        }
+    };
+
+    if let Some(redrive_policy) = attrs.get("RedrivePolicy") {
+        let engine = crate::engine::SqsEngine::new(app_state.clone());
+        if let Err(e) = engine
+            .check_redrive_allowed(&queue_name, redrive_policy)
+            .await
+        {
+            return e.error_response();
         }
     }
 
-    // Update in-memory VisibilityTimeout if provided
-    if let Some(vt) = attrs.get("VisibilityTimeout") {
-        if let Ok(timeout) = vt.parse::<u32>() {
-            let mut writer = app_state.queues.lock().await;
-            if let Some(queue) = writer.get_mut(&queue_name) {
-                queue.default_visibility_timeout = timeout;
-            }
+    let is_fifo = queue.lock().await.is_fifo;
+    if let Some(name) = attrs
+        .keys()
+        .chain(cleared.keys())
+        .find(|name| helpers::FIFO_ONLY_ATTRIBUTES.contains(&name.as_str()))
+        .filter(|_| !is_fifo)
+    {
+        return SqsError::InvalidAttributeName(format!(
+            "Attribute {} is only valid for FIFO queues",
+            name
+        ))
+        .error_response();
+    }
+
+    // Persist to DB first, so a failed write leaves the queue as it was
+    let service = crate::service::queue::Queue::new(&app_state.db_pool);
+    if !cleared.is_empty() {
+        let names: Vec<String> = cleared.keys().cloned().collect();
+        if let Err(e) = service.delete_queue_attributes(&queue_name, &names).await {
+            return SqsError::InternalFailure(format!("Failed to clear attributes: {}", e))
+                .error_response();
         }
     }
+    if let Err(e) = service
+        .set_queue_attributes(&queue_name, attrs.clone())
+        .await
+    {
+        return SqsError::InternalFailure(format!("Failed to set attributes: {}", e))
+            .error_response();
+    }
 
-    // Persist to DB
-    let service = crate::service::queue::Queue::new(&app_state.db_pool, &app_state.host_name);
-    if let Err(e) = service.set_queue_attributes(&queue_name, attrs).await {
-        return HttpResponse::InternalServerError()
-            .body(format!("Failed to set attributes: {}", e));
+    // Then update the in-memory VisibilityTimeout, DelaySeconds, size and receive limits
+    {
+        let mut queue = queue.lock().await;
+        app_state.apply_queue_attributes(&mut queue, &attrs);
+        app_state.apply_queue_attributes(&mut queue, &cleared);
     }
 
     let response = SetQueueAttributesResponse {
         response_metadata: ResponseMetadata {
-            request_id: helpers::generate_random_uuid4(),
+            request_id: helpers::request_id(),
         },
     };
 
-    match quick_xml::se::to_string(&response) {
+    match helpers::to_xml(&response) {
         Ok(resp) => HttpResponse::Ok().body(resp),
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to serialize response: {}", e))
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn set_attribute(state: Arc<AppState>, name: &str, value: &str) -> HttpResponse {
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SetQueueAttributes"),
+            ("QueueUrl", "http://localhost:9090/q"),
+            ("Attribute.1.Name", name),
+            ("Attribute.1.Value", value),
+        ])
+        .unwrap();
+        process(state, &web::Bytes::from(payload), false).await
+    }
+
+    #[tokio::test]
+    async fn test_rejects_non_numeric_visibility_timeout() {
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let resp = set_attribute(state, "VisibilityTimeout", "abc").await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidAttributeValue</Code>"));
+        assert_eq!(queue.lock().await.default_visibility_timeout, 30);
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_leaves_queue_unchanged() {
+        // The queue isn't in the (unmigrated) database, so the write fails
+        let state = Arc::new(AppState::for_tests());
+        let queue = state.add_test_queue("q").await;
+
+        let resp = set_attribute(state, "VisibilityTimeout", "45").await;
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(queue.lock().await.default_visibility_timeout, 30);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_out_of_range_delay() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let resp = set_attribute(state, "DelaySeconds", "-5").await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidAttributeValue</Code>"));
+    }
+
+    #[tokio::test]
+    async fn test_clear_redrive_policy() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let payload = serde_urlencoded::to_string([
+            ("Action", "CreateQueue"),
+            ("QueueName", "q"),
+            ("Attribute.1.Name", "RedrivePolicy"),
+            (
+                "Attribute.1.Value",
+                r#"{"deadLetterTargetArn":"arn:aws:sqs:us-east-1:000000000000:dlq","maxReceiveCount":"3"}"#,
+            ),
+        ])
+        .unwrap();
+        let resp =
+            super::super::create_queue::process(state.clone(), &web::Bytes::from(payload), false)
+                .await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let resp = set_attribute(state.clone(), "RedrivePolicy", "").await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q&AttributeName.1=All",
+        );
+        let resp = super::super::get_queue_attributes::process(state, &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("RedrivePolicy"));
+    }
+
+    #[tokio::test]
+    async fn test_redrive_policy_needs_dead_letter_queue_permission() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let engine = crate::engine::SqsEngine::new(state.clone());
+        engine
+            .create_queue(
+                "dlq",
+                HashMap::from([(
+                    "RedriveAllowPolicy".to_string(),
+                    r#"{"redrivePermission":"denyAll"}"#.to_string(),
+                )]),
+                HashMap::new(),
+            )
+            .await
+            .unwrap();
+        engine
+            .create_queue("q", HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+
+        let redrive = r#"{"deadLetterTargetArn":"arn:aws:sqs:us-east-1:000000000000:dlq","maxReceiveCount":"3"}"#;
+        let resp = set_attribute(state.clone(), "RedrivePolicy", redrive).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidParameterValue</Code>"));
+
+        // Opening the dead-letter queue up lets the policy through
+        let payload = serde_urlencoded::to_string([
+            ("Action", "SetQueueAttributes"),
+            ("QueueUrl", "http://localhost:9090/dlq"),
+            ("Attribute.1.Name", "RedriveAllowPolicy"),
+            (
+                "Attribute.1.Value",
+                r#"{"redrivePermission":"byQueue","sourceQueueArns":["arn:aws:sqs:us-east-1:000000000000:q"]}"#,
+            ),
+        ])
+        .unwrap();
+        let resp = process(state.clone(), &web::Bytes::from(payload), false).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        let resp = set_attribute(state, "RedrivePolicy", redrive).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_fifo_throughput_attributes_round_trip() {
+        let state = Arc::new(AppState::for_tests_with_db().await);
+        let payload = serde_urlencoded::to_string([
+            ("Action", "CreateQueue"),
+            ("QueueName", "q.fifo"),
+            ("Attribute.1.Name", "FifoQueue"),
+            ("Attribute.1.Value", "true"),
+            ("Attribute.2.Name", "DeduplicationScope"),
+            ("Attribute.2.Value", "messageGroup"),
+        ])
+        .unwrap();
+        let resp =
+            super::super::create_queue::process(state.clone(), &web::Bytes::from(payload), false)
+                .await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let set = |name: &'static str, value: &'static str| {
+            let state = state.clone();
+            async move {
+                let payload = serde_urlencoded::to_string([
+                    ("Action", "SetQueueAttributes"),
+                    ("QueueUrl", "http://localhost:9090/q.fifo"),
+                    ("Attribute.1.Name", name),
+                    ("Attribute.1.Value", value),
+                ])
+                .unwrap();
+                process(state, &web::Bytes::from(payload), false).await
+            }
+        };
+        let resp = set("FifoThroughputLimit", "perQueueGroup").await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let resp = set("FifoThroughputLimit", "perMessageGroupId").await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+        let payload = web::Bytes::from(
+            "Action=GetQueueAttributes&QueueUrl=http://localhost:9090/q.fifo&AttributeName.1=All",
+        );
+        let resp =
+            super::super::get_queue_attributes::process(state.clone(), &payload, false).await;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        for (name, value) in [
+            ("DeduplicationScope", "messageGroup"),
+            ("FifoThroughputLimit", "perMessageGroupId"),
+            ("ContentBasedDeduplication", "false"),
+        ] {
+            let attr = format!("<Name>{}</Name><Value>{}</Value>", name, value);
+            assert_eq!(body.matches(&attr).count(), 1, "{} in {}", attr, body);
         }
     }
+
+    #[tokio::test]
+    async fn test_rejects_fifo_attributes_on_standard_queue() {
+        let state = Arc::new(AppState::for_tests());
+        state.add_test_queue("q").await;
+
+        let resp = set_attribute(state, "DeduplicationScope", "queue").await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>InvalidAttributeName</Code>"));
+    }
 }
@@ -0,0 +1,224 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::helpers;
+use crate::error::SqsError;
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StartMessageMoveTaskParams {
+    source_arn: Option<String>,
+    destination_arn: Option<String>,
+    max_number_of_messages_per_second: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct StartMessageMoveTaskResponse {
+    start_message_move_task_result: StartMessageMoveTaskResult,
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct StartMessageMoveTaskResult {
+    task_handle: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+/// Redrive a dead-letter queue's messages to `DestinationArn`, or back to the queue whose
+/// RedrivePolicy targets it. The move is done before the response is sent.
+pub async fn process(
+    app_state: Arc<AppState>,
+    payload: &web::Bytes,
+    _is_json: bool,
+) -> HttpResponse {
+    let params = match super::struct_from_url_encode::<StartMessageMoveTaskParams>(payload) {
+        Ok(p) => p,
+        Err(e) => return SqsError::MalformedQueryString(e.to_string()).error_response(),
+    };
+
+    let Some(source_arn) = params.source_arn else {
+        return SqsError::MissingParameter(
+            "The request must contain the parameter SourceArn.".to_string(),
+        )
+        .error_response();
+    };
+
+    let engine = crate::engine::SqsEngine::new(app_state);
+    let task = match engine
+        .start_message_move_task(
+            &source_arn,
+            params.destination_arn.as_deref(),
+            params.max_number_of_messages_per_second,
+        )
+        .await
+    {
+        Ok(task) => task,
+        Err(e) => return e.error_response(),
+    };
+
+    let response = StartMessageMoveTaskResponse {
+        start_message_move_task_result: StartMessageMoveTaskResult {
+            task_handle: task.task_handle,
+        },
+        response_metadata: ResponseMetadata {
+            request_id: helpers::request_id(),
+        },
+    };
+
+    match helpers::to_xml(&response) {
+        Ok(resp) => HttpResponse::Ok().body(resp),
+        Err(e) => SqsError::InternalFailure(format!("Failed to serialize response: {}", e))
+            .error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SqsEngine;
+    use crate::move_task::MoveTaskStatus;
+    use crate::queue::Message;
+    use actix_web::http::StatusCode;
+    use std::collections::HashMap;
+
+    const DLQ_ARN: &str = "arn:aws:sqs:us-east-1:000000000000:dlq";
+
+    /// An engine with a `dlq` that `orders` redrives into, and an unrelated `other` queue.
+    async fn engine_with_dlq() -> SqsEngine {
+        let engine = SqsEngine::new(Arc::new(AppState::for_tests_with_db().await));
+        let redrive = format!(
+            r#"{{"deadLetterTargetArn":"{}","maxReceiveCount":"3"}}"#,
+            DLQ_ARN
+        );
+        for (name, attributes) in [
+            ("dlq", HashMap::new()),
+            ("other", HashMap::new()),
+            (
+                "orders",
+                HashMap::from([("RedrivePolicy".to_string(), redrive)]),
+            ),
+        ] {
+            engine
+                .create_queue(name, attributes, HashMap::new())
+                .await
+                .unwrap();
+        }
+        engine
+    }
+
+    async fn start(state: Arc<AppState>, params: &str) -> (StatusCode, String) {
+        let payload = web::Bytes::from(format!("Action=StartMessageMoveTask&{}", params));
+        let resp = process(state, &payload, false).await;
+        let status = resp.status();
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_moves_messages_back_to_source() {
+        let engine = engine_with_dlq().await;
+        for id in ["1", "2"] {
+            engine
+                .send(
+                    "dlq",
+                    Message::new(id.to_owned(), "poison".to_owned()),
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        // A message that had been received is delivered afresh
+        engine.receive("dlq", &Default::default()).await.unwrap();
+
+        let (status, body) = start(engine.state().clone(), &format!("SourceArn={}", DLQ_ARN)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let handle = body
+            .split("<TaskHandle>")
+            .nth(1)
+            .and_then(|rest| rest.split("</TaskHandle>").next())
+            .unwrap()
+            .to_string();
+
+        let state = engine.state();
+        let dlq = state.get_queue("dlq").await.unwrap();
+        assert_eq!(dlq.lock().await.messages().count(), 0);
+        let orders = state.get_queue("orders").await.unwrap();
+        let orders = orders.lock().await;
+        let moved: Vec<_> = orders.messages().collect();
+        assert_eq!(moved.len(), 2);
+        assert_eq!(moved[0].id, "1");
+        assert!(moved.iter().all(|m| m.receive_count == 0));
+        assert_eq!(orders.counts().visible, 2);
+
+        let tasks = state.move_tasks.lock().await;
+        let task = &tasks[&handle];
+        assert_eq!(task.status, MoveTaskStatus::Completed);
+        assert_eq!(task.approximate_number_of_messages_moved, 2);
+        assert_eq!(task.source_arn, DLQ_ARN);
+    }
+
+    #[tokio::test]
+    async fn test_moves_to_destination() {
+        let engine = engine_with_dlq().await;
+        engine
+            .send("dlq", Message::new("1".to_owned(), "hi".to_owned()), None)
+            .await
+            .unwrap();
+
+        let (status, body) = start(
+            engine.state().clone(),
+            &format!(
+                "SourceArn={}&DestinationArn=arn:aws:sqs:us-east-1:000000000000:other",
+                DLQ_ARN
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let other = engine.state().get_queue("other").await.unwrap();
+        assert_eq!(other.lock().await.messages().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_requests() {
+        let engine = engine_with_dlq().await;
+        for (params, code) in [
+            ("", "MissingParameter"),
+            ("SourceArn=dlq", "InvalidParameterValue"),
+            // Not a dead-letter queue
+            (
+                "SourceArn=arn:aws:sqs:us-east-1:000000000000:other",
+                "InvalidParameterValue",
+            ),
+            (
+                "SourceArn=arn:aws:sqs:us-east-1:000000000000:missing",
+                "AWS.SimpleQueueService.NonExistentQueue",
+            ),
+            (
+                "SourceArn=arn:aws:sqs:us-east-1:000000000000:dlq&MaxNumberOfMessagesPerSecond=501",
+                "InvalidParameterValue",
+            ),
+            (
+                "SourceArn=arn:aws:sqs:us-east-1:000000000000:dlq&DestinationArn=arn:aws:sqs:us-east-1:000000000000:dlq",
+                "InvalidParameterValue",
+            ),
+        ] {
+            let (status, body) = start(engine.state().clone(), params).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", params);
+            assert!(
+                body.contains(&format!("<Code>{}</Code>", code)),
+                "{}: {}",
+                params,
+                body
+            );
+        }
+    }
+}
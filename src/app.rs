@@ -0,0 +1,79 @@
+//! The actix app serving the mock, shared by the server in `main.rs` and the HTTP tests.
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    middleware, web, App,
+};
+
+use crate::{admin, api, cors, metrics, signature, AppState};
+
+/// Default limit on request bodies: room for a 10-entry SendMessageBatch of 256 KiB
+/// messages even when URL-encoding triples their size.
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 8 * 1024 * 1024;
+
+/// Optional parts of the app, off by default.
+#[derive(Debug, Clone)]
+pub struct AppOptions {
+    /// Serve the debugging endpoints under /admin
+    pub admin: bool,
+    /// Serve Prometheus metrics at /metrics
+    pub metrics: bool,
+    pub cors: Option<cors::Cors>,
+    pub signature_check: Option<signature::SignatureCheck>,
+    /// Largest request body accepted, after decompression; larger ones get a 413
+    pub max_payload_bytes: usize,
+}
+
+impl Default for AppOptions {
+    fn default() -> Self {
+        AppOptions {
+            admin: false,
+            metrics: false,
+            cors: None,
+            signature_check: None,
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+        }
+    }
+}
+
+/// Build the app for one worker. Every worker gets a clone of the same `state`, so they
+/// share queues.
+pub fn build_app(
+    state: AppState,
+    options: &AppOptions,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let options = options.clone();
+    App::new()
+        .configure(|cfg| {
+            if options.admin {
+                admin::configure(cfg);
+            }
+            if options.metrics {
+                metrics::configure(cfg);
+            }
+            if let Some(cors) = options.cors {
+                cfg.app_data(web::Data::new(cors));
+            }
+            if let Some(check) = options.signature_check {
+                cfg.app_data(web::Data::new(check));
+            }
+        })
+        .service(api::post_handler)
+        .service(web::resource("/").to(api::method_not_allowed))
+        .service(api::queue_path_handler)
+        .app_data(web::Data::new(state))
+        .app_data(web::PayloadConfig::new(options.max_payload_bytes))
+        .wrap(middleware::from_fn(signature::middleware))
+        .wrap(middleware::from_fn(cors::middleware))
+        .wrap(middleware::Compress::default())
+        .wrap(middleware::Logger::default())
+}
@@ -0,0 +1,376 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::SqsError;
+
+/// Prefix of the environment variables configuring per-action latency,
+/// e.g. `SQS_LATENCY_RECEIVEMESSAGE=500`. `SQS_LATENCY_ALL` applies to every action.
+const LATENCY_ENV_PREFIX: &str = "SQS_LATENCY_";
+
+/// Prefix of the environment variables configuring error injection, as
+/// `<probability>:<ErrorCode>`, e.g. `SQS_CHAOS_SENDMESSAGE=0.25:ServiceUnavailable`.
+const CHAOS_ENV_PREFIX: &str = "SQS_CHAOS_";
+
+/// Seeds the error injection RNG so failures are reproducible.
+const CHAOS_SEED_ENV: &str = "SQS_CHAOS_SEED";
+
+/// Distribution of the extra delay before ReceiveMessage responds, as `fixed:<ms>`,
+/// `uniform:<min>-<max>` or `normal:<mean>:<std dev>`, e.g. `SQS_RECEIVE_LATENCY=normal:40:10`.
+const RECEIVE_LATENCY_ENV: &str = "SQS_RECEIVE_LATENCY";
+
+/// Seeds the receive latency RNG so the delays are reproducible.
+const RECEIVE_LATENCY_SEED_ENV: &str = "SQS_RECEIVE_LATENCY_SEED";
+
+/// Action -> (probability in 0..=1, error to return).
+pub type ChaosConfig = HashMap<String, (f32, SqsError)>;
+
+/// Key matching every action in the latency and chaos maps.
+pub const WILDCARD: &str = "*";
+
+/// Normalize an action name so `AmazonSQS.ReceiveMessage`, `ReceiveMessage`
+/// and `RECEIVEMESSAGE` all map to the same key.
+pub fn action_key(action: &str) -> String {
+    let action = action.to_lowercase();
+    match action.strip_prefix("amazonsqs.") {
+        Some(stripped) => stripped.to_string(),
+        None => action,
+    }
+}
+
+/// Build the action -> millis latency map from `SQS_LATENCY_*` environment variables.
+/// Variables with a non-numeric value are ignored.
+pub fn latency_from_env(vars: impl Iterator<Item = (String, String)>) -> HashMap<String, u64> {
+    let mut latency = HashMap::new();
+    for (key, value) in vars {
+        let Some(action) = key.strip_prefix(LATENCY_ENV_PREFIX) else {
+            continue;
+        };
+        let Ok(millis) = value.trim().parse::<u64>() else {
+            continue;
+        };
+        let action = if action == "ALL" {
+            WILDCARD.to_string()
+        } else {
+            action_key(action)
+        };
+        latency.insert(action, millis);
+    }
+    latency
+}
+
+/// The delay to inject before handling `action`. A specific entry wins over the wildcard.
+pub fn latency_for(latency: &HashMap<String, u64>, action: &str) -> Option<Duration> {
+    latency
+        .get(&action_key(action))
+        .or_else(|| latency.get(WILDCARD))
+        .filter(|millis| **millis > 0)
+        .map(|millis| Duration::from_millis(*millis))
+}
+
+/// Build the chaos config from `SQS_CHAOS_*` environment variables.
+/// Malformed values are ignored.
+pub fn chaos_from_env(vars: impl Iterator<Item = (String, String)>) -> ChaosConfig {
+    let mut chaos = HashMap::new();
+    for (key, value) in vars {
+        if key == CHAOS_SEED_ENV {
+            continue;
+        }
+        let Some(action) = key.strip_prefix(CHAOS_ENV_PREFIX) else {
+            continue;
+        };
+        let Some((probability, error)) = value.split_once(':') else {
+            continue;
+        };
+        let (Ok(probability), Ok(error)) =
+            (probability.trim().parse::<f32>(), error.trim().parse())
+        else {
+            continue;
+        };
+        let action = if action == "ALL" {
+            WILDCARD.to_string()
+        } else {
+            action_key(action)
+        };
+        chaos.insert(action, (probability.clamp(0.0, 1.0), error));
+    }
+    chaos
+}
+
+/// RNG for error injection, seeded from `SQS_CHAOS_SEED` when set.
+pub fn chaos_rng_from_env(vars: impl Iterator<Item = (String, String)>) -> Arc<Mutex<StdRng>> {
+    let seed = vars
+        .filter(|(key, _)| key == CHAOS_SEED_ENV)
+        .find_map(|(_, value)| value.trim().parse::<u64>().ok());
+    let rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    Arc::new(Mutex::new(rng))
+}
+
+/// Shape of the receive latency, in millis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyDistribution {
+    Fixed(u64),
+    /// Between the bounds, inclusive
+    Uniform(u64, u64),
+    /// Mean and standard deviation; samples below zero count as zero
+    Normal(f64, f64),
+}
+
+impl std::str::FromStr for LatencyDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid latency distribution: {}", s);
+        let (kind, args) = s.trim().split_once(':').ok_or_else(invalid)?;
+        match kind.to_lowercase().as_str() {
+            "fixed" => args
+                .parse()
+                .map(LatencyDistribution::Fixed)
+                .map_err(|_| invalid()),
+            "uniform" => {
+                let (min, max) = args.split_once('-').ok_or_else(invalid)?;
+                match (min.parse(), max.parse()) {
+                    (Ok(min), Ok(max)) if min <= max => Ok(LatencyDistribution::Uniform(min, max)),
+                    _ => Err(invalid()),
+                }
+            }
+            "normal" => {
+                let (mean, std_dev) = args.split_once(':').ok_or_else(invalid)?;
+                match (mean.parse::<f64>(), std_dev.parse::<f64>()) {
+                    (Ok(mean), Ok(std_dev)) if mean >= 0.0 && std_dev >= 0.0 => {
+                        Ok(LatencyDistribution::Normal(mean, std_dev))
+                    }
+                    _ => Err(invalid()),
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Extra delay for ReceiveMessage, drawn per request after the messages are fetched.
+#[derive(Debug, Clone)]
+pub struct ReceiveLatency {
+    pub distribution: LatencyDistribution,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl ReceiveLatency {
+    /// Delays drawn from `distribution`; the same seed gives the same delays.
+    pub fn new(distribution: LatencyDistribution, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        ReceiveLatency {
+            distribution,
+            rng: Arc::new(Mutex::new(rng)),
+        }
+    }
+
+    pub fn sample(&self) -> Duration {
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        match self.distribution {
+            LatencyDistribution::Fixed(millis) => Duration::from_millis(millis),
+            LatencyDistribution::Uniform(min, max) => {
+                Duration::from_millis(rng.gen_range(min..=max))
+            }
+            LatencyDistribution::Normal(mean, std_dev) => {
+                // Box-Muller; 1 - u keeps the logarithm's argument in (0, 1]
+                let u1: f64 = 1.0 - rng.gen::<f64>();
+                let u2: f64 = rng.gen();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                Duration::from_secs_f64((mean + std_dev * z).max(0.0) / 1000.0)
+            }
+        }
+    }
+}
+
+/// Build the receive latency from `SQS_RECEIVE_LATENCY` and `SQS_RECEIVE_LATENCY_SEED`.
+/// None when unset or malformed.
+pub fn receive_latency_from_env(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Option<ReceiveLatency> {
+    let mut distribution = None;
+    let mut seed = None;
+    for (key, value) in vars {
+        if key == RECEIVE_LATENCY_ENV {
+            distribution = value.parse().ok();
+        } else if key == RECEIVE_LATENCY_SEED_ENV {
+            seed = value.trim().parse::<u64>().ok();
+        }
+    }
+    distribution.map(|distribution| ReceiveLatency::new(distribution, seed))
+}
+
+/// Roll the dice for `action`; returns the error to short-circuit with, if any.
+pub fn roll(chaos: &ChaosConfig, rng: &Mutex<StdRng>, action: &str) -> Option<SqsError> {
+    let (probability, error) = chaos
+        .get(&action_key(action))
+        .or_else(|| chaos.get(WILDCARD))?;
+    if *probability <= 0.0 {
+        return None;
+    }
+
+    let mut rng = rng.lock().unwrap_or_else(|e| e.into_inner());
+    if rng.gen::<f32>() < *probability {
+        Some(error.clone())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_from_env() {
+        let vars = vec![
+            ("SQS_LATENCY_RECEIVEMESSAGE".to_string(), "500".to_string()),
+            ("SQS_LATENCY_ALL".to_string(), "10".to_string()),
+            ("SQS_LATENCY_SENDMESSAGE".to_string(), "abc".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ];
+        let latency = latency_from_env(vars.into_iter());
+
+        assert_eq!(latency.len(), 2);
+        assert_eq!(latency.get("receivemessage"), Some(&500));
+        assert_eq!(latency.get(WILDCARD), Some(&10));
+    }
+
+    #[test]
+    fn test_latency_for() {
+        let mut latency = HashMap::new();
+        latency.insert("receivemessage".to_string(), 500);
+
+        assert_eq!(
+            latency_for(&latency, "AmazonSQS.ReceiveMessage"),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(latency_for(&latency, "SendMessage"), None);
+
+        latency.insert(WILDCARD.to_string(), 20);
+        assert_eq!(
+            latency_for(&latency, "SendMessage"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            latency_for(&latency, "ReceiveMessage"),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn test_receive_latency_from_env() {
+        let vars = |latency: &str| {
+            vec![
+                ("SQS_RECEIVE_LATENCY".to_string(), latency.to_string()),
+                ("SQS_RECEIVE_LATENCY_SEED".to_string(), "3".to_string()),
+            ]
+            .into_iter()
+        };
+        for (value, expected) in [
+            ("fixed:50", Some(LatencyDistribution::Fixed(50))),
+            (
+                "uniform:10-100",
+                Some(LatencyDistribution::Uniform(10, 100)),
+            ),
+            (
+                "Normal:40:7.5",
+                Some(LatencyDistribution::Normal(40.0, 7.5)),
+            ),
+            ("uniform:100-10", None),
+            ("normal:40", None),
+            ("poisson:3", None),
+            ("50", None),
+        ] {
+            assert_eq!(
+                receive_latency_from_env(vars(value)).map(|l| l.distribution),
+                expected,
+                "{}",
+                value
+            );
+        }
+        assert!(receive_latency_from_env(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn test_receive_latency_samples() {
+        let fixed = ReceiveLatency::new(LatencyDistribution::Fixed(25), None);
+        assert_eq!(fixed.sample(), Duration::from_millis(25));
+
+        let uniform = ReceiveLatency::new(LatencyDistribution::Uniform(10, 20), Some(1));
+        let samples: Vec<_> = (0..100).map(|_| uniform.sample()).collect();
+        assert!(samples
+            .iter()
+            .all(|d| (Duration::from_millis(10)..=Duration::from_millis(20)).contains(d)));
+        assert!(samples.iter().any(|d| *d != samples[0]));
+
+        // Same seed, same delays
+        let normal = || ReceiveLatency::new(LatencyDistribution::Normal(40.0, 10.0), Some(9));
+        let (a, b) = (normal(), normal());
+        let samples: Vec<_> = (0..200).map(|_| a.sample()).collect();
+        assert_eq!(samples, (0..200).map(|_| b.sample()).collect::<Vec<_>>());
+        let mean = samples.iter().map(Duration::as_secs_f64).sum::<f64>() / 200.0 * 1000.0;
+        assert!((35.0..45.0).contains(&mean), "{}", mean);
+    }
+
+    #[test]
+    fn test_chaos_from_env() {
+        let vars = vec![
+            (
+                "SQS_CHAOS_SENDMESSAGE".to_string(),
+                "0.25:ServiceUnavailable".to_string(),
+            ),
+            (
+                "SQS_CHAOS_ALL".to_string(),
+                "1:RequestThrottled".to_string(),
+            ),
+            ("SQS_CHAOS_SEED".to_string(), "42".to_string()),
+            (
+                "SQS_CHAOS_RECEIVEMESSAGE".to_string(),
+                "0.5:Nope".to_string(),
+            ),
+        ];
+        let chaos = chaos_from_env(vars.into_iter());
+
+        assert_eq!(chaos.len(), 2);
+        assert_eq!(
+            chaos.get("sendmessage"),
+            Some(&(0.25, SqsError::ServiceUnavailable))
+        );
+        assert_eq!(
+            chaos.get(WILDCARD),
+            Some(&(1.0, SqsError::RequestThrottled))
+        );
+    }
+
+    #[test]
+    fn test_roll_is_deterministic_with_seed() {
+        let mut chaos = HashMap::new();
+        chaos.insert(
+            "sendmessage".to_string(),
+            (0.5, SqsError::ServiceUnavailable),
+        );
+        let seed = || vec![("SQS_CHAOS_SEED".to_string(), "7".to_string())].into_iter();
+
+        let rng_a = chaos_rng_from_env(seed());
+        let rng_b = chaos_rng_from_env(seed());
+        let rolls_a: Vec<_> = (0..50)
+            .map(|_| roll(&chaos, &rng_a, "SendMessage"))
+            .collect();
+        let rolls_b: Vec<_> = (0..50)
+            .map(|_| roll(&chaos, &rng_b, "SendMessage"))
+            .collect();
+
+        assert_eq!(rolls_a, rolls_b);
+        assert!(rolls_a.iter().any(|r| r.is_some()));
+        assert!(rolls_a.iter().any(|r| r.is_none()));
+        assert_eq!(roll(&chaos, &rng_a, "ReceiveMessage"), None);
+    }
+}
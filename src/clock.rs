@@ -0,0 +1,68 @@
+//! The time queues run on: real time, or a mock clock that tests move forward through
+//! `POST /admin/advance-time` instead of sleeping.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Move the clock forward. Returns false if it follows real time and can't be moved.
+    fn advance(&self, _by: Duration) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that stands still until it is advanced, so tests decide exactly how much time
+/// passes. It starts at the real time it was created, so its instants can be compared with
+/// real ones. Real-time waits such as long polls still end by their own deadlines.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn advance(&self, by: Duration) -> bool {
+        *self.now.lock().unwrap() += by;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_advances() {
+        let clock = MockClock::default();
+        let before = clock.now();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(clock.now(), before);
+        assert!(clock.advance(Duration::from_secs(60)));
+        assert_eq!(clock.now(), before + Duration::from_secs(60));
+
+        assert!(!SystemClock.advance(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,286 @@
+//! Queues to create at startup, read from the file given with `--config`.
+//!
+//! The layout follows ElasticMQ's `queues` block, written as YAML, so existing setups carry
+//! over with little more than swapping `=` for `:`. JSON is YAML too, so a JSON file works
+//! as well:
+//!
+//! ```yaml
+//! queues:
+//!   orders-dlq: {}
+//!   orders:
+//!     defaultVisibilityTimeout: 10 seconds
+//!     delay: 5
+//!     deadLettersQueue: { name: orders-dlq, maxReceiveCount: 3 }
+//!   events.fifo:
+//!     fifo: true
+//!     contentBasedDeduplication: true
+//! ```
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::api::helpers;
+use crate::engine::SqsEngine;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub queues: BTreeMap<String, QueueConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct QueueConfig {
+    pub default_visibility_timeout: Option<Seconds>,
+    pub delay: Option<Seconds>,
+    pub receive_message_wait: Option<Seconds>,
+    #[serde(default)]
+    pub fifo: bool,
+    #[serde(default)]
+    pub content_based_deduplication: bool,
+    pub dead_letters_queue: Option<DeadLettersQueue>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DeadLettersQueue {
+    pub name: String,
+    pub max_receive_count: u32,
+}
+
+/// A duration as a number of seconds, or as text like `"10 seconds"` or `"2 minutes"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seconds(pub u64);
+
+impl<'de> Deserialize<'de> for Seconds {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(seconds) => Ok(Seconds(seconds)),
+            Raw::Text(text) => parse_duration(&text)
+                .map(Seconds)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid duration: {}", text))),
+        }
+    }
+}
+
+fn parse_duration(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let value: u64 = text[..split].parse().ok()?;
+    let multiplier = match text[split..].trim() {
+        "" | "s" | "second" | "seconds" => 1,
+        "m" | "minute" | "minutes" => 60,
+        "h" | "hour" | "hours" => 3600,
+        _ => return None,
+    };
+    value.checked_mul(multiplier)
+}
+
+impl Config {
+    /// Parse a config written as YAML, or as JSON.
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        Ok(serde_yaml_ng::from_str(yaml)?)
+    }
+
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_yaml(&yaml).map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e))
+    }
+
+    /// Create the configured queues the way CreateQueue does. Queues that already exist,
+    /// e.g. restored from the database, are left as they are. Returns how many queues
+    /// the config names.
+    pub async fn create_queues(&self, engine: &SqsEngine) -> anyhow::Result<usize> {
+        // Dead-letter queues first, so they exist by the time their sources are created
+        let mut names: Vec<&String> = self.queues.keys().collect();
+        names.sort_by_key(|name| !self.is_dead_letter_queue(name));
+
+        for name in names {
+            let queue = &self.queues[name];
+            let attributes = self.queue_attributes(name, queue, &engine.state().account_id)?;
+            engine
+                .create_queue(name, attributes, queue.tags.clone())
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to create queue {}: {}", name, e))?;
+        }
+        Ok(self.queues.len())
+    }
+
+    fn is_dead_letter_queue(&self, name: &str) -> bool {
+        self.queues.values().any(|queue| {
+            queue
+                .dead_letters_queue
+                .as_ref()
+                .is_some_and(|dlq| dlq.name == name)
+        })
+    }
+
+    /// The CreateQueue attributes of a configured queue.
+    fn queue_attributes(
+        &self,
+        name: &str,
+        queue: &QueueConfig,
+        account_id: &str,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        if !helpers::is_valid_queue_name(name) {
+            anyhow::bail!("Invalid queue name: {}", name);
+        }
+        let mut attributes = HashMap::new();
+        let seconds = [
+            ("VisibilityTimeout", queue.default_visibility_timeout),
+            ("DelaySeconds", queue.delay),
+            ("ReceiveMessageWaitTimeSeconds", queue.receive_message_wait),
+        ];
+        for (attribute, value) in seconds {
+            if let Some(Seconds(value)) = value {
+                attributes.insert(attribute.to_string(), value.to_string());
+            }
+        }
+        if queue.fifo {
+            attributes.insert("FifoQueue".to_string(), "true".to_string());
+            attributes.insert(
+                "ContentBasedDeduplication".to_string(),
+                queue.content_based_deduplication.to_string(),
+            );
+        } else if queue.content_based_deduplication {
+            anyhow::bail!(
+                "Queue {}: contentBasedDeduplication is only valid for FIFO queues",
+                name
+            );
+        }
+        if let Some(dlq) = &queue.dead_letters_queue {
+            if !self.queues.contains_key(&dlq.name) {
+                anyhow::bail!(
+                    "Queue {}: dead-letter queue {} is not configured",
+                    name,
+                    dlq.name
+                );
+            }
+            let redrive_policy = serde_json::json!({
+                "deadLetterTargetArn": helpers::queue_arn(account_id, &dlq.name),
+                "maxReceiveCount": dlq.max_receive_count.to_string(),
+            });
+            attributes.insert("RedrivePolicy".to_string(), redrive_policy.to_string());
+        }
+        Ok(attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+queues:
+  orders:
+    defaultVisibilityTimeout: 10 seconds
+    delay: 5
+    deadLettersQueue:
+      name: orders-dlq
+      maxReceiveCount: 3
+    tags:
+      team: billing
+  orders-dlq: {}
+  events.fifo:
+    fifo: true
+    contentBasedDeduplication: true
+";
+
+    async fn attributes(engine: &SqsEngine, name: &str) -> HashMap<String, String> {
+        engine
+            .get_attributes(name, &["All".to_string()])
+            .await
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_create_configured_queues() {
+        let engine = SqsEngine::in_memory().await.unwrap();
+        let config = Config::from_yaml(CONFIG).unwrap();
+        assert_eq!(config.create_queues(&engine).await.unwrap(), 3);
+
+        let orders = attributes(&engine, "orders").await;
+        assert_eq!(orders["VisibilityTimeout"], "10");
+        assert_eq!(orders["DelaySeconds"], "5");
+        let redrive: serde_json::Value = serde_json::from_str(&orders["RedrivePolicy"]).unwrap();
+        assert_eq!(
+            redrive["deadLetterTargetArn"],
+            "arn:aws:sqs:us-east-1:000000000000:orders-dlq"
+        );
+        assert_eq!(redrive["maxReceiveCount"], "3");
+
+        assert_eq!(
+            attributes(&engine, "orders-dlq").await["VisibilityTimeout"],
+            "30"
+        );
+        let events = attributes(&engine, "events.fifo").await;
+        assert_eq!(events["FifoQueue"], "true");
+        assert_eq!(events["ContentBasedDeduplication"], "true");
+        assert!(
+            engine
+                .state()
+                .get_queue("events.fifo")
+                .await
+                .unwrap()
+                .lock()
+                .await
+                .is_fifo
+        );
+
+        // Loading again leaves the queues alone
+        assert_eq!(config.create_queues(&engine).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_configs() {
+        let engine = SqsEngine::in_memory().await.unwrap();
+        for json in [
+            r#"{ "queues": { "bad name": {} } }"#,
+            r#"{ "queues": { "events": { "fifo": true } } }"#,
+            r#"{ "queues": { "q": { "contentBasedDeduplication": true } } }"#,
+            r#"{ "queues": { "q": { "deadLettersQueue": { "name": "missing", "maxReceiveCount": 3 } } } }"#,
+            r#"{ "queues": { "q": { "delay": 901 } } }"#,
+        ] {
+            let config = Config::from_yaml(json).unwrap();
+            assert!(config.create_queues(&engine).await.is_err(), "{}", json);
+        }
+
+        for json in [
+            r#"{ "queues": { "q": { "visibilityTimeout": 5 } } }"#,
+            r#"{ "queues": { "q": { "delay": "5 fortnights" } } }"#,
+            r#"{ "queues": { "q": { "delay": "18446744073709551615 hours" } } }"#,
+        ] {
+            assert!(Config::from_yaml(json).is_err(), "{}", json);
+        }
+        assert!(engine.state().queues.lock().await.is_empty());
+    }
+
+    #[test]
+    fn test_json_is_accepted() {
+        let json = r#"{
+            "queues": {
+                "orders": { "defaultVisibilityTimeout": "10 seconds", "tags": { "team": "billing" } },
+                "events.fifo": { "fifo": true }
+            }
+        }"#;
+        let config = Config::from_yaml(json).unwrap();
+        let orders = &config.queues["orders"];
+        assert_eq!(orders.default_visibility_timeout, Some(Seconds(10)));
+        assert_eq!(orders.tags["team"], "billing");
+        assert!(config.queues["events.fifo"].fifo);
+    }
+}
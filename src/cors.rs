@@ -0,0 +1,187 @@
+//! CORS headers for browser-based clients, only sent when the server runs with
+//! `--cors-allow-origin`.
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, Method},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+
+/// Headers the AWS SDKs send with a request, allowed unless `--cors-allow-headers` says otherwise.
+pub const DEFAULT_ALLOW_HEADERS: &str = "Content-Type, Content-Encoding, Authorization, X-Amz-Date, X-Amz-Target, X-Amz-Security-Token, X-Amz-User-Agent, Amz-Sdk-Invocation-Id, Amz-Sdk-Request";
+
+pub const DEFAULT_ALLOW_METHODS: &str = "GET, POST, OPTIONS";
+
+/// How long browsers may cache a preflight response, in seconds.
+const MAX_AGE: &str = "600";
+
+#[derive(Debug, Clone)]
+pub struct Cors {
+    /// Origins allowed to call the mock; `*` allows any
+    pub allow_origins: Vec<String>,
+    pub allow_methods: String,
+    pub allow_headers: String,
+}
+
+impl Cors {
+    pub fn new(allow_origins: Vec<String>) -> Self {
+        Cors {
+            allow_origins,
+            allow_methods: DEFAULT_ALLOW_METHODS.to_string(),
+            allow_headers: DEFAULT_ALLOW_HEADERS.to_string(),
+        }
+    }
+
+    /// The `Access-Control-Allow-Origin` value for a request from `origin`, if it is allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.allow_origins.iter().any(|o| o == "*") {
+            Some("*".to_string())
+        } else {
+            self.allow_origins
+                .iter()
+                .find(|o| o.eq_ignore_ascii_case(origin))
+                .cloned()
+        }
+    }
+}
+
+/// Middleware adding CORS headers when a [`Cors`] is registered as app data, and
+/// answering preflight `OPTIONS` requests itself. Without one, requests pass through
+/// untouched.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let cors = req.app_data::<web::Data<Cors>>().cloned();
+    let origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|o| o.to_str().ok())
+        .map(str::to_string);
+    let allow_origin = match (cors.as_deref(), origin) {
+        (Some(cors), Some(origin)) => cors.allow_origin(&origin),
+        _ => None,
+    };
+    let (Some(cors), Some(allow_origin)) = (cors, allow_origin) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let is_preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if is_preflight {
+        let resp = HttpResponse::NoContent()
+            .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin))
+            .insert_header((
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                cors.allow_methods.as_str(),
+            ))
+            .insert_header((
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                cors.allow_headers.as_str(),
+            ))
+            .insert_header((header::ACCESS_CONTROL_MAX_AGE, MAX_AGE))
+            .insert_header((header::VARY, "Origin"))
+            .finish();
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+
+    let mut resp = next.call(req).await?;
+    let headers = resp.headers_mut();
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        header::HeaderValue::from_str(&allow_origin)?,
+    );
+    headers.insert(header::VARY, header::HeaderValue::from_static("Origin"));
+    Ok(resp.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware::from_fn, test, App};
+
+    macro_rules! app {
+        ($app:expr) => {
+            test::init_service(
+                $app.route("/", web::post().to(HttpResponse::Ok))
+                    .wrap(from_fn(middleware)),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn test_allow_origin_when_configured() {
+        let cors = Cors::new(vec!["http://localhost:3000".to_string()]);
+        let app = app!(App::new().app_data(web::Data::new(cors)));
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://localhost:3000"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "http://localhost:3000"
+        );
+
+        // Other origins get no CORS headers, so the browser blocks the response
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://evil.example"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[actix_web::test]
+    async fn test_preflight() {
+        let cors = Cors::new(vec!["*".to_string()]);
+        let app = app!(App::new().app_data(web::Data::new(cors)));
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://localhost:3000"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "*"
+        );
+        assert_eq!(
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_METHODS)
+                .unwrap(),
+            DEFAULT_ALLOW_METHODS
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_off_by_default() {
+        let app = app!(App::new());
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header((header::ORIGIN, "http://localhost:3000"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert!(!resp
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
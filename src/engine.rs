@@ -0,0 +1,1128 @@
+//! The queue engine: SQS actions on typed arguments, without HTTP. The handlers in `api`
+//! parse requests, call into the engine and render its results, and other Rust code can
+//! drive the mock in-process the same way:
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use sqs_navbat::{engine::{ReceiveOptions, SqsEngine}, queue::Message};
+//!
+//! let engine = SqsEngine::in_memory().await?;
+//! engine.create_queue("jobs", Default::default(), Default::default()).await?;
+//! engine.send("jobs", Message::new("1".into(), "hello".into()), None).await?;
+//! let received = engine.receive("jobs", &ReceiveOptions::default()).await?;
+//! engine.delete("jobs", received[0].receipt_handle.as_deref().unwrap()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::futures::OwnedNotified;
+
+use crate::{
+    api::helpers,
+    error::SqsError,
+    move_task::{MoveTask, MoveTaskStatus},
+    queue::{ChangeVisibilityError, Message},
+    AppState, SharedQueue,
+};
+
+/// Attributes every queue reports, with the AWS default used until they are set.
+const DEFAULT_ATTRIBUTES: [(&str, &str); 4] = [
+    ("DelaySeconds", "0"),
+    ("MessageRetentionPeriod", "345600"),
+    ("MaximumMessageSize", "262144"),
+    ("ReceiveMessageWaitTimeSeconds", "0"),
+];
+
+/// Attributes FIFO queues report on top of `DEFAULT_ATTRIBUTES`.
+const FIFO_DEFAULT_ATTRIBUTES: [(&str, &str); 4] = [
+    ("FifoQueue", "true"),
+    ("ContentBasedDeduplication", "false"),
+    ("DeduplicationScope", "queue"),
+    ("FifoThroughputLimit", "perQueue"),
+];
+
+/// Longest DelaySeconds a message or queue may have
+const MAX_DELAY_SECONDS: u32 = 900;
+
+/// Most messages a single receive may return
+const MAX_NUMBER_OF_MESSAGES: u32 = 10;
+
+#[derive(Debug, Clone)]
+pub struct ReceiveOptions {
+    /// 1 to 10
+    pub max_number_of_messages: u32,
+    /// Overrides the queue's VisibilityTimeout for the received messages
+    pub visibility_timeout: Option<u32>,
+    /// How long to wait for a message when none is visible; zero for a short poll
+    pub wait_time: Duration,
+    /// FIFO only: retries with the same id return the same messages
+    pub receive_request_attempt_id: Option<String>,
+}
+
+impl Default for ReceiveOptions {
+    /// One message, short poll, the queue's visibility timeout.
+    fn default() -> Self {
+        ReceiveOptions {
+            max_number_of_messages: 1,
+            visibility_timeout: None,
+            wait_time: Duration::ZERO,
+            receive_request_attempt_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqsEngine {
+    state: Arc<AppState>,
+}
+
+impl SqsEngine {
+    pub fn new(state: Arc<AppState>) -> Self {
+        SqsEngine { state }
+    }
+
+    /// An engine with default settings over a fresh in-memory database.
+    pub async fn in_memory() -> anyhow::Result<Self> {
+        Ok(SqsEngine::new(Arc::new(AppState::in_memory().await?)))
+    }
+
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    async fn queue(&self, queue_name: &str) -> Result<SharedQueue, SqsError> {
+        self.state
+            .get_queue(queue_name)
+            .await
+            .ok_or_else(|| SqsError::NonExistentQueue(queue_name.to_string()))
+    }
+
+    /// Create a queue, or return the URL of the existing queue with that name.
+    pub async fn create_queue(
+        &self,
+        queue_name: &str,
+        attributes: HashMap<String, String>,
+        tags: HashMap<String, String>,
+    ) -> Result<String, SqsError> {
+        validate_queue_attributes(&attributes)?;
+        if let Some(redrive_policy) = attributes.get("RedrivePolicy") {
+            self.check_redrive_allowed(queue_name, redrive_policy)
+                .await?;
+        }
+        let is_fifo = attributes.get("FifoQueue").is_some_and(|v| v == "true");
+        if is_fifo != queue_name.ends_with(".fifo") {
+            return Err(SqsError::InvalidParameterValue(if is_fifo {
+                "The name of a FIFO queue can only include alphanumeric characters, hyphens, or underscores, must end with .fifo suffix and be 1 to 80 in length.".to_string()
+            } else {
+                "Only FIFO queues, created with the attribute FifoQueue set to true, can have names ending in .fifo.".to_string()
+            }));
+        }
+
+        // Hold the map lock from the existence check through the DB write and the insert,
+        // so concurrent creates of one name resolve to a single queue
+        let mut queues = self.state.queues.lock().await;
+        if queues.contains_key(queue_name) {
+            return Ok(self.state.queue_url(queue_name));
+        }
+
+        let service = crate::service::queue::Queue::new(&self.state.db_pool);
+        service
+            .create_queue(crate::service::queue::QueueEntity {
+                id: None,
+                name: queue_name.to_string(),
+                queue_type: if is_fifo { "Fifo" } else { "Standard" }.to_string(),
+                attributes: Some(attributes.clone()),
+                tags: Some(tags.clone()),
+                created_at: None,
+                updated_at: None,
+            })
+            .await
+            .map_err(|e| SqsError::InternalFailure(format!("Failed to create queue: {}", e)))?;
+
+        let mut queue = self.state.build_queue(queue_name, is_fifo, &tags);
+        self.state.apply_queue_attributes(&mut queue, &attributes);
+
+        queues.insert(
+            queue_name.to_string(),
+            Arc::new(tokio::sync::Mutex::new(queue)),
+        );
+        Ok(self.state.queue_url(queue_name))
+    }
+
+    /// Send a message, delayed by `delay_seconds` or else the queue's DelaySeconds.
+    /// Returns the message as stored, with its FIFO sequence number.
+    pub async fn send(
+        &self,
+        queue_name: &str,
+        message: Message,
+        delay_seconds: Option<u32>,
+    ) -> Result<Message, SqsError> {
+        let mut results = self
+            .send_batch(queue_name, vec![(message, delay_seconds)])
+            .await?;
+        results.remove(0)
+    }
+
+    /// Send several messages as one request against the queue's rate limit, each delayed
+    /// by its own delay or else the queue's DelaySeconds. Messages fail independently,
+    /// with a result per message in order; the outer error fails the whole request.
+    pub async fn send_batch(
+        &self,
+        queue_name: &str,
+        messages: Vec<(Message, Option<u32>)>,
+    ) -> Result<Vec<Result<Message, SqsError>>, SqsError> {
+        let queue = self.queue(queue_name).await?;
+        let mut queue = queue.lock().await;
+        if !queue.try_acquire_request() {
+            return Err(SqsError::RequestThrottled);
+        }
+        let results: Vec<_> = messages
+            .into_iter()
+            .map(|(message, delay_seconds)| push_message(&mut queue, message, delay_seconds))
+            .collect();
+        drop(queue);
+
+        if let Some(observer) = &self.state.observer {
+            for stored in results.iter().flatten() {
+                observer.on_send(queue_name, stored);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Receive up to `max_number_of_messages` messages, waiting up to `wait_time` for the
+    /// first one to become visible.
+    ///
+    /// The queue is looked up once, when the request arrives. A receive on a missing queue
+    /// fails straight away even if the queue is created while it would have waited, and a
+    /// queue removed mid-wait is still polled until the wait runs out, which then returns
+    /// no messages since nothing can be sent to it any more.
+    pub async fn receive(
+        &self,
+        queue_name: &str,
+        options: &ReceiveOptions,
+    ) -> Result<Vec<Message>, SqsError> {
+        let max = options.max_number_of_messages;
+        if !(1..=MAX_NUMBER_OF_MESSAGES).contains(&max) {
+            return Err(SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter MaxNumberOfMessages is invalid. Reason: must be between 1 and {}.",
+                max, MAX_NUMBER_OF_MESSAGES
+            )));
+        }
+
+        let queue = self.queue(queue_name).await?;
+        if !queue.lock().await.try_acquire_request() {
+            return Err(SqsError::RequestThrottled);
+        }
+
+        // Short poll: a single receive attempt, no deadline or wake-up bookkeeping
+        let messages = if options.wait_time.is_zero() {
+            let mut queue = queue.lock().await;
+            let messages = receive_once(&mut queue, options, max);
+            if messages.is_empty() && queue.is_at_in_flight_limit() {
+                return Err(SqsError::OverLimit);
+            }
+            messages
+        } else {
+            let deadline = tokio::time::Instant::now() + options.wait_time;
+            loop {
+                let (wake, next_visible_in) = match poll_once(&queue, options, max).await {
+                    Attempt::Received(messages) => break messages,
+                    Attempt::OverLimit => return Err(SqsError::OverLimit),
+                    Attempt::Wait(wake, next_visible_in) => (wake, next_visible_in),
+                };
+
+                if tokio::time::Instant::now() >= deadline {
+                    break Vec::new();
+                }
+                // Nothing is pushed when a delay or visibility timeout runs out, so also
+                // wake up by the time the next message becomes visible
+                let wake_at = next_visible_in
+                    .map(|after| tokio::time::Instant::now() + after)
+                    .map_or(deadline, |at| at.min(deadline));
+                let _ = tokio::time::timeout_at(wake_at, wake).await;
+            }
+        };
+
+        if let Some(observer) = &self.state.observer {
+            for message in &messages {
+                observer.on_receive(queue_name, message);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Delete the message received with `receipt_handle`.
+    pub async fn delete(&self, queue_name: &str, receipt_handle: &str) -> Result<(), SqsError> {
+        let message_id = self.check_receipt_handle(queue_name, receipt_handle)?;
+        let queue = self.queue(queue_name).await?;
+        if !queue.lock().await.delete_by_receipt_handle(receipt_handle) {
+            return Err(SqsError::ReceiptHandleIsInvalid);
+        }
+        if let Some(observer) = &self.state.observer {
+            observer.on_delete(queue_name, &message_id);
+        }
+        Ok(())
+    }
+
+    /// Make the message received with `receipt_handle` visible again `timeout` seconds
+    /// from now.
+    pub async fn change_visibility(
+        &self,
+        queue_name: &str,
+        receipt_handle: &str,
+        timeout: u32,
+    ) -> Result<(), SqsError> {
+        self.check_receipt_handle(queue_name, receipt_handle)?;
+        let queue = self.queue(queue_name).await?;
+        let changed = queue
+            .lock()
+            .await
+            .change_visibility(receipt_handle, timeout);
+        changed.map_err(|e| match e {
+            ChangeVisibilityError::NotFound => SqsError::ReceiptHandleIsInvalid,
+            ChangeVisibilityError::LimitExceeded => SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter VisibilityTimeout is invalid. Reason: Total VisibilityTimeout for the message is beyond the limit [{} seconds]",
+                timeout,
+                crate::queue::MAX_VISIBILITY_TIMEOUT
+            )),
+        })
+    }
+
+    /// Reject handles we could never have issued, or issued for another queue, without
+    /// touching the queue. Returns the id of the message the handle was issued for.
+    fn check_receipt_handle(
+        &self,
+        queue_name: &str,
+        receipt_handle: &str,
+    ) -> Result<String, SqsError> {
+        match crate::queue::decode_receipt_handle(receipt_handle) {
+            Some(handle) if handle.queue_name == queue_name => Ok(handle.message_id),
+            _ => Err(SqsError::ReceiptHandleIsInvalid),
+        }
+    }
+
+    /// Get queue attributes as `(name, value)` pairs. An empty `names`, `All` or `.*`
+    /// asks for every attribute.
+    pub async fn get_attributes(
+        &self,
+        queue_name: &str,
+        names: &[String],
+    ) -> Result<Vec<(String, String)>, SqsError> {
+        let want_all = names.is_empty() || helpers::wants_all(names);
+        let wants = |name: &str| want_all || names.iter().any(|n| n == name);
+
+        // Check the queue exists before going to the DB, so a missing queue is
+        // reported the same way as in every other action
+        let queue = self.queue(queue_name).await?;
+
+        let service = crate::service::queue::Queue::new(&self.state.db_pool);
+        let db_attrs = service
+            .get_queue_attributes(queue_name)
+            .await
+            .map_err(|e| {
+                SqsError::InternalFailure(format!("Failed to load queue attributes: {}", e))
+            })?;
+
+        let mut queue = queue.lock().await;
+        let now = queue.now();
+        queue.expire_messages(now);
+        let counts = queue.counts();
+        let mut attrs = Vec::new();
+
+        // Always-available computed attributes
+        let computed = [
+            ("ApproximateNumberOfMessages", counts.visible.to_string()),
+            (
+                "ApproximateNumberOfMessagesNotVisible",
+                counts.not_visible.to_string(),
+            ),
+            (
+                "ApproximateNumberOfMessagesDelayed",
+                counts.delayed.to_string(),
+            ),
+            (
+                "VisibilityTimeout",
+                queue.default_visibility_timeout.to_string(),
+            ),
+            (
+                "QueueArn",
+                helpers::queue_arn(&self.state.account_id, queue_name),
+            ),
+        ];
+        for (name, value) in computed {
+            if wants(name) {
+                attrs.push((name.to_string(), value));
+            }
+        }
+
+        // JSON-valued ones such as RedrivePolicy and Policy are passed on as stored
+        for (name, value) in &db_attrs {
+            // Don't duplicate VisibilityTimeout if already added from computed
+            if wants(name) && name != "VisibilityTimeout" {
+                attrs.push((name.clone(), value.clone()));
+            }
+        }
+
+        let fifo_defaults = if queue.is_fifo {
+            &FIFO_DEFAULT_ATTRIBUTES[..]
+        } else {
+            &[]
+        };
+        for &(name, default) in DEFAULT_ATTRIBUTES.iter().chain(fifo_defaults) {
+            if wants(name) && !db_attrs.contains_key(name) {
+                attrs.push((name.to_string(), default.to_string()));
+            }
+        }
+
+        Ok(attrs)
+    }
+
+    /// Move the messages of the dead-letter queue `source_arn` to `destination_arn`, or
+    /// else back to the queue whose RedrivePolicy targets it. Messages arrive as new,
+    /// never-received messages. Returns the finished task.
+    pub async fn start_message_move_task(
+        &self,
+        source_arn: &str,
+        destination_arn: Option<&str>,
+        max_number_of_messages_per_second: Option<u32>,
+    ) -> Result<MoveTask, SqsError> {
+        let source_name = helpers::queue_name_from_arn(source_arn).ok_or_else(|| {
+            SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter SourceArn is invalid.",
+                source_arn
+            ))
+        })?;
+        if let Some(rate) = max_number_of_messages_per_second.filter(|r| !(1..=500).contains(r)) {
+            return Err(SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter MaxNumberOfMessagesPerSecond is invalid. Reason: must be between 1 and 500.",
+                rate
+            )));
+        }
+        let source = self.queue(source_name).await?;
+
+        let redrive_sources = self.redrive_sources(source_name).await?;
+        if redrive_sources.is_empty() {
+            return Err(SqsError::InvalidParameterValue(
+                "Source queue must be configured as a Dead Letter Queue.".to_string(),
+            ));
+        }
+        let destination_name = match destination_arn {
+            Some(arn) => helpers::queue_name_from_arn(arn)
+                .ok_or_else(|| {
+                    SqsError::InvalidParameterValue(format!(
+                        "Value {} for parameter DestinationArn is invalid.",
+                        arn
+                    ))
+                })?
+                .to_string(),
+            None if redrive_sources.len() == 1 => redrive_sources[0].clone(),
+            None => {
+                return Err(SqsError::InvalidParameterValue(
+                    "DestinationArn is required when several queues use the source as their dead-letter queue.".to_string(),
+                ))
+            }
+        };
+        if destination_name == source_name {
+            return Err(SqsError::InvalidParameterValue(
+                "Source and destination queues must be different.".to_string(),
+            ));
+        }
+        let destination = self.queue(&destination_name).await?;
+
+        // Lock in name order, so two moves between the same queues can't deadlock
+        let (mut source, mut destination) = if source_name < destination_name.as_str() {
+            let source = source.lock().await;
+            (source, destination.lock().await)
+        } else {
+            let destination = destination.lock().await;
+            (source.lock().await, destination)
+        };
+        if source.is_fifo != destination.is_fifo {
+            return Err(SqsError::InvalidParameterValue(
+                "Source and destination queues must be of the same type.".to_string(),
+            ));
+        }
+
+        let mut task = MoveTask::start(
+            source_arn,
+            destination_arn,
+            max_number_of_messages_per_second,
+        );
+        task.approximate_number_of_messages_to_move = source.messages().count() as u64;
+        while !destination.is_full() {
+            let Some(moved) = source.pop_front() else {
+                break;
+            };
+            let mut message = Message::new(moved.id, moved.message_body);
+            message.message_group_id = moved.message_group_id;
+            message.message_deduplication_id = moved.message_deduplication_id;
+            message.message_attributes = moved.message_attributes;
+            message.message_system_attributes = moved.message_system_attributes;
+            destination.push(message);
+            task.approximate_number_of_messages_moved += 1;
+        }
+        if source.messages().next().is_some() {
+            task.status = MoveTaskStatus::Failed;
+            task.failure_reason = Some(SqsError::QueueFull.code().to_string());
+        } else {
+            task.status = MoveTaskStatus::Completed;
+        }
+        drop((source, destination));
+
+        self.state
+            .move_tasks
+            .lock()
+            .await
+            .insert(task.task_handle.clone(), task.clone());
+        Ok(task)
+    }
+
+    /// The most recent move tasks out of `source_arn`, newest first, at most `max_results`.
+    pub async fn list_message_move_tasks(
+        &self,
+        source_arn: &str,
+        max_results: u32,
+    ) -> Result<Vec<MoveTask>, SqsError> {
+        if !(1..=10).contains(&max_results) {
+            return Err(SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter MaxResults is invalid. Reason: must be between 1 and 10.",
+                max_results
+            )));
+        }
+        let source_name = helpers::queue_name_from_arn(source_arn).ok_or_else(|| {
+            SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter SourceArn is invalid.",
+                source_arn
+            ))
+        })?;
+        self.queue(source_name).await?;
+
+        let mut tasks: Vec<MoveTask> = self
+            .state
+            .move_tasks
+            .lock()
+            .await
+            .values()
+            .filter(|task| task.source_arn == source_arn)
+            .cloned()
+            .collect();
+        tasks.sort_by_key(|task| std::cmp::Reverse(task.started_timestamp));
+        tasks.truncate(max_results as usize);
+        Ok(tasks)
+    }
+
+    /// Cancel a running move task. Messages already moved stay where they are.
+    /// Returns the cancelled task.
+    pub async fn cancel_message_move_task(&self, task_handle: &str) -> Result<MoveTask, SqsError> {
+        let mut tasks = self.state.move_tasks.lock().await;
+        let task = tasks.get_mut(task_handle).ok_or_else(|| {
+            SqsError::ResourceNotFound(format!("Task does not exist: {}", task_handle))
+        })?;
+        if task.status != MoveTaskStatus::Running {
+            return Err(SqsError::InvalidParameterValue(format!(
+                "Only a RUNNING task can be cancelled; this one is {}.",
+                task.status.as_str()
+            )));
+        }
+        task.status = MoveTaskStatus::Cancelled;
+        Ok(task.clone())
+    }
+
+    /// Check that the dead-letter queue `redrive_policy` targets lets `source_queue` use
+    /// it, according to the dead-letter queue's RedriveAllowPolicy. Targets that don't
+    /// exist have no policy, so are allowed.
+    pub async fn check_redrive_allowed(
+        &self,
+        source_queue: &str,
+        redrive_policy: &str,
+    ) -> Result<(), SqsError> {
+        let Some(dead_letter_queue) = serde_json::from_str::<serde_json::Value>(redrive_policy)
+            .ok()
+            .and_then(|policy| {
+                policy["deadLetterTargetArn"]
+                    .as_str()
+                    .and_then(helpers::queue_name_from_arn)
+                    .map(str::to_string)
+            })
+        else {
+            return Ok(());
+        };
+        let service = crate::service::queue::Queue::new(&self.state.db_pool);
+        let attributes = service
+            .get_queue_attributes(&dead_letter_queue)
+            .await
+            .map_err(|e| {
+                SqsError::InternalFailure(format!("Failed to load queue attributes: {}", e))
+            })?;
+        let source_arn = helpers::queue_arn(&self.state.account_id, source_queue);
+        match attributes.get("RedriveAllowPolicy") {
+            Some(allow) if !helpers::redrive_allows(allow, &source_arn) => {
+                Err(SqsError::InvalidParameterValue(format!(
+                    "Value {} for parameter RedrivePolicy is invalid. Reason: Queue {} does not allow {} as a source queue.",
+                    redrive_policy, dead_letter_queue, source_arn
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Names of the queues whose RedrivePolicy sends messages to `dead_letter_queue`.
+    async fn redrive_sources(&self, dead_letter_queue: &str) -> Result<Vec<String>, SqsError> {
+        let names: Vec<String> = self.state.queues.lock().await.keys().cloned().collect();
+        let service = crate::service::queue::Queue::new(&self.state.db_pool);
+        let mut sources = Vec::new();
+        for name in names {
+            let attributes = service.get_queue_attributes(&name).await.map_err(|e| {
+                SqsError::InternalFailure(format!("Failed to load queue attributes: {}", e))
+            })?;
+            let target = attributes
+                .get("RedrivePolicy")
+                .and_then(|policy| serde_json::from_str::<serde_json::Value>(policy).ok())
+                .and_then(|policy| policy["deadLetterTargetArn"].as_str().map(str::to_string));
+            if target
+                .as_deref()
+                .and_then(helpers::queue_name_from_arn)
+                .is_some_and(|target| target == dead_letter_queue)
+            {
+                sources.push(name);
+            }
+        }
+        sources.sort();
+        Ok(sources)
+    }
+}
+
+/// Check one message of a send against the locked `queue` and push it. Returns the message
+/// as stored, with its FIFO sequence number.
+fn push_message(
+    queue: &mut crate::queue::Queue,
+    mut message: Message,
+    delay_seconds: Option<u32>,
+) -> Result<Message, SqsError> {
+    helpers::validate_message_body(&message.message_body)
+        .map_err(SqsError::InvalidMessageContents)?;
+    if let Some(delay) = delay_seconds.filter(|d| *d > MAX_DELAY_SECONDS) {
+        return Err(SqsError::InvalidParameterValue(format!(
+            "Value {} for parameter DelaySeconds is invalid. Reason: must be between 0 and {}.",
+            delay, MAX_DELAY_SECONDS
+        )));
+    }
+    if queue.is_fifo {
+        if message.message_group_id.is_none() {
+            return Err(SqsError::MissingParameter(
+                "The request must contain the parameter MessageGroupId.".to_string(),
+            ));
+        }
+        // FIFO queues only support the queue-level delay
+        if let Some(delay) = delay_seconds {
+            return Err(SqsError::InvalidParameterValue(format!(
+                "Value {} for parameter DelaySeconds is invalid. Reason: The request include parameter that is not valid for this queue type.",
+                delay
+            )));
+        }
+    } else {
+        message.message_group_id = None;
+        message.message_deduplication_id = None;
+    }
+    check_message_size(queue.name(), &message, queue.maximum_message_size)?;
+
+    let delay = delay_seconds.unwrap_or(queue.default_delay_seconds);
+    if !queue.push_with_delay(message, delay) {
+        return Err(SqsError::QueueFull);
+    }
+    // The queue assigns FIFO sequence numbers on push
+    queue
+        .messages()
+        .last()
+        .cloned()
+        .ok_or_else(|| SqsError::InternalFailure("Sent message went missing".to_string()))
+}
+
+/// Check that `message`, body and attributes together, fits in `maximum_message_size` bytes.
+fn check_message_size(
+    queue_name: &str,
+    message: &Message,
+    maximum_message_size: usize,
+) -> Result<(), SqsError> {
+    let size = message.size();
+    if size <= maximum_message_size {
+        return Ok(());
+    }
+    tracing::warn!(
+        "Rejected a {} byte message to {}, whose MaximumMessageSize is {}",
+        size,
+        queue_name,
+        maximum_message_size
+    );
+    Err(SqsError::InvalidParameterValue(format!(
+        "One or more parameters are invalid. Reason: Message must be shorter than {} bytes.",
+        maximum_message_size
+    )))
+}
+
+/// Check queue attribute values, and that FIFO-only attributes are only set on FIFO
+/// queues.
+pub fn validate_queue_attributes(attributes: &HashMap<String, String>) -> Result<(), SqsError> {
+    for (name, value) in attributes {
+        helpers::validate_attribute_value(name, value)
+            .map_err(|e| SqsError::InvalidAttributeValue(e.to_string()))?;
+    }
+
+    let is_fifo = attributes.get("FifoQueue").is_some_and(|v| v == "true");
+    match attributes
+        .keys()
+        .find(|name| helpers::FIFO_ONLY_ATTRIBUTES.contains(&name.as_str()))
+    {
+        Some(name) if !is_fifo => Err(SqsError::InvalidAttributeName(format!(
+            "Attribute {} is only valid for FIFO queues",
+            name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+enum Attempt {
+    Received(Vec<Message>),
+    /// There are messages, but the queue's in-flight limit keeps them from being received
+    OverLimit,
+    /// Nothing to receive; resolves on the queue's next push or delete. Also has how soon
+    /// a message may become visible, if any is delayed or in flight. That is a duration
+    /// rather than an instant, since the queue's clock may not follow real time.
+    Wait(Pin<Box<OwnedNotified>>, Option<std::time::Duration>),
+}
+
+/// One long-poll attempt. The queue lock lives only inside this function, so
+/// callers can't accidentally hold it while they wait.
+async fn poll_once(queue: &SharedQueue, options: &ReceiveOptions, max: u32) -> Attempt {
+    let mut guard = queue.lock().await;
+    let received = receive_once(&mut guard, options, max);
+    if !received.is_empty() {
+        return Attempt::Received(received);
+    }
+    if guard.is_at_in_flight_limit() {
+        return Attempt::OverLimit;
+    }
+
+    // Subscribe before the lock is released so a concurrent push can't slip past us
+    let mut wake = Box::pin(guard.notifier().notified_owned());
+    wake.as_mut().enable();
+    let now = guard.now();
+    let next_visible_in = guard
+        .next_visible_at()
+        .map(|at| at.saturating_duration_since(now));
+    Attempt::Wait(wake, next_visible_in)
+}
+
+/// One receive attempt, honouring `ReceiveRequestAttemptId` on FIFO queues.
+fn receive_once(
+    queue: &mut crate::queue::Queue,
+    options: &ReceiveOptions,
+    max: u32,
+) -> Vec<Message> {
+    match &options.receive_request_attempt_id {
+        Some(attempt_id) if queue.is_fifo => {
+            queue.receive_with_attempt_id(attempt_id, max, options.visibility_timeout)
+        }
+        _ => queue.receive(max, options.visibility_timeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn engine_with_queue(name: &str, attributes: &[(&str, &str)]) -> SqsEngine {
+        let engine = SqsEngine::in_memory().await.unwrap();
+        let attributes = attributes
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        engine
+            .create_queue(name, attributes, HashMap::new())
+            .await
+            .unwrap();
+        engine
+    }
+
+    #[tokio::test]
+    async fn test_send_receive_delete() {
+        let engine = engine_with_queue("q", &[]).await;
+        let sent = engine
+            .send("q", Message::new("1".to_owned(), "hello".to_owned()), None)
+            .await
+            .unwrap();
+        assert_eq!(sent.id, "1");
+
+        let received = engine
+            .receive("q", &ReceiveOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].message_body, "hello");
+        let handle = received[0].receipt_handle.clone().unwrap();
+
+        // In flight, so a second receive comes back empty
+        assert!(engine
+            .receive("q", &ReceiveOptions::default())
+            .await
+            .unwrap()
+            .is_empty());
+
+        engine.delete("q", &handle).await.unwrap();
+        assert_eq!(
+            engine.delete("q", "not a handle").await,
+            Err(SqsError::ReceiptHandleIsInvalid)
+        );
+        let counts = engine.queue("q").await.unwrap().lock().await.counts();
+        assert_eq!(counts, Default::default());
+    }
+
+    #[tokio::test]
+    async fn test_change_visibility() {
+        let engine = engine_with_queue("q", &[]).await;
+        engine
+            .send("q", Message::new("1".to_owned(), "hi".to_owned()), None)
+            .await
+            .unwrap();
+        let received = engine
+            .receive("q", &ReceiveOptions::default())
+            .await
+            .unwrap();
+        let handle = received[0].receipt_handle.clone().unwrap();
+
+        engine.change_visibility("q", &handle, 0).await.unwrap();
+        let received = engine
+            .receive("q", &ReceiveOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        let handle = received[0].receipt_handle.clone().unwrap();
+        assert!(matches!(
+            engine.change_visibility("q", &handle, 43201).await,
+            Err(SqsError::InvalidParameterValue(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_redrive_allow_policy() {
+        let engine = SqsEngine::new(Arc::new(AppState::for_tests_with_db().await));
+        let redrive_to = |dlq: &str| {
+            HashMap::from([(
+                "RedrivePolicy".to_string(),
+                format!(
+                    r#"{{"deadLetterTargetArn":"arn:aws:sqs:us-east-1:000000000000:{}","maxReceiveCount":"3"}}"#,
+                    dlq
+                ),
+            )])
+        };
+        for (dlq, allow) in [
+            ("open-dlq", r#"{"redrivePermission":"allowAll"}"#),
+            ("closed-dlq", r#"{"redrivePermission":"denyAll"}"#),
+            (
+                "orders-dlq",
+                r#"{"redrivePermission":"byQueue","sourceQueueArns":["arn:aws:sqs:us-east-1:000000000000:orders"]}"#,
+            ),
+        ] {
+            engine
+                .create_queue(
+                    dlq,
+                    HashMap::from([("RedriveAllowPolicy".to_string(), allow.to_string())]),
+                    HashMap::new(),
+                )
+                .await
+                .unwrap();
+        }
+
+        for (source, dlq, allowed) in [
+            ("a", "open-dlq", true),
+            ("b", "closed-dlq", false),
+            ("orders", "orders-dlq", true),
+            ("other", "orders-dlq", false),
+            // No policy at all
+            ("c", "missing-dlq", true),
+        ] {
+            let created = engine
+                .create_queue(source, redrive_to(dlq), HashMap::new())
+                .await;
+            assert_eq!(created.is_ok(), allowed, "{} -> {}", source, dlq);
+            if !allowed {
+                assert!(matches!(created, Err(SqsError::InvalidParameterValue(_))));
+                assert!(engine.queue(source).await.is_err());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_max_receive_count_drops_poison_messages() {
+        let state = AppState {
+            default_max_receive_count: Some(1),
+            ..AppState::for_tests_with_db().await
+        };
+        let engine = SqsEngine::new(Arc::new(state));
+        let redrive = r#"{"deadLetterTargetArn":"arn:aws:sqs:us-east-1:000000000000:dlq","maxReceiveCount":"5"}"#;
+        for (name, attributes) in [
+            ("plain", HashMap::new()),
+            (
+                "redriven",
+                HashMap::from([("RedrivePolicy".to_string(), redrive.to_string())]),
+            ),
+        ] {
+            engine
+                .create_queue(name, attributes, HashMap::new())
+                .await
+                .unwrap();
+            engine
+                .send(name, Message::new("1".to_owned(), "hi".to_owned()), None)
+                .await
+                .unwrap();
+        }
+
+        let peek = ReceiveOptions {
+            visibility_timeout: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(engine.receive("plain", &peek).await.unwrap().len(), 1);
+        assert!(engine.receive("plain", &peek).await.unwrap().is_empty());
+        let plain = engine.queue("plain").await.unwrap();
+        assert_eq!(plain.lock().await.messages().count(), 0);
+
+        // The queue's own RedrivePolicy wins over the server default
+        for _ in 0..2 {
+            assert_eq!(engine.receive("redriven", &peek).await.unwrap().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_long_poll_wakes_when_visibility_expires() {
+        let engine = engine_with_queue("q", &[]).await;
+        engine
+            .send("q", Message::new("1".to_owned(), "hi".to_owned()), None)
+            .await
+            .unwrap();
+        let in_flight = ReceiveOptions {
+            visibility_timeout: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(engine.receive("q", &in_flight).await.unwrap().len(), 1);
+
+        // Nothing is sent; the message just comes back once its second is up
+        let options = ReceiveOptions {
+            wait_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let started = tokio::time::Instant::now();
+        let received = engine.receive("q", &options).await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].receive_count, 2);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_long_poll_wakes_on_send() {
+        let engine = engine_with_queue("q", &[]).await;
+        let options = ReceiveOptions {
+            wait_time: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        let (received, _) = tokio::join!(engine.receive("q", &options), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            engine
+                .send("q", Message::new("1".to_owned(), "hi".to_owned()), None)
+                .await
+                .unwrap();
+        });
+        assert_eq!(received.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_long_poll_resolves_queue_once() {
+        let engine = engine_with_queue("q", &[]).await;
+        let options = ReceiveOptions {
+            wait_time: Duration::from_millis(300),
+            ..Default::default()
+        };
+
+        // Removing the queue mid-wait ends the wait empty-handed, not with an error
+        let (received, _) = tokio::join!(engine.receive("q", &options), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            engine.state().queues.lock().await.remove("q");
+        });
+        assert_eq!(received.unwrap().len(), 0);
+
+        // A queue that shows up during the wait doesn't rescue a request for a missing one
+        let (received, _) = tokio::join!(engine.receive("q", &options), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            engine
+                .create_queue("q", HashMap::new(), HashMap::new())
+                .await
+                .unwrap();
+        });
+        assert_eq!(
+            received.unwrap_err(),
+            SqsError::NonExistentQueue("q".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_message_size_includes_attributes() {
+        let engine = engine_with_queue("q", &[("MaximumMessageSize", "1024")]).await;
+        let message = |body: &str, attribute: &str| {
+            let mut message = Message::new("1".to_owned(), body.to_owned());
+            message.message_attributes = vec![crate::queue::MessageAttribute {
+                name: "a".to_owned(),
+                data_type: "String".to_owned(),
+                value: crate::queue::MessageAttributeValue::String(attribute.to_owned()),
+            }];
+            message
+        };
+
+        // A small body whose attribute takes it over the limit
+        let too_big = engine
+            .send("q", message("hi", &"x".repeat(1020)), None)
+            .await;
+        assert!(
+            matches!(too_big, Err(SqsError::InvalidParameterValue(ref m)) if m.contains("1024 bytes")),
+            "{:?}",
+            too_big
+        );
+        // 500 characters, but 1000 bytes
+        assert!(engine
+            .send("q", message(&"é".repeat(500), &"x".repeat(20)), None)
+            .await
+            .is_err());
+        // Exactly at the limit: 1000 + 1 + 6 + 17
+        engine
+            .send("q", message(&"é".repeat(500), &"x".repeat(17)), None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fifo_rules() {
+        let engine = engine_with_queue("q.fifo", &[("FifoQueue", "true")]).await;
+        let message = Message::new("1".to_owned(), "hi".to_owned());
+        assert!(matches!(
+            engine.send("q.fifo", message.clone(), None).await,
+            Err(SqsError::MissingParameter(_))
+        ));
+
+        let mut message = message;
+        message.message_group_id = Some("g".to_owned());
+        assert!(matches!(
+            engine.send("q.fifo", message.clone(), Some(5)).await,
+            Err(SqsError::InvalidParameterValue(_))
+        ));
+        let sent = engine.send("q.fifo", message, None).await.unwrap();
+        assert_eq!(
+            sent.sequence_number.as_deref(),
+            Some("00000000000000000001")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_entries_fail_independently() {
+        let engine = engine_with_queue("q.fifo", &[("FifoQueue", "true")]).await;
+        let message = |id: &str, group: Option<&str>| {
+            let mut message = Message::new(id.to_owned(), "hi".to_owned());
+            message.message_group_id = group.map(str::to_owned);
+            message
+        };
+        let results = engine
+            .send_batch(
+                "q.fifo",
+                vec![
+                    (message("1", Some("g")), None),
+                    (message("2", None), None),
+                    (message("3", Some("g")), None),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[1], Err(SqsError::MissingParameter(_))));
+        let sequence_numbers: Vec<_> = [&results[0], &results[2]]
+            .map(|r| r.as_ref().unwrap().sequence_number.clone().unwrap())
+            .into();
+        assert_eq!(
+            sequence_numbers,
+            ["00000000000000000001", "00000000000000000002"]
+        );
+
+        assert!(matches!(
+            engine.send_batch("missing", Vec::new()).await,
+            Err(SqsError::NonExistentQueue(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_attributes() {
+        let engine = engine_with_queue("q", &[("DelaySeconds", "5")]).await;
+        engine
+            .send("q", Message::new("1".to_owned(), "hi".to_owned()), Some(0))
+            .await
+            .unwrap();
+
+        let attrs: HashMap<String, String> = engine
+            .get_attributes("q", &[])
+            .await
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(attrs["ApproximateNumberOfMessages"], "1");
+        assert_eq!(attrs["DelaySeconds"], "5");
+        assert_eq!(attrs["VisibilityTimeout"], "30");
+        assert_eq!(attrs["MessageRetentionPeriod"], "345600");
+
+        let attrs = engine
+            .get_attributes("q", &["DelaySeconds".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(attrs, vec![("DelaySeconds".to_string(), "5".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_queue() {
+        let engine = SqsEngine::in_memory().await.unwrap();
+        let missing = Err(SqsError::NonExistentQueue("nope".to_string()));
+        assert_eq!(
+            engine
+                .send("nope", Message::new("1".to_owned(), "hi".to_owned()), None)
+                .await
+                .map(|_| ()),
+            missing
+        );
+        assert_eq!(
+            engine
+                .receive("nope", &ReceiveOptions::default())
+                .await
+                .map(|_| ()),
+            missing
+        );
+        assert_eq!(
+            engine.get_attributes("nope", &[]).await.map(|_| ()),
+            missing
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_is_idempotent() {
+        let engine = engine_with_queue("q", &[]).await;
+        let url = engine
+            .create_queue("q", HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(url, "http://localhost:9090/000000000000/q");
+        assert_eq!(engine.state().queues.lock().await.len(), 1);
+
+        let fifo_only = HashMap::from([("DeduplicationScope".to_string(), "queue".to_string())]);
+        assert!(matches!(
+            engine
+                .create_queue("other", fifo_only, HashMap::new())
+                .await,
+            Err(SqsError::InvalidAttributeName(_))
+        ));
+    }
+}
@@ -0,0 +1,310 @@
+use actix_web::{http::StatusCode, HttpResponse};
+use std::{fmt, str::FromStr};
+
+/// SQS errors the mock can return, with their AWS code and HTTP status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SqsError {
+    ServiceUnavailable,
+    RequestThrottled,
+    /// The request has no `Action` parameter or `X-Amz-Target` header
+    MissingAction,
+    /// The action name
+    InvalidAction(String),
+    /// The HTTP method, on an endpoint that only takes `POST`
+    MethodNotAllowed(String),
+    /// The request body isn't a form the action can be read from
+    MalformedQueryString(String),
+    /// The `QueueUrl`
+    InvalidAddress(String),
+    /// The queue name
+    NonExistentQueue(String),
+    /// The queue name
+    PurgeQueueInProgress(String),
+    /// No such message move task
+    ResourceNotFound(String),
+    ReceiptHandleIsInvalid,
+    MissingParameter(String),
+    InvalidParameterValue(String),
+    InvalidAttributeName(String),
+    InvalidAttributeValue(String),
+    InvalidMessageContents(String),
+    /// A `*Batch` request without entries
+    EmptyBatchRequest,
+    /// The number of entries in a `*Batch` request over `MAX_BATCH_ENTRIES`
+    TooManyEntriesInBatchRequest(usize),
+    /// The entry id used more than once in a `*Batch` request
+    BatchEntryIdsNotDistinct(String),
+    /// The queue has reached `--max-queue-messages`
+    QueueFull,
+    /// The queue's in-flight message limit is reached
+    OverLimit,
+    InternalFailure(String),
+    /// The request has no `Authorization` header, with `--validate-signatures`
+    MissingAuthenticationToken,
+    /// The `Authorization` header isn't a well-formed SigV4 header
+    IncompleteSignature(String),
+    /// The SigV4 credential scope doesn't match this endpoint
+    SignatureDoesNotMatch(String),
+}
+
+impl SqsError {
+    /// The AWS error `Code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SqsError::ServiceUnavailable => "ServiceUnavailable",
+            SqsError::RequestThrottled => "RequestThrottled",
+            SqsError::MissingAction => "MissingAction",
+            SqsError::InvalidAction(_) => "InvalidAction",
+            SqsError::MethodNotAllowed(_) => "MethodNotAllowed",
+            SqsError::MalformedQueryString(_) => "MalformedQueryString",
+            SqsError::InvalidAddress(_) => "InvalidAddress",
+            SqsError::NonExistentQueue(_) => "AWS.SimpleQueueService.NonExistentQueue",
+            SqsError::PurgeQueueInProgress(_) => "AWS.SimpleQueueService.PurgeQueueInProgress",
+            SqsError::ResourceNotFound(_) => "ResourceNotFoundException",
+            SqsError::ReceiptHandleIsInvalid => "ReceiptHandleIsInvalid",
+            SqsError::MissingParameter(_) => "MissingParameter",
+            SqsError::InvalidParameterValue(_) => "InvalidParameterValue",
+            SqsError::InvalidAttributeName(_) => "InvalidAttributeName",
+            SqsError::InvalidAttributeValue(_) => "InvalidAttributeValue",
+            SqsError::InvalidMessageContents(_) => "InvalidMessageContents",
+            SqsError::EmptyBatchRequest => "AWS.SimpleQueueService.EmptyBatchRequest",
+            SqsError::TooManyEntriesInBatchRequest(_) => {
+                "AWS.SimpleQueueService.TooManyEntriesInBatchRequest"
+            }
+            SqsError::BatchEntryIdsNotDistinct(_) => {
+                "AWS.SimpleQueueService.BatchEntryIdsNotDistinct"
+            }
+            SqsError::QueueFull => "AWS.SimpleQueueService.QueueFull",
+            SqsError::OverLimit => "OverLimit",
+            SqsError::InternalFailure(_) => "InternalFailure",
+            SqsError::MissingAuthenticationToken => "MissingAuthenticationToken",
+            SqsError::IncompleteSignature(_) => "IncompleteSignature",
+            SqsError::SignatureDoesNotMatch(_) => "SignatureDoesNotMatch",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            SqsError::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            SqsError::RequestThrottled
+            | SqsError::PurgeQueueInProgress(_)
+            | SqsError::OverLimit
+            | SqsError::MissingAuthenticationToken
+            | SqsError::SignatureDoesNotMatch(_) => StatusCode::FORBIDDEN,
+            SqsError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            SqsError::InternalFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            SqsError::ServiceUnavailable => {
+                "The request has failed due to a temporary failure of the server.".to_string()
+            }
+            SqsError::RequestThrottled => "Request is throttled.".to_string(),
+            SqsError::MissingAction => "The request must contain the parameter Action.".to_string(),
+            SqsError::InvalidAction(action) => {
+                format!("The action {} is not valid for this endpoint.", action)
+            }
+            SqsError::MethodNotAllowed(method) => {
+                format!(
+                    "The HTTP method {} is not allowed for this endpoint.",
+                    method
+                )
+            }
+            SqsError::InvalidAddress(queue_url) => {
+                format!("The address {} is not valid for this endpoint.", queue_url)
+            }
+            SqsError::NonExistentQueue(queue_name) => {
+                format!("The specified queue does not exist: {}", queue_name)
+            }
+            SqsError::PurgeQueueInProgress(queue_name) => format!(
+                "Only one PurgeQueue operation on {} is allowed every 60 seconds.",
+                queue_name
+            ),
+            SqsError::ReceiptHandleIsInvalid => {
+                "The input receipt handle is not a valid receipt handle.".to_string()
+            }
+            SqsError::EmptyBatchRequest => {
+                "The batch request doesn't contain any entries.".to_string()
+            }
+            SqsError::TooManyEntriesInBatchRequest(count) => format!(
+                "Maximum number of entries per request are {}. You have sent {}.",
+                crate::api::helpers::MAX_BATCH_ENTRIES,
+                count
+            ),
+            SqsError::BatchEntryIdsNotDistinct(id) => format!("Id {} repeated.", id),
+            SqsError::QueueFull => "The queue has reached its message limit.".to_string(),
+            SqsError::OverLimit => {
+                "The maximum number of in-flight messages has been reached.".to_string()
+            }
+            SqsError::MissingAuthenticationToken => {
+                "Request is missing Authentication Token".to_string()
+            }
+            SqsError::MalformedQueryString(message)
+            | SqsError::ResourceNotFound(message)
+            | SqsError::MissingParameter(message)
+            | SqsError::InvalidParameterValue(message)
+            | SqsError::InvalidAttributeName(message)
+            | SqsError::InvalidAttributeValue(message)
+            | SqsError::InvalidMessageContents(message)
+            | SqsError::InternalFailure(message)
+            | SqsError::IncompleteSignature(message)
+            | SqsError::SignatureDoesNotMatch(message) => message.clone(),
+        }
+    }
+
+    /// The SQS `ErrorResponse` document for this error.
+    pub fn error_response(&self) -> HttpResponse {
+        crate::api::helpers::error_response(self.status(), self.code(), &self.message())
+    }
+
+    /// The same error for a JSON protocol request.
+    pub fn json_error_response(&self) -> HttpResponse {
+        crate::api::helpers::json_error_response(self.status(), self.code(), &self.message())
+    }
+}
+
+impl fmt::Display for SqsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for SqsError {}
+
+impl FromStr for SqsError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ServiceUnavailable" => Ok(SqsError::ServiceUnavailable),
+            "RequestThrottled" => Ok(SqsError::RequestThrottled),
+            _ => Err(anyhow::anyhow!("Unknown SQS error: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_and_status() {
+        let s = String::new;
+        for (error, code, status) in [
+            (SqsError::ServiceUnavailable, "ServiceUnavailable", 503),
+            (SqsError::RequestThrottled, "RequestThrottled", 403),
+            (SqsError::MissingAction, "MissingAction", 400),
+            (SqsError::InvalidAction(s()), "InvalidAction", 400),
+            (SqsError::MethodNotAllowed(s()), "MethodNotAllowed", 405),
+            (
+                SqsError::MalformedQueryString(s()),
+                "MalformedQueryString",
+                400,
+            ),
+            (SqsError::InvalidAddress(s()), "InvalidAddress", 400),
+            (
+                SqsError::NonExistentQueue(s()),
+                "AWS.SimpleQueueService.NonExistentQueue",
+                400,
+            ),
+            (
+                SqsError::PurgeQueueInProgress(s()),
+                "AWS.SimpleQueueService.PurgeQueueInProgress",
+                403,
+            ),
+            (
+                SqsError::ResourceNotFound(s()),
+                "ResourceNotFoundException",
+                400,
+            ),
+            (
+                SqsError::ReceiptHandleIsInvalid,
+                "ReceiptHandleIsInvalid",
+                400,
+            ),
+            (SqsError::MissingParameter(s()), "MissingParameter", 400),
+            (
+                SqsError::InvalidParameterValue(s()),
+                "InvalidParameterValue",
+                400,
+            ),
+            (
+                SqsError::InvalidAttributeName(s()),
+                "InvalidAttributeName",
+                400,
+            ),
+            (
+                SqsError::InvalidAttributeValue(s()),
+                "InvalidAttributeValue",
+                400,
+            ),
+            (
+                SqsError::InvalidMessageContents(s()),
+                "InvalidMessageContents",
+                400,
+            ),
+            (
+                SqsError::EmptyBatchRequest,
+                "AWS.SimpleQueueService.EmptyBatchRequest",
+                400,
+            ),
+            (
+                SqsError::TooManyEntriesInBatchRequest(11),
+                "AWS.SimpleQueueService.TooManyEntriesInBatchRequest",
+                400,
+            ),
+            (
+                SqsError::BatchEntryIdsNotDistinct(s()),
+                "AWS.SimpleQueueService.BatchEntryIdsNotDistinct",
+                400,
+            ),
+            (SqsError::QueueFull, "AWS.SimpleQueueService.QueueFull", 400),
+            (SqsError::OverLimit, "OverLimit", 403),
+            (SqsError::InternalFailure(s()), "InternalFailure", 500),
+            (
+                SqsError::MissingAuthenticationToken,
+                "MissingAuthenticationToken",
+                403,
+            ),
+            (
+                SqsError::IncompleteSignature(s()),
+                "IncompleteSignature",
+                400,
+            ),
+            (
+                SqsError::SignatureDoesNotMatch(s()),
+                "SignatureDoesNotMatch",
+                403,
+            ),
+        ] {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.status().as_u16(), status, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let error = SqsError::NonExistentQueue("q".to_string());
+        assert_eq!(
+            error.to_string(),
+            "AWS.SimpleQueueService.NonExistentQueue: The specified queue does not exist: q"
+        );
+        let error: Box<dyn std::error::Error> = Box::new(SqsError::QueueFull);
+        assert!(error
+            .to_string()
+            .starts_with("AWS.SimpleQueueService.QueueFull"));
+    }
+
+    #[tokio::test]
+    async fn test_error_response_is_xml() {
+        let resp = SqsError::ReceiptHandleIsInvalid.error_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<Type>Sender</Type><Code>ReceiptHandleIsInvalid</Code>"));
+        assert!(body.contains("<RequestId>"));
+    }
+}
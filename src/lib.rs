@@ -0,0 +1,289 @@
+//! An SQS mock. The HTTP server in `main.rs` is a thin layer over this library, which
+//! can also be used in-process through [`engine::SqsEngine`].
+
+use rand::{rngs::StdRng, SeedableRng};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+
+pub mod admin;
+pub mod api;
+pub mod app;
+pub mod chaos;
+pub mod clock;
+pub mod config;
+pub mod cors;
+pub mod engine;
+pub mod error;
+pub mod metrics;
+pub mod move_task;
+pub mod observer;
+pub mod persistence;
+pub mod queue;
+pub mod service;
+pub mod signature;
+
+/// A queue guarded by its own lock, so operations on different queues don't contend.
+pub type SharedQueue = Arc<Mutex<queue::Queue>>;
+
+#[derive(Debug, Clone)]
+pub struct AppState {
+    pub db_pool: SqlitePool,
+    pub host_name: String,
+    pub account_id: String,
+    pub url_style: api::helpers::UrlStyle,
+    /// The map lock is only held long enough to look up or insert a queue; CreateQueue
+    /// also holds it across its DB write so racing creates of one name can't both insert.
+    pub queues: Arc<Mutex<HashMap<String, SharedQueue>>>,
+    pub max_queue_messages: Option<usize>,
+    /// Most messages each queue lets be in flight at once
+    pub in_flight_limit: usize,
+    pub queue_rate_limit: Option<u32>,
+    /// Artificial delay in millis per action (lowercase name, or `*` for all)
+    pub latency: HashMap<String, u64>,
+    /// Error injection per action (lowercase name, or `*` for all)
+    pub chaos: chaos::ChaosConfig,
+    pub chaos_rng: Arc<std::sync::Mutex<StdRng>>,
+    /// Extra delay before ReceiveMessage responds; none when unset
+    pub receive_latency: Option<chaos::ReceiveLatency>,
+    /// Seed for the RNG each standard queue shuffles its receives with; queue order when unset
+    pub shuffle_seed: Option<u64>,
+    /// How long receipt handles stay usable; as long as they are current when unset
+    pub receipt_handle_ttl: Option<std::time::Duration>,
+    /// Receive count after which messages in queues without a RedrivePolicy are dropped
+    pub default_max_receive_count: Option<u32>,
+    /// Drop messages once their `X-Mock-TTL-Seconds` attribute runs out
+    pub per_message_ttl: bool,
+    /// Never deliver a message twice, even if it isn't deleted
+    pub at_most_once: bool,
+    /// Answer requests for mock-only message attributes such as
+    /// `X-Mock-VisibilitySecondsRemaining`; on with `--admin`
+    pub debug_attributes: bool,
+    /// Shared by every queue; real time unless the server runs with `--mock-clock`
+    pub clock: Arc<dyn clock::Clock>,
+    /// Notified of sends, receives and deletes; nothing is called when unset
+    pub observer: Option<Arc<dyn observer::QueueObserver>>,
+    /// Message move tasks by task handle
+    pub move_tasks: Arc<Mutex<HashMap<String, move_task::MoveTask>>>,
+}
+
+impl AppState {
+    /// The URL clients use to address a queue.
+    pub fn queue_url(&self, queue_name: &str) -> String {
+        api::helpers::build_queue_url(
+            &self.host_name,
+            &self.account_id,
+            queue_name,
+            self.url_style,
+        )
+    }
+
+    /// Look up a queue by name, releasing the map lock before returning.
+    pub async fn get_queue(&self, name: &str) -> Option<SharedQueue> {
+        self.queues.lock().await.get(name).cloned()
+    }
+
+    /// State with no queues, served at `http://localhost:9090` for account `000000000000`,
+    /// without limits, chaos or an observer.
+    pub fn new(db_pool: SqlitePool) -> Self {
+        AppState {
+            db_pool,
+            host_name: "http://localhost:9090".to_string(),
+            account_id: "000000000000".to_string(),
+            url_style: Default::default(),
+            queues: Arc::new(Mutex::new(HashMap::new())),
+            max_queue_messages: None,
+            in_flight_limit: queue::IN_FLIGHT_LIMIT,
+            queue_rate_limit: None,
+            latency: HashMap::new(),
+            chaos: HashMap::new(),
+            chaos_rng: chaos::chaos_rng_from_env(std::iter::empty()),
+            receive_latency: None,
+            shuffle_seed: None,
+            receipt_handle_ttl: None,
+            default_max_receive_count: None,
+            per_message_ttl: false,
+            at_most_once: false,
+            debug_attributes: false,
+            clock: Arc::new(clock::SystemClock),
+            observer: None,
+            move_tasks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Like `new`, over a fresh, migrated in-memory database.
+    pub async fn in_memory() -> anyhow::Result<Self> {
+        // Every connection to `sqlite::memory:` is a separate database, so keep just one
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        sqlx::migrate!().run(&db_pool).await?;
+        Ok(AppState::new(db_pool))
+    }
+
+    /// State with no queues and a lazily-connected in-memory database.
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        AppState::new(SqlitePool::connect_lazy("sqlite::memory:").unwrap())
+    }
+
+    /// Like `for_tests`, but backed by a migrated in-memory database.
+    #[cfg(test)]
+    pub async fn for_tests_with_db() -> Self {
+        AppState::in_memory().await.unwrap()
+    }
+
+    /// An empty queue configured from the server-wide limits, with default attributes
+    /// until `apply_queue_attributes` sets them.
+    pub fn build_queue(
+        &self,
+        name: &str,
+        is_fifo: bool,
+        tags: &HashMap<String, String>,
+    ) -> queue::Queue {
+        let mut tags: Vec<queue::QueueTags> = tags
+            .iter()
+            .map(|(key, value)| queue::QueueTags {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        tags.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut queue = queue::Queue::new(name, tags, None);
+        queue.is_fifo = is_fifo;
+        queue.max_messages = self.max_queue_messages;
+        queue.in_flight_limit = self.in_flight_limit;
+        queue.rate_limiter = self.queue_rate_limit.map(queue::RateLimiter::new);
+        queue.shuffle_rng = self.shuffle_seed.map(StdRng::seed_from_u64);
+        queue.receipt_handle_ttl = self.receipt_handle_ttl;
+        queue.max_receive_count = self.default_max_receive_count;
+        queue.honor_message_ttl = self.per_message_ttl;
+        queue.at_most_once = self.at_most_once;
+        queue.clock = self.clock.clone();
+        queue
+    }
+
+    /// Apply the queue attributes that affect in-memory behaviour, as given to CreateQueue
+    /// or SetQueueAttributes or stored in the database; the rest only live in the database.
+    /// An attribute with an empty value goes back to its default.
+    pub fn apply_queue_attributes(
+        &self,
+        queue: &mut queue::Queue,
+        attributes: &HashMap<String, String>,
+    ) {
+        for (name, value) in attributes {
+            let value = Some(value.as_str()).filter(|v| !v.is_empty());
+            match name.as_str() {
+                "VisibilityTimeout" => {
+                    queue.default_visibility_timeout = value
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(queue::DEFAULT_VISIBILITY_TIMEOUT)
+                }
+                "DelaySeconds" => {
+                    queue.default_delay_seconds = value.and_then(|v| v.parse().ok()).unwrap_or(0)
+                }
+                "MaximumMessageSize" => {
+                    queue.maximum_message_size = value
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(queue::MAX_MESSAGE_SIZE)
+                }
+                // A RedrivePolicy takes over from the server's default max receive count
+                "RedrivePolicy" => {
+                    queue.max_receive_count = match value {
+                        Some(_) => None,
+                        None => self.default_max_receive_count,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Register an empty in-memory queue, bypassing the database.
+    #[cfg(test)]
+    pub async fn add_test_queue(&self, name: &str) -> SharedQueue {
+        let mut queue = queue::Queue::new(name, vec![], None);
+        queue.clock = self.clock.clone();
+        let queue = Arc::new(Mutex::new(queue));
+        self.queues
+            .lock()
+            .await
+            .insert(name.to_string(), queue.clone());
+        queue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queues_lock_independently() {
+        let state = AppState::for_tests();
+        state.add_test_queue("a").await;
+        state.add_test_queue("b").await;
+
+        // Hold queue "a" locked while working on queue "b"
+        let queue_a = state.get_queue("a").await.unwrap();
+        let _guard_a = queue_a.lock().await;
+
+        let queue_b = state.get_queue("b").await.unwrap();
+        let locked_b =
+            tokio::time::timeout(std::time::Duration::from_millis(100), queue_b.lock()).await;
+        assert!(locked_b.is_ok());
+        locked_b
+            .unwrap()
+            .push(queue::Message::new("1".to_owned(), "body".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_apply_queue_attributes() {
+        let state = AppState {
+            default_max_receive_count: Some(5),
+            ..AppState::for_tests()
+        };
+        let mut queue = state.build_queue("q", false, &HashMap::new());
+        assert_eq!(queue.max_receive_count, Some(5));
+
+        let attributes = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        state.apply_queue_attributes(
+            &mut queue,
+            &attributes(&[
+                ("VisibilityTimeout", "45"),
+                ("DelaySeconds", "5"),
+                ("MaximumMessageSize", "1024"),
+                ("RedrivePolicy", "{}"),
+                ("MessageRetentionPeriod", "60"),
+            ]),
+        );
+        assert_eq!(queue.default_visibility_timeout, 45);
+        assert_eq!(queue.default_delay_seconds, 5);
+        assert_eq!(queue.maximum_message_size, 1024);
+        assert_eq!(queue.max_receive_count, None);
+
+        // Empty values go back to the defaults
+        state.apply_queue_attributes(
+            &mut queue,
+            &attributes(&[
+                ("VisibilityTimeout", ""),
+                ("DelaySeconds", ""),
+                ("MaximumMessageSize", ""),
+                ("RedrivePolicy", ""),
+            ]),
+        );
+        assert_eq!(
+            queue.default_visibility_timeout,
+            queue::DEFAULT_VISIBILITY_TIMEOUT
+        );
+        assert_eq!(queue.default_delay_seconds, 0);
+        assert_eq!(queue.maximum_message_size, queue::MAX_MESSAGE_SIZE);
+        assert_eq!(queue.max_receive_count, Some(5));
+    }
+}
@@ -1,13 +1,13 @@
-use actix_web::{get, middleware, web, App, HttpResponse, HttpServer};
+use actix_web::{http::KeepAlive, HttpServer};
 use clap::Parser;
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use sqlx::sqlite::SqlitePoolOptions;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
 use tracing::{error, info};
 
-mod api;
-mod queue;
-mod service;
+use sqs_navbat::{
+    api, app, chaos, clock, config, cors, engine, persistence, signature, AppState, SharedQueue,
+};
 
 #[derive(clap::Parser, Debug)]
 #[command(author, about, version)]
@@ -16,17 +16,94 @@ struct CliParams {
     bind_address: String,
     #[clap(short, long, default_value = "9090")]
     port: u16,
+    /// Address to listen on as `<ip>:<port>`, e.g. `0.0.0.0:9324`; overrides bind_address and port
+    #[clap(long)]
+    bind: Option<std::net::SocketAddr>,
+    /// Number of HTTP worker threads (defaults to the number of CPU cores)
+    #[clap(long)]
+    workers: Option<std::num::NonZeroUsize>,
     #[clap(short, long, default_value = "sqlite://database.db")]
     db_url: String,
     #[clap(long, default_value = "http://locahost:9090")]
     host_name: String,
-}
-
-#[derive(Debug, Clone)]
-pub struct AppState {
-    pub db_pool: SqlitePool,
-    pub host_name: String,
-    pub queues: Arc<Mutex<HashMap<String, queue::Queue>>>,
+    /// Maximum number of messages a single queue may hold (unbounded if unset)
+    #[clap(long)]
+    max_queue_messages: Option<usize>,
+    /// Maximum number of messages a single queue may have in flight before receives fail
+    /// with OverLimit
+    #[clap(long, default_value_t = sqs_navbat::queue::IN_FLIGHT_LIMIT)]
+    in_flight_limit: usize,
+    /// Per-queue SendMessage/ReceiveMessage requests per second before throttling (unlimited if unset)
+    #[clap(long)]
+    queue_rate_limit: Option<u32>,
+    /// Scheme of queue URLs (defaults to the one in host_name, or http)
+    #[clap(long, value_enum)]
+    url_scheme: Option<api::helpers::UrlScheme>,
+    /// Form of queue URLs: `path` (host/account/queue) or `virtual-host` (sqs.host/account/queue)
+    #[clap(long, value_enum, default_value = "path")]
+    url_form: api::helpers::UrlForm,
+    /// AWS account id reported by the mock, e.g. as the `SenderId` of messages
+    #[clap(long, default_value = "000000000000")]
+    account_id: String,
+    /// YAML (or JSON) file of queues to create at startup, in the layout of ElasticMQ's `queues` block
+    #[clap(long)]
+    config: Option<std::path::PathBuf>,
+    /// Serve the debugging endpoints under /admin, and let ReceiveMessage return the
+    /// mock-only `X-Mock-VisibilitySecondsRemaining` attribute
+    #[clap(long)]
+    admin: bool,
+    /// Serve Prometheus metrics at /metrics
+    #[clap(long)]
+    metrics: bool,
+    /// Origin allowed to call the mock from a browser, e.g. `http://localhost:3000`, or `*`
+    /// for any; repeat for several. CORS headers are only sent when this is set
+    #[clap(long)]
+    cors_allow_origin: Vec<String>,
+    /// Methods allowed in CORS preflight responses
+    #[clap(long, default_value = cors::DEFAULT_ALLOW_METHODS)]
+    cors_allow_methods: String,
+    /// Request headers allowed in CORS preflight responses
+    #[clap(long, default_value = cors::DEFAULT_ALLOW_HEADERS)]
+    cors_allow_headers: String,
+    /// Hand out standard queue messages in random order, like real SQS may, instead of
+    /// queue order
+    #[clap(long)]
+    shuffle_receives: bool,
+    /// Seed for --shuffle-receives, to make the order reproducible (random if unset)
+    #[clap(long)]
+    shuffle_seed: Option<u64>,
+    /// Seconds after which a receipt handle can no longer delete or change the visibility
+    /// of its message, for negative tests (handles last as long as they are current if unset)
+    #[clap(long)]
+    receipt_handle_ttl: Option<u64>,
+    /// Drop messages received more than this many times from queues without a
+    /// RedrivePolicy, as if dead-lettered to nowhere (not AWS behaviour)
+    #[clap(long)]
+    default_max_receive_count: Option<u32>,
+    /// Drop a message once the seconds in its `X-Mock-TTL-Seconds` message attribute have
+    /// passed since it was sent, whatever the queue's retention (not AWS behaviour)
+    #[clap(long)]
+    per_message_ttl: bool,
+    /// Deliver each message at most once: a received message never becomes receivable
+    /// again, even if it isn't deleted, until it is deleted or purged (not AWS behaviour)
+    #[clap(long)]
+    at_most_once: bool,
+    /// Run queues on a clock that `POST /admin/advance-time` (needs --admin) can move
+    /// forward, so tests can run out delays and visibility timeouts without sleeping
+    #[clap(long)]
+    mock_clock: bool,
+    /// Largest request body accepted, in bytes; the default fits a batch of ten 256 KiB messages
+    #[clap(long, default_value_t = app::DEFAULT_MAX_PAYLOAD_BYTES)]
+    max_payload_bytes: usize,
+    /// Seconds an idle keep-alive connection stays open; 0 disables keep-alive (5 if unset)
+    #[clap(long)]
+    keep_alive_secs: Option<u64>,
+    /// Reject API requests without a well-formed SigV4 `Authorization` header
+    #[clap(long)]
+    validate_signatures: bool,
+    /// Region signed requests must be scoped to (any if unset); needs --validate-signatures
+    #[clap(long)]
+    signature_region: Option<String>,
 }
 
 #[actix_web::main]
@@ -48,30 +125,110 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
-    let queue_list: HashMap<String, queue::Queue> = HashMap::new();
+    let queue_list: HashMap<String, SharedQueue> = HashMap::new();
     let state = AppState {
         db_pool,
         host_name: cli_params.host_name,
+        account_id: cli_params.account_id,
+        url_style: api::helpers::UrlStyle {
+            scheme: cli_params.url_scheme,
+            form: cli_params.url_form,
+        },
         queues: Arc::new(Mutex::new(queue_list)),
+        max_queue_messages: cli_params.max_queue_messages,
+        in_flight_limit: cli_params.in_flight_limit,
+        queue_rate_limit: cli_params.queue_rate_limit,
+        latency: chaos::latency_from_env(std::env::vars()),
+        chaos: chaos::chaos_from_env(std::env::vars()),
+        chaos_rng: chaos::chaos_rng_from_env(std::env::vars()),
+        receive_latency: chaos::receive_latency_from_env(std::env::vars()),
+        shuffle_seed: cli_params
+            .shuffle_receives
+            .then(|| cli_params.shuffle_seed.unwrap_or_else(rand::random)),
+        receipt_handle_ttl: cli_params
+            .receipt_handle_ttl
+            .map(std::time::Duration::from_secs),
+        default_max_receive_count: cli_params.default_max_receive_count,
+        per_message_ttl: cli_params.per_message_ttl,
+        at_most_once: cli_params.at_most_once,
+        debug_attributes: cli_params.admin,
+        clock: if cli_params.mock_clock {
+            Arc::new(clock::MockClock::default())
+        } else {
+            Arc::new(clock::SystemClock)
+        },
+        observer: None,
+        move_tasks: Default::default(),
+    };
+
+    match persistence::restore(&state).await {
+        Ok(count) => info!("Restored {} messages", count),
+        Err(e) => error!("Failed to restore queues: {}", e),
+    }
+    if let Some(path) = &cli_params.config {
+        let engine = engine::SqsEngine::new(Arc::new(state.clone()));
+        let count = config::Config::load(path)?.create_queues(&engine).await?;
+        info!("Configured {} queues from {}", count, path.display());
+    }
+    let shutdown_state = state.clone();
+
+    let bind_addr = match cli_params.bind {
+        Some(addr) => addr.to_string(),
+        None => format!("{}:{}", cli_params.bind_address, cli_params.port),
     };
 
-    info!("Starting server ...");
-    HttpServer::new(move || {
-        App::new()
-            .service(index)
-            .service(api::post_handler)
-            .app_data(web::Data::new(state.clone()))
-            .wrap(middleware::Logger::default())
-    })
-    .bind((cli_params.bind_address, cli_params.port))?
-    .run()
-    .await?;
+    info!("Starting server on {} ...", bind_addr);
+    let options = app::AppOptions {
+        admin: cli_params.admin,
+        metrics: cli_params.metrics,
+        cors: (!cli_params.cors_allow_origin.is_empty()).then_some(cors::Cors {
+            allow_origins: cli_params.cors_allow_origin,
+            allow_methods: cli_params.cors_allow_methods,
+            allow_headers: cli_params.cors_allow_headers,
+        }),
+        signature_check: cli_params
+            .validate_signatures
+            .then_some(signature::SignatureCheck {
+                region: cli_params.signature_region,
+            }),
+        max_payload_bytes: cli_params.max_payload_bytes,
+    };
+    let mut server = HttpServer::new(move || app::build_app(state.clone(), &options));
+    if let Some(workers) = cli_params.workers {
+        server = server.workers(workers.get());
+    }
+    if let Some(secs) = cli_params.keep_alive_secs {
+        server = server.keep_alive(match secs {
+            0 => KeepAlive::Disabled,
+            secs => KeepAlive::Timeout(std::time::Duration::from_secs(secs)),
+        });
+    }
+    // Returns once a SIGINT/SIGTERM has stopped the workers
+    server.bind(bind_addr)?.run().await?;
+
+    info!("Draining queues to the database ...");
+    match persistence::drain(&shutdown_state).await {
+        Ok(count) => info!("Saved {} messages", count),
+        Err(e) => error!("Failed to drain queues: {}", e),
+    }
     info!("Server stopped.");
 
     Ok(())
 }
 
-#[get("/")]
-async fn index() -> HttpResponse {
-    HttpResponse::Ok().body("Hello world!")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_and_workers_flags() {
+        let params =
+            CliParams::try_parse_from(["sqs-navbat", "--bind", "0.0.0.0:9324", "--workers", "4"])
+                .unwrap();
+        assert_eq!(params.bind.unwrap().to_string(), "0.0.0.0:9324");
+        assert_eq!(params.workers.unwrap().get(), 4);
+
+        assert!(CliParams::try_parse_from(["sqs-navbat", "--bind", "localhost"]).is_err());
+        assert!(CliParams::try_parse_from(["sqs-navbat", "--workers", "0"]).is_err());
+    }
 }
@@ -0,0 +1,86 @@
+//! Prometheus metrics at `/metrics`, only registered when the server runs with `--metrics`.
+
+use actix_web::{get, web, HttpResponse};
+use std::fmt::Write;
+
+use crate::AppState;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics);
+}
+
+/// Per-queue gauges in the Prometheus text format, named after their CloudWatch metrics.
+#[get("/metrics")]
+async fn metrics(app_state: web::Data<AppState>) -> HttpResponse {
+    let queues: Vec<_> = {
+        let queues = app_state.queues.lock().await;
+        let mut queues: Vec<_> = queues
+            .iter()
+            .map(|(name, queue)| (name.clone(), queue.clone()))
+            .collect();
+        queues.sort_by(|(a, _), (b, _)| a.cmp(b));
+        queues
+    };
+
+    let mut body = String::new();
+    body.push_str(
+        "# HELP sqs_approximate_age_of_oldest_message_seconds Age of the oldest visible message in the queue.\n",
+    );
+    body.push_str("# TYPE sqs_approximate_age_of_oldest_message_seconds gauge\n");
+    for (name, queue) in queues {
+        let age = queue.lock().await.approximate_age_of_oldest_message_secs();
+        let _ = writeln!(
+            body,
+            "sqs_approximate_age_of_oldest_message_seconds{{queue=\"{}\"}} {}",
+            name, age
+        );
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_age_of_oldest_message() {
+        let clock = std::sync::Arc::new(crate::clock::MockClock::default());
+        let state = AppState {
+            clock: clock.clone(),
+            ..AppState::for_tests()
+        };
+        state.add_test_queue("empty").await;
+        state
+            .add_test_queue("q")
+            .await
+            .lock()
+            .await
+            .push(crate::queue::Message::new("1".to_owned(), "hi".to_owned()));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(configure),
+        )
+        .await;
+
+        clock.advance(std::time::Duration::from_secs(90));
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("sqs_approximate_age_of_oldest_message_seconds{queue=\"empty\"} 0\n"));
+        let age: u64 = body
+            .lines()
+            .find_map(|l| {
+                l.strip_prefix("sqs_approximate_age_of_oldest_message_seconds{queue=\"q\"} ")
+            })
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(age, 90);
+    }
+}
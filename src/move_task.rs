@@ -0,0 +1,74 @@
+//! Dead-letter queue redrives started with StartMessageMoveTask. The mock moves every
+//! message in one go, so a task has finished by the time the call returns; tasks are kept
+//! afterwards so they can be listed.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveTaskStatus {
+    Running,
+    Completed,
+    Cancelling,
+    Cancelled,
+    Failed,
+}
+
+impl MoveTaskStatus {
+    /// The status as AWS spells it.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MoveTaskStatus::Running => "RUNNING",
+            MoveTaskStatus::Completed => "COMPLETED",
+            MoveTaskStatus::Cancelling => "CANCELLING",
+            MoveTaskStatus::Cancelled => "CANCELLED",
+            MoveTaskStatus::Failed => "FAILED",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MoveTask {
+    pub task_handle: String,
+    pub source_arn: String,
+    /// Unset when the messages went back to the queue whose RedrivePolicy names the source
+    pub destination_arn: Option<String>,
+    pub max_number_of_messages_per_second: Option<u32>,
+    pub status: MoveTaskStatus,
+    pub approximate_number_of_messages_moved: u64,
+    pub approximate_number_of_messages_to_move: u64,
+    pub failure_reason: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub started_timestamp: u64,
+}
+
+impl MoveTask {
+    /// A running task with a fresh handle, started now.
+    pub fn start(
+        source_arn: &str,
+        destination_arn: Option<&str>,
+        max_number_of_messages_per_second: Option<u32>,
+    ) -> Self {
+        // Like AWS's, the handle is opaque base64 naming the task and its source
+        let task_handle = STANDARD.encode(
+            serde_json::json!({
+                "taskId": uuid::Uuid::new_v4().to_string(),
+                "sourceArn": source_arn,
+            })
+            .to_string(),
+        );
+        MoveTask {
+            task_handle,
+            source_arn: source_arn.to_string(),
+            destination_arn: destination_arn.map(str::to_string),
+            max_number_of_messages_per_second,
+            status: MoveTaskStatus::Running,
+            approximate_number_of_messages_moved: 0,
+            approximate_number_of_messages_to_move: 0,
+            failure_reason: None,
+            started_timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        }
+    }
+}
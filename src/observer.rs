@@ -0,0 +1,109 @@
+use std::fmt;
+
+use crate::queue::Message;
+
+/// Hooks into the message lifecycle, e.g. so a test harness can assert on what
+/// happened without polling queue attributes.
+///
+/// Callbacks run on the actix worker handling the request, after the queue lock
+/// has been released. Implementations must be `Send + Sync` since several
+/// workers may call them at once, and should return quickly: the request's
+/// response waits on them.
+pub trait QueueObserver: Send + Sync {
+    /// A message was accepted by `SendMessage`.
+    fn on_send(&self, _queue_name: &str, _message: &Message) {}
+
+    /// A message was handed out by `ReceiveMessage`; called once per message.
+    fn on_receive(&self, _queue_name: &str, _message: &Message) {}
+
+    /// The message with this id was removed by `DeleteMessage`.
+    fn on_delete(&self, _queue_name: &str, _message_id: &str) {}
+}
+
+impl fmt::Debug for dyn QueueObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("QueueObserver")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use actix_web::{test, web, App};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl QueueObserver for Recorder {
+        fn on_send(&self, queue_name: &str, message: &Message) {
+            let event = format!("send {} {}", queue_name, message.message_body);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_receive(&self, queue_name: &str, message: &Message) {
+            let event = format!("receive {} {}", queue_name, message.message_body);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_delete(&self, queue_name: &str, message_id: &str) {
+            let event = format!("delete {} {}", queue_name, message_id);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    fn between<'a>(body: &'a str, start: &str, end: &str) -> &'a str {
+        let from = body.find(start).unwrap() + start.len();
+        let to = from + body[from..].find(end).unwrap();
+        &body[from..to]
+    }
+
+    #[actix_web::test]
+    async fn test_observer_sees_message_lifecycle() {
+        let recorder = Arc::new(Recorder::default());
+        let state = AppState {
+            observer: Some(recorder.clone()),
+            ..AppState::for_tests()
+        };
+        state.add_test_queue("q").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(crate::api::post_handler),
+        )
+        .await;
+
+        let post = |body: String| test::TestRequest::post().uri("/").set_payload(body);
+
+        let req = post("Action=SendMessage&QueueUrl=http://localhost:9090/q&MessageBody=hi".into());
+        test::call_service(&app, req.to_request()).await;
+
+        let req = post("Action=ReceiveMessage&QueueUrl=http://localhost:9090/q".into());
+        let body = test::read_body(test::call_service(&app, req.to_request()).await).await;
+        let body = String::from_utf8_lossy(&body);
+        let message_id = between(&body, "<MessageId>", "</MessageId>");
+        let handle = between(&body, "<ReceiptHandle>", "</ReceiptHandle>");
+
+        let req = post(
+            serde_urlencoded::to_string([
+                ("Action", "DeleteMessage"),
+                ("QueueUrl", "http://localhost:9090/q"),
+                ("ReceiptHandle", handle),
+            ])
+            .unwrap(),
+        );
+        test::call_service(&app, req.to_request()).await;
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                "send q hi".to_string(),
+                "receive q hi".to_string(),
+                format!("delete q {}", message_id),
+            ]
+        );
+    }
+}
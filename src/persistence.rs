@@ -0,0 +1,275 @@
+//! Saving queue contents to the database on shutdown and loading them back on start.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use crate::queue::Message;
+use crate::service::message::MessageEntity;
+use crate::AppState;
+
+/// Write every in-memory message to the database, replacing what was stored before.
+/// Returns the number of messages saved.
+pub async fn drain(app_state: &AppState) -> anyhow::Result<usize> {
+    let queues: Vec<_> = app_state
+        .queues
+        .lock()
+        .await
+        .iter()
+        .map(|(name, queue)| (name.clone(), queue.clone()))
+        .collect();
+
+    let service = crate::service::message::Message::new(&app_state.db_pool);
+    let mut saved = 0;
+    for (name, queue) in queues {
+        let entities: Vec<MessageEntity> = {
+            let queue = queue.lock().await;
+            let now = queue.now();
+            queue.messages().map(|m| to_entity(m, now)).collect()
+        };
+        service.replace_queue_messages(&name, &entities).await?;
+        saved += entities.len();
+    }
+
+    Ok(saved)
+}
+
+/// Recreate the queues stored in the database, along with any drained messages.
+/// Returns the number of messages restored.
+pub async fn restore(app_state: &AppState) -> anyhow::Result<usize> {
+    let queue_service = crate::service::queue::Queue::new(&app_state.db_pool);
+    let message_service = crate::service::message::Message::new(&app_state.db_pool);
+    let mut restored = 0;
+
+    for (name, queue_type) in queue_service.list_queue_types().await? {
+        let attributes = queue_service.get_queue_attributes(&name).await?;
+        let tags = queue_service.get_queue_tags(&name).await?;
+        let mut queue = app_state.build_queue(&name, queue_type == "Fifo", &tags);
+        app_state.apply_queue_attributes(&mut queue, &attributes);
+
+        let now = queue.now();
+        let messages: Vec<Message> = message_service
+            .list_queue_messages(&name)
+            .await?
+            .into_iter()
+            .map(|e| from_entity(e, now))
+            .map(|mut message| {
+                // In at-most-once mode every message received before has been consumed
+                message.consumed = queue.at_most_once && message.first_received_at.is_some();
+                message
+            })
+            .collect();
+        restored += messages.len();
+        queue.restore_messages(messages);
+
+        app_state
+            .queues
+            .lock()
+            .await
+            .insert(name, Arc::new(Mutex::new(queue)));
+    }
+
+    Ok(restored)
+}
+
+fn to_epoch_millis(instant: Instant, now: Instant) -> i64 {
+    let wall = if instant >= now {
+        SystemTime::now() + (instant - now)
+    } else {
+        SystemTime::now() - (now - instant)
+    };
+    wall.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn from_epoch_millis(millis: i64, now: Instant) -> Instant {
+    let wall = UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64);
+    match wall.duration_since(SystemTime::now()) {
+        Ok(ahead) => now + ahead,
+        Err(e) => now.checked_sub(e.duration()).unwrap_or(now),
+    }
+}
+
+fn to_entity(msg: &Message, now: Instant) -> MessageEntity {
+    MessageEntity {
+        message_id: msg.id.clone(),
+        body: msg.message_body.clone(),
+        receipt_handle: msg.receipt_handle.clone(),
+        receive_count: msg.receive_count as i64,
+        sent_at: Some(to_epoch_millis(msg.sent_at, now)),
+        visible_at: to_epoch_millis(msg.visible_at, now),
+        first_received_at: msg.first_received_at.map(|t| to_epoch_millis(t, now)),
+        message_group_id: msg.message_group_id.clone(),
+        message_deduplication_id: msg.message_deduplication_id.clone(),
+        sequence_number: msg.sequence_number.clone(),
+        message_attributes: serde_json::to_string(&msg.message_attributes)
+            .unwrap_or_else(|_| "[]".to_string()),
+        message_system_attributes: serde_json::to_string(&msg.message_system_attributes)
+            .unwrap_or_else(|_| "[]".to_string()),
+    }
+}
+
+fn from_entity(entity: MessageEntity, now: Instant) -> Message {
+    Message {
+        id: entity.message_id,
+        message_body: entity.body,
+        receipt_handle: entity.receipt_handle,
+        receive_count: entity.receive_count as u32,
+        // Renumbered when pushed back onto the queue
+        enqueue_sequence: 0,
+        sent_at: entity.sent_at.map_or(now, |t| from_epoch_millis(t, now)),
+        visible_at: from_epoch_millis(entity.visible_at, now),
+        first_received_at: entity.first_received_at.map(|t| from_epoch_millis(t, now)),
+        // Not stored; the visibility cap falls back to the first receive
+        received_at: None,
+        message_group_id: entity.message_group_id,
+        message_deduplication_id: entity.message_deduplication_id,
+        sequence_number: entity.sequence_number,
+        message_attributes: serde_json::from_str(&entity.message_attributes).unwrap_or_default(),
+        message_system_attributes: serde_json::from_str(&entity.message_system_attributes)
+            .unwrap_or_default(),
+        // Not stored; worked out again by `restore` for at-most-once queues
+        consumed: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::queue::QueueEntity;
+
+    #[tokio::test]
+    async fn test_drain_and_restore() {
+        let state = AppState::for_tests_with_db().await;
+        crate::service::queue::Queue::new(&state.db_pool)
+            .create_queue(QueueEntity {
+                id: None,
+                name: "q".to_owned(),
+                queue_type: "Standard".to_owned(),
+                attributes: None,
+                tags: None,
+                created_at: None,
+                updated_at: None,
+            })
+            .await
+            .unwrap();
+
+        let queue = state.add_test_queue("q").await;
+        {
+            let mut queue = queue.lock().await;
+            let mut first = Message::new("1".to_owned(), "first".to_owned());
+            first.message_attributes = vec![crate::queue::MessageAttribute {
+                name: "blob".to_owned(),
+                data_type: "Binary".to_owned(),
+                value: crate::queue::MessageAttributeValue::Binary(vec![0, 255]),
+            }];
+            queue.push(first);
+            queue.push(Message::new("2".to_owned(), "second".to_owned()));
+            queue.push_with_delay(Message::new("3".to_owned(), "later".to_owned()), 600);
+            queue.receive(1, None);
+        }
+
+        assert_eq!(drain(&state).await.unwrap(), 3);
+        let rows: Vec<(String, String, i64)> =
+            sqlx::query_as("SELECT message_id, body, receive_count FROM messages ORDER BY id")
+                .fetch_all(&state.db_pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                ("1".to_owned(), "first".to_owned(), 1),
+                ("2".to_owned(), "second".to_owned(), 0),
+                ("3".to_owned(), "later".to_owned(), 0),
+            ]
+        );
+
+        // Draining again replaces rather than duplicates
+        assert_eq!(drain(&state).await.unwrap(), 3);
+
+        let restarted = AppState {
+            db_pool: state.db_pool.clone(),
+            queues: Default::default(),
+            ..AppState::for_tests()
+        };
+        assert_eq!(restore(&restarted).await.unwrap(), 3);
+        let queue = restarted.get_queue("q").await.unwrap();
+        let queue = queue.lock().await;
+        let counts = queue.counts();
+        assert_eq!(
+            (counts.visible, counts.not_visible, counts.delayed),
+            (1, 1, 1)
+        );
+        let first = queue.messages().next().unwrap();
+        assert_eq!(
+            first.message_attributes[0].value,
+            crate::queue::MessageAttributeValue::Binary(vec![0, 255])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_keeps_remaining_visibility_timeout() {
+        let state = AppState::for_tests_with_db().await;
+        crate::service::queue::Queue::new(&state.db_pool)
+            .create_queue(QueueEntity {
+                id: None,
+                name: "q".to_owned(),
+                queue_type: "Standard".to_owned(),
+                attributes: None,
+                tags: None,
+                created_at: None,
+                updated_at: None,
+            })
+            .await
+            .unwrap();
+
+        // As if drained a while ago: one timer ran out while the server was down,
+        // the other still has about five minutes to go
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let in_flight = |id: &str, visible_at: i64| MessageEntity {
+            message_id: id.to_owned(),
+            body: id.to_owned(),
+            receipt_handle: Some(crate::queue::encode_receipt_handle("q", id)),
+            receive_count: 1,
+            sent_at: Some(now_ms - 900_000),
+            visible_at,
+            first_received_at: Some(now_ms - 600_000),
+            message_group_id: None,
+            message_deduplication_id: None,
+            sequence_number: None,
+            message_attributes: "[]".to_owned(),
+            message_system_attributes: "[]".to_owned(),
+        };
+        crate::service::message::Message::new(&state.db_pool)
+            .replace_queue_messages(
+                "q",
+                &[
+                    in_flight("expired", now_ms - 60_000),
+                    in_flight("pending", now_ms + 300_000),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(restore(&state).await.unwrap(), 2);
+        let queue = state.get_queue("q").await.unwrap();
+        let mut queue = queue.lock().await;
+        let counts = queue.counts();
+        assert_eq!((counts.visible, counts.not_visible), (1, 1));
+        // The send time survives the restart
+        assert_eq!(queue.approximate_age_of_oldest_message_secs(), 900);
+
+        let pending = queue.messages().find(|m| m.id == "pending").unwrap();
+        let remaining = pending.visible_at - Instant::now();
+        assert!(remaining > Duration::from_secs(290) && remaining <= Duration::from_secs(300));
+
+        let received = queue.receive(10, None);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].id, "expired");
+        assert_eq!(received[0].receive_count, 2);
+    }
+}
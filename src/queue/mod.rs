@@ -1,5 +1,71 @@
-use std::collections::VecDeque;
-use std::time::Instant;
+use rand::{rngs::StdRng, seq::SliceRandom};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::clock::{Clock, SystemClock};
+
+mod rate_limit;
+mod receipt_handle;
+
+pub use rate_limit::RateLimiter;
+pub use receipt_handle::{decode_receipt_handle, encode_receipt_handle, ReceiptHandle};
+
+/// Visibility timeout used when the queue doesn't set one, in seconds.
+pub const DEFAULT_VISIBILITY_TIMEOUT: u32 = 30;
+
+/// Longest a message may stay in flight after it is received, in seconds (12 hours).
+pub const MAX_VISIBILITY_TIMEOUT: u32 = 43200;
+
+/// Message attribute giving a message its own lifetime in seconds, with `--per-message-ttl`.
+/// Not an AWS feature.
+pub const MESSAGE_TTL_ATTRIBUTE: &str = "X-Mock-TTL-Seconds";
+
+/// Default and largest `MaximumMessageSize`, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 262_144;
+
+/// Most messages a queue lets be in flight at once, as in AWS.
+pub const IN_FLIGHT_LIMIT: usize = 120_000;
+
+/// How long deleting an already deleted message still succeeds.
+const DELETED_HANDLE_TTL: Duration = Duration::from_secs(60);
+
+/// How long after a purge another purge of the same queue is refused.
+pub const PURGE_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How long a FIFO receive result is replayed for a retried `ReceiveRequestAttemptId`.
+const RECEIVE_ATTEMPT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A typed `MessageAttribute` sent with a message.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MessageAttribute {
+    pub name: String,
+    /// `String`, `Number` or `Binary`, optionally with a custom suffix such as `Binary.gif`
+    pub data_type: String,
+    pub value: MessageAttributeValue,
+}
+
+impl MessageAttribute {
+    /// Bytes counted towards the message size: the UTF-8 name, data type and value, or
+    /// the raw bytes of a binary value.
+    pub fn size(&self) -> usize {
+        let value = match &self.value {
+            MessageAttributeValue::String(value) => value.len(),
+            MessageAttributeValue::Binary(value) => value.len(),
+        };
+        self.name.len() + self.data_type.len() + value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum MessageAttributeValue {
+    /// `String` and `Number` attributes
+    String(String),
+    /// `Binary` attributes, already decoded from base64
+    Binary(Vec<u8>),
+}
 
 #[derive(Debug, Clone)]
 pub struct Message {
@@ -7,8 +73,29 @@ pub struct Message {
     pub message_body: String,
     pub receipt_handle: Option<String>,
     pub receive_count: u32,
+    /// Arrival order within the queue, assigned on push; restored messages are renumbered
+    /// in their stored order
+    pub enqueue_sequence: u64,
+    /// Stamped from the queue's clock on push, like `visible_at`
+    pub sent_at: Instant,
     pub visible_at: Instant,
+    /// Visibility can't be extended past `MAX_VISIBILITY_TIMEOUT` from here, however many
+    /// times the message is received
     pub first_received_at: Option<Instant>,
+    /// When the current delivery was received, i.e. when `receipt_handle` was issued
+    pub received_at: Option<Instant>,
+    /// FIFO only
+    pub message_group_id: Option<String>,
+    /// FIFO only
+    pub message_deduplication_id: Option<String>,
+    /// FIFO only, assigned when the message is pushed
+    pub sequence_number: Option<String>,
+    /// Sorted by name
+    pub message_attributes: Vec<MessageAttribute>,
+    /// `MessageSystemAttribute.N.*`; only `AWSTraceHeader` exists
+    pub message_system_attributes: Vec<MessageAttribute>,
+    /// Received from a queue with `at_most_once` set, so never receivable again
+    pub consumed: bool,
 }
 
 impl Message {
@@ -18,26 +105,160 @@ impl Message {
             message_body,
             receipt_handle: None,
             receive_count: 0,
+            enqueue_sequence: 0,
+            sent_at: Instant::now(),
             visible_at: Instant::now(),
             first_received_at: None,
+            received_at: None,
+            message_group_id: None,
+            message_deduplication_id: None,
+            sequence_number: None,
+            message_attributes: Vec::new(),
+            message_system_attributes: Vec::new(),
+            consumed: false,
         }
     }
+
+    /// Bytes counted against `MaximumMessageSize`: the UTF-8 body plus the message
+    /// attributes. System attributes don't count, as in AWS.
+    pub fn size(&self) -> usize {
+        self.message_body.len()
+            + self
+                .message_attributes
+                .iter()
+                .map(MessageAttribute::size)
+                .sum::<usize>()
+    }
+
+    /// The lifetime set by a `MESSAGE_TTL_ATTRIBUTE` attribute, if it has a valid one.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.message_attributes
+            .iter()
+            .find(|a| a.name == MESSAGE_TTL_ATTRIBUTE)
+            .and_then(|a| match &a.value {
+                MessageAttributeValue::String(value) => value.trim().parse::<u64>().ok(),
+                MessageAttributeValue::Binary(_) => None,
+            })
+            .map(Duration::from_secs)
+    }
+
+    /// Still inside its initial DelaySeconds window and never delivered.
+    pub fn is_delayed(&self, now: Instant) -> bool {
+        self.first_received_at.is_none() && self.visible_at > now
+    }
+
+    /// Delivered to a consumer and still within its visibility timeout.
+    pub fn is_in_flight(&self, now: Instant) -> bool {
+        self.first_received_at.is_some() && self.visible_at > now
+    }
+}
+
+/// A message can be received once its delay or visibility timeout has lapsed,
+/// whether or not it has been delivered before.
+fn is_receivable(msg: &Message, now: Instant) -> bool {
+    !msg.consumed && msg.visible_at <= now
+}
+
+/// Why `Queue::change_visibility` refused a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeVisibilityError {
+    /// No message currently holds the receipt handle, or it is older than `receipt_handle_ttl`
+    NotFound,
+    /// The message would stay in flight longer than `MAX_VISIBILITY_TIMEOUT` after it was
+    /// first received
+    LimitExceeded,
+}
+
+/// Messages sent to, received from and deleted from a queue since it was created, like
+/// CloudWatch's `NumberOfMessages*` metrics. Purges don't count as deletes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub sent: u64,
+    /// Every delivery, so a message received twice counts twice
+    pub received: u64,
+    pub deleted: u64,
+}
+
+/// Message counts as reported by the `ApproximateNumberOfMessages*` attributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueCounts {
+    /// Available to receive
+    pub visible: usize,
+    /// Received and still within their visibility timeout
+    pub not_visible: usize,
+    /// Waiting out their initial delay
+    pub delayed: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct QueueTags {
-    key: String,
-    value: String,
+    pub key: String,
+    pub value: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct Queue {
-    #[allow(dead_code)]
     name: String,
-    #[allow(dead_code)]
+    /// Sorted by key
     tags: Vec<QueueTags>,
     pub default_visibility_timeout: u32,
-    messages: VecDeque<Message>,
+    /// The queue's `DelaySeconds`, applied to sends that don't set their own delay.
+    pub default_delay_seconds: u32,
+    /// The queue's `MaximumMessageSize`: most bytes a message may take, see `Message::size`.
+    pub maximum_message_size: usize,
+    pub is_fifo: bool,
+    /// Maximum number of messages the queue may hold; `None` means unbounded.
+    pub max_messages: Option<usize>,
+    /// Most messages that may be in flight at once; `IN_FLIGHT_LIMIT` unless changed.
+    pub in_flight_limit: usize,
+    /// Keyed by `enqueue_sequence`, so iteration is in queue order.
+    messages: BTreeMap<u64, Message>,
+    /// `enqueue_sequence` of the next pushed message.
+    next_enqueue_sequence: u64,
+    /// Keys of messages that were receivable when last checked.
+    visible: BTreeSet<u64>,
+    /// When each message that isn't in `visible` becomes receivable. Entries whose time no
+    /// longer matches the message's `visible_at`, or whose message is gone, are stale and
+    /// skipped.
+    pending: BinaryHeap<Reverse<(Instant, u64)>>,
+    /// Current receipt handle -> key.
+    handles: HashMap<String, u64>,
+    /// When each message with its own TTL expires; entries for deleted messages are stale.
+    expiries: BinaryHeap<Reverse<(Instant, u64)>>,
+    /// Drop messages once their `MESSAGE_TTL_ATTRIBUTE` runs out. Not AWS behaviour.
+    pub honor_message_ttl: bool,
+    /// Deliver each message at most once: a received message is never receivable again,
+    /// whatever its visibility timeout, but stays in the queue until deleted or purged.
+    /// Not AWS behaviour, which is at-least-once.
+    pub at_most_once: bool,
+    /// Throttles SendMessage/ReceiveMessage when set.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Last sequence number handed out to a FIFO message.
+    last_sequence_number: u64,
+    /// Signalled to wake long-polling receivers whenever what they can receive, or when,
+    /// may have changed: a message is pushed, restored, taken with `pop_front`, deleted or
+    /// removed (which may unblock a FIFO group), or has its visibility changed. Woken
+    /// receivers try again and recompute when the next message becomes visible.
+    notify: Arc<Notify>,
+    /// FIFO receive results keyed by `ReceiveRequestAttemptId`, with the time they were made.
+    receive_attempts: HashMap<String, (Instant, Vec<Message>)>,
+    /// Minimum time between two purges; `PURGE_COOLDOWN` unless changed for tests.
+    pub purge_cooldown: Duration,
+    last_purged_at: Option<Instant>,
+    stats: QueueStats,
+    /// How long a receipt handle can delete or change the visibility of its message;
+    /// for as long as the handle is current when unset, as in AWS
+    pub receipt_handle_ttl: Option<Duration>,
+    /// A message already received this many times is dropped instead of being received
+    /// again. Not AWS behaviour: the server-wide default for queues without a RedrivePolicy.
+    pub max_receive_count: Option<u32>,
+    /// Receipt handles of recently deleted messages, with the time they were deleted.
+    recently_deleted: HashMap<String, Instant>,
+    /// When set, a standard queue hands out visible messages in random order instead of
+    /// queue order, the way real standard queues give no ordering guarantee.
+    pub shuffle_rng: Option<StdRng>,
+    /// What `now` is for delays, visibility timeouts and handle and message ages
+    pub clock: Arc<dyn Clock>,
 }
 
 /// Queue is a FIFO data structure to implement a mock SQS queue.
@@ -46,94 +267,556 @@ impl Queue {
         Queue {
             name: name.to_owned(),
             tags,
-            default_visibility_timeout: default_visibility_timeout.unwrap_or(30),
-            messages: VecDeque::new(),
+            default_visibility_timeout: default_visibility_timeout
+                .unwrap_or(DEFAULT_VISIBILITY_TIMEOUT),
+            default_delay_seconds: 0,
+            maximum_message_size: MAX_MESSAGE_SIZE,
+            at_most_once: false,
+            is_fifo: false,
+            max_messages: None,
+            in_flight_limit: IN_FLIGHT_LIMIT,
+            rate_limiter: None,
+            last_sequence_number: 0,
+            messages: BTreeMap::new(),
+            next_enqueue_sequence: 0,
+            visible: BTreeSet::new(),
+            pending: BinaryHeap::new(),
+            handles: HashMap::new(),
+            expiries: BinaryHeap::new(),
+            honor_message_ttl: false,
+            notify: Arc::new(Notify::new()),
+            receive_attempts: HashMap::new(),
+            purge_cooldown: PURGE_COOLDOWN,
+            last_purged_at: None,
+            stats: QueueStats::default(),
+            receipt_handle_ttl: None,
+            max_receive_count: None,
+            recently_deleted: HashMap::new(),
+            shuffle_rng: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Push a message to the back of the queue, receivable straight away.
+    /// Returns false if the queue already holds `max_messages` messages.
+    pub fn push(&mut self, msg: Message) -> bool {
+        self.push_with_delay(msg, 0)
+    }
+
+    /// Push a message that only becomes receivable after `delay_seconds`.
+    /// Returns false if the queue is full.
+    pub fn push_with_delay(&mut self, mut msg: Message, delay_seconds: u32) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let now = self.now();
+        msg.sent_at = now;
+        msg.visible_at = now + Duration::from_secs(delay_seconds as u64);
+        if self.is_fifo {
+            self.last_sequence_number += 1;
+            // AWS sequence numbers are large, zero-padded decimal strings
+            msg.sequence_number = Some(format!("{:020}", self.last_sequence_number));
+        }
+        self.insert(msg, now);
+        self.stats.sent += 1;
+        self.notify.notify_waiters();
+        true
+    }
+
+    /// Append a message, numbering it in arrival order. Pushes are serialized by the
+    /// queue's lock, so a plain counter gives each message a unique, increasing number.
+    fn insert(&mut self, mut msg: Message, now: Instant) {
+        let key = self.next_enqueue_sequence;
+        self.next_enqueue_sequence += 1;
+        msg.enqueue_sequence = key;
+        self.index(key, &msg, now);
+        if let Some(ttl) = msg.ttl().filter(|_| self.honor_message_ttl) {
+            self.expiries.push(Reverse((msg.sent_at + ttl, key)));
+        }
+        self.messages.insert(key, msg);
+    }
+
+    /// Add a message to the visibility and receipt handle indexes. Consumed messages
+    /// only get their receipt handle indexed, so they can still be deleted.
+    fn index(&mut self, key: u64, msg: &Message, now: Instant) {
+        if msg.consumed {
+            self.visible.remove(&key);
+        } else if msg.visible_at <= now {
+            self.visible.insert(key);
+        } else {
+            self.visible.remove(&key);
+            self.pending.push(Reverse((msg.visible_at, key)));
+        }
+        if let Some(handle) = &msg.receipt_handle {
+            self.handles.insert(handle.clone(), key);
+        }
+    }
+
+    /// Move messages whose delay or visibility timeout has lapsed into `visible`.
+    fn promote_visible(&mut self, now: Instant) {
+        while let Some(&Reverse((visible_at, key))) = self.pending.peek() {
+            if visible_at > now {
+                break;
+            }
+            self.pending.pop();
+            if self
+                .messages
+                .get(&key)
+                .is_some_and(|msg| msg.visible_at == visible_at)
+            {
+                self.visible.insert(key);
+            }
+        }
+        // Deletes and visibility changes leave stale entries behind; drop them once
+        // they outnumber the live ones
+        if self.pending.len() > 2 * self.messages.len() + 64 {
+            self.pending = self
+                .messages
+                .iter()
+                .filter(|(key, msg)| !msg.consumed && !self.visible.contains(key))
+                .map(|(&key, msg)| Reverse((msg.visible_at, key)))
+                .collect();
+        }
+    }
+
+    /// Take a message out of the queue and its indexes.
+    fn remove_key(&mut self, key: u64) -> Option<Message> {
+        let msg = self.messages.remove(&key)?;
+        self.visible.remove(&key);
+        if let Some(handle) = &msg.receipt_handle {
+            self.handles.remove(handle);
+        }
+        Some(msg)
+    }
+
+    /// Replace the queue's contents with previously stored messages, e.g. on restart.
+    /// Sequence numbers carry on from the highest one restored.
+    pub fn restore_messages(&mut self, messages: Vec<Message>) {
+        self.last_sequence_number = messages
+            .iter()
+            .filter_map(|m| m.sequence_number.as_ref()?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+        self.messages.clear();
+        self.visible.clear();
+        self.pending.clear();
+        self.handles.clear();
+        self.expiries.clear();
+        let now = self.now();
+        for msg in messages {
+            self.insert(msg, now);
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Drop messages whose own TTL has run out, in flight or not.
+    /// Returns the number dropped.
+    pub fn expire_messages(&mut self, now: Instant) -> usize {
+        let mut expired = 0;
+        while let Some(&Reverse((expires_at, key))) = self.expiries.peek() {
+            if expires_at > now {
+                break;
+            }
+            self.expiries.pop();
+            if self.remove_key(key).is_some() {
+                expired += 1;
+            }
+        }
+        expired
+    }
+
+    /// Take the first message in queue order out of the queue, whatever its visibility.
+    pub fn pop_front(&mut self) -> Option<Message> {
+        let key = *self.messages.keys().next()?;
+        let msg = self.remove_key(key);
+        self.notify.notify_waiters();
+        msg
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tags(&self) -> &[QueueTags] {
+        &self.tags
+    }
+
+    /// All messages in queue order, whatever their visibility.
+    pub fn messages(&self) -> impl Iterator<Item = &Message> {
+        self.messages.values()
+    }
+
+    /// Count a request against the queue's rate limit.
+    /// Returns false if the request should be throttled.
+    pub fn try_acquire_request(&mut self) -> bool {
+        let now = self.now();
+        match self.rate_limiter.as_mut() {
+            Some(limiter) => limiter.try_acquire(now),
+            None => true,
         }
     }
 
-    pub fn push(&mut self, msg: Message) {
-        self.messages.push_back(msg);
+    /// Handle used by receivers to wait for new messages.
+    /// Register interest (`Notified::enable`) before releasing the queue lock,
+    /// otherwise a push in between would be missed.
+    pub fn notifier(&self) -> Arc<Notify> {
+        self.notify.clone()
+    }
+
+    /// The current time on the queue's clock.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The soonest a delayed or in-flight message may become receivable, if there is one.
+    /// Can be early, but never late, since stale index entries are only dropped on receive.
+    pub fn next_visible_at(&self) -> Option<Instant> {
+        self.pending
+            .peek()
+            .map(|Reverse((visible_at, _))| *visible_at)
+    }
+
+    /// Whether the queue has reached its configured message limit.
+    pub fn is_full(&self) -> bool {
+        self.max_messages
+            .is_some_and(|max| self.messages.len() >= max)
+    }
+
+    /// Whether there are messages to receive, but `in_flight_limit` messages are already
+    /// in flight so none can be taken. Only up to date straight after a receive.
+    pub fn is_at_in_flight_limit(&self) -> bool {
+        !self.visible.is_empty() && self.in_flight_room(1, self.now()) == 0
+    }
+
+    /// How many of `wanted` messages can go in flight without passing `in_flight_limit`.
+    fn in_flight_room(&self, wanted: usize, now: Instant) -> usize {
+        // Whatever isn't visible bounds the number in flight; only count exactly when
+        // that bound gets close to the limit
+        let not_visible = self.messages.len() - self.visible.len();
+        if not_visible + wanted <= self.in_flight_limit {
+            return wanted;
+        }
+        let in_flight = self
+            .messages
+            .values()
+            .filter(|m| m.is_in_flight(now))
+            .count();
+        wanted.min(self.in_flight_limit.saturating_sub(in_flight))
     }
 
     /// Receive up to `max_messages` visible messages from the queue.
     /// Messages are not removed — they become invisible for `visibility_timeout` seconds.
-    /// Returns clones of the received messages (with receipt_handle set).
+    /// Returns clones of the received messages (with receipt_handle set), fewer than asked
+    /// for if more would pass `in_flight_limit`.
+    ///
+    /// A `visibility_timeout` of `Some(0)` peeks: the message stays receivable, but the
+    /// delivery still counts towards `receive_count` and issues a fresh receipt handle.
+    ///
+    /// Messages that have used up `max_receive_count` are removed instead of returned.
+    ///
+    /// Only visible messages are looked at, so messages in flight or delayed don't slow
+    /// down a receive, except that FIFO queues check every message for blocked groups.
     pub fn receive(&mut self, max_messages: u32, visibility_timeout: Option<u32>) -> Vec<Message> {
         let timeout = visibility_timeout.unwrap_or(self.default_visibility_timeout);
-        let now = Instant::now();
+        let now = self.now();
+        let visible_at = now + Duration::from_secs(timeout as u64);
         let mut received = Vec::new();
+        self.expire_messages(now);
+        self.promote_visible(now);
+        // A peek leaves messages visible, so it doesn't count towards the limit
+        let max_messages = if timeout == 0 {
+            max_messages as usize
+        } else {
+            self.in_flight_room(max_messages as usize, now)
+        };
+
+        // FIFO: a group with a message in flight is blocked until that message
+        // is deleted or becomes visible again. That includes messages handed out
+        // by this call, so at most one message per group is returned.
+        let mut blocked_groups: HashSet<String> = if self.is_fifo {
+            self.messages
+                .values()
+                .filter(|m| m.is_in_flight(now))
+                .filter_map(|m| m.message_group_id.clone())
+                .collect()
+        } else {
+            HashSet::new()
+        };
 
-        for msg in self.messages.iter_mut() {
-            if received.len() >= max_messages as usize {
+        let shuffled = match (self.is_fifo, self.shuffle_rng.as_mut()) {
+            (false, Some(rng)) => {
+                let mut keys: Vec<u64> = self.visible.iter().copied().collect();
+                keys.shuffle(rng);
+                Some(keys)
+            }
+            _ => None,
+        };
+        let candidates: Box<dyn Iterator<Item = u64>> = match shuffled {
+            Some(keys) => Box::new(keys.into_iter()),
+            None => Box::new(self.visible.iter().copied()),
+        };
+        let mut taken = Vec::new();
+        let mut dropped = Vec::new();
+        for key in candidates {
+            if received.len() >= max_messages {
                 break;
             }
-            if msg.visible_at <= now {
-                let handle = uuid::Uuid::new_v4().to_string();
-                msg.receipt_handle = Some(handle);
-                msg.receive_count += 1;
-                msg.visible_at = now + std::time::Duration::from_secs(timeout as u64);
-                if msg.first_received_at.is_none() {
-                    msg.first_received_at = Some(now);
+            let msg = self.messages.get_mut(&key).expect("visible message exists");
+            let group_blocked = msg
+                .message_group_id
+                .as_ref()
+                .is_some_and(|group| blocked_groups.contains(group));
+            if group_blocked {
+                continue;
+            }
+            if self
+                .max_receive_count
+                .is_some_and(|max| msg.receive_count >= max)
+            {
+                dropped.push(key);
+                continue;
+            }
+            if let Some(old) = msg.receipt_handle.take() {
+                self.handles.remove(&old);
+            }
+            let handle = encode_receipt_handle(&self.name, &msg.id);
+            msg.receipt_handle = Some(handle);
+            msg.receive_count += 1;
+            msg.visible_at = visible_at;
+            msg.received_at = Some(now);
+            msg.consumed = self.at_most_once;
+            if msg.first_received_at.is_none() {
+                msg.first_received_at = Some(now);
+            }
+            if self.is_fifo {
+                if let Some(group) = &msg.message_group_id {
+                    blocked_groups.insert(group.clone());
                 }
-                received.push(msg.clone());
             }
+            received.push(msg.clone());
+            self.stats.received += 1;
+            taken.push(key);
+        }
+
+        for (key, msg) in taken.into_iter().zip(&received) {
+            self.index(key, msg, now);
+        }
+        for key in dropped {
+            self.remove_key(key);
+        }
+        received
+    }
+
+    /// Receive for a FIFO queue with a `ReceiveRequestAttemptId`.
+    /// A retry with the same attempt id within the dedup window returns the
+    /// original messages and receipt handles instead of advancing the queue.
+    pub fn receive_with_attempt_id(
+        &mut self,
+        attempt_id: &str,
+        max_messages: u32,
+        visibility_timeout: Option<u32>,
+    ) -> Vec<Message> {
+        let now = self.now();
+        self.receive_attempts
+            .retain(|_, (made_at, _)| now.duration_since(*made_at) < RECEIVE_ATTEMPT_TTL);
+
+        if let Some((_, messages)) = self.receive_attempts.get(attempt_id) {
+            return messages.clone();
         }
 
+        let received = self.receive(max_messages, visibility_timeout);
+        // Empty results aren't cached so a long-polling retry can still pick up new messages
+        if !received.is_empty() {
+            self.receive_attempts
+                .insert(attempt_id.to_string(), (now, received.clone()));
+        }
         received
     }
 
-    /// Delete a message by its receipt handle. Returns true if found and removed.
+    /// Delete a message by its receipt handle. Returns true if found and removed,
+    /// or if the same handle already deleted it in the last minute, as AWS does.
+    /// Waiting receivers are woken, since the delete may unblock a FIFO group.
     pub fn delete_by_receipt_handle(&mut self, handle: &str) -> bool {
-        if let Some(pos) = self
-            .messages
-            .iter()
-            .position(|m| m.receipt_handle.as_deref() == Some(handle))
-        {
-            self.messages.remove(pos);
+        let now = self.now();
+        self.recently_deleted
+            .retain(|_, deleted_at| now.duration_since(*deleted_at) < DELETED_HANDLE_TTL);
+
+        if let Some(&key) = self.handles.get(handle) {
+            if self.is_handle_expired(key, now) {
+                return false;
+            }
+            self.remove_key(key);
+            self.recently_deleted.insert(handle.to_string(), now);
+            self.stats.deleted += 1;
+            self.notify.notify_waiters();
             true
         } else {
-            false
+            self.recently_deleted.contains_key(handle)
         }
     }
 
-    /// Change the visibility timeout of an in-flight message. Returns true if found.
-    pub fn change_visibility(&mut self, handle: &str, timeout: u32) -> bool {
-        if let Some(msg) = self
+    /// Whether the receipt handle of the message at `key` has outlived `receipt_handle_ttl`.
+    /// Restored messages don't know when their handle was issued, so it counts from their
+    /// first receive.
+    fn is_handle_expired(&self, key: u64, now: Instant) -> bool {
+        let Some(ttl) = self.receipt_handle_ttl else {
+            return false;
+        };
+        self.messages
+            .get(&key)
+            .and_then(|msg| msg.received_at.or(msg.first_received_at))
+            .is_some_and(|issued_at| now.duration_since(issued_at) > ttl)
+    }
+
+    /// Make an in-flight message visible again `timeout` seconds from now.
+    pub fn change_visibility(
+        &mut self,
+        handle: &str,
+        timeout: u32,
+    ) -> Result<(), ChangeVisibilityError> {
+        let now = self.now();
+        let key = *self
+            .handles
+            .get(handle)
+            .ok_or(ChangeVisibilityError::NotFound)?;
+        if self.is_handle_expired(key, now) {
+            return Err(ChangeVisibilityError::NotFound);
+        }
+        let msg = self
             .messages
-            .iter_mut()
-            .find(|m| m.receipt_handle.as_deref() == Some(handle))
-        {
-            msg.visible_at = Instant::now() + std::time::Duration::from_secs(timeout as u64);
-            true
+            .get_mut(&key)
+            .ok_or(ChangeVisibilityError::NotFound)?;
+
+        let visible_at = now + Duration::from_secs(timeout as u64);
+        if let Some(first_received_at) = msg.first_received_at {
+            let limit = first_received_at + Duration::from_secs(MAX_VISIBILITY_TIMEOUT as u64);
+            if visible_at > limit {
+                return Err(ChangeVisibilityError::LimitExceeded);
+            }
+        }
+        msg.visible_at = visible_at;
+        if msg.consumed {
+            // Nothing to requeue
+        } else if visible_at <= now {
+            self.visible.insert(key);
         } else {
-            false
+            self.visible.remove(&key);
+            self.pending.push(Reverse((visible_at, key)));
+        }
+        // Waiting receivers may now have a message to take, or an earlier time to wake at
+        self.notify.notify_waiters();
+        Ok(())
+    }
+
+    /// Delete every message, in flight or not.
+    /// Returns false without purging if the last purge was less than `purge_cooldown` ago.
+    pub fn purge(&mut self) -> bool {
+        let now = self.now();
+        if self
+            .last_purged_at
+            .is_some_and(|last| now.duration_since(last) < self.purge_cooldown)
+        {
+            return false;
         }
+        self.messages.clear();
+        self.visible.clear();
+        self.pending.clear();
+        self.handles.clear();
+        self.receive_attempts.clear();
+        self.last_purged_at = Some(now);
+        true
     }
 
-    /// Count of messages that are currently visible (available to receive).
-    pub fn approximate_number_of_messages(&self) -> usize {
-        let now = Instant::now();
-        self.messages.iter().filter(|m| m.visible_at <= now).count()
+    /// Snapshot of the visible, in-flight and delayed message counts,
+    /// all taken in one pass against the same `now`.
+    pub fn counts(&self) -> QueueCounts {
+        let now = self.now();
+        let mut counts = QueueCounts::default();
+        for msg in self.messages.values() {
+            if is_receivable(msg, now) {
+                counts.visible += 1;
+            } else if msg.is_delayed(now) {
+                counts.delayed += 1;
+            } else {
+                counts.not_visible += 1;
+            }
+        }
+        counts
     }
 
-    /// Count of messages that are currently in-flight (not visible).
-    pub fn approximate_number_of_messages_not_visible(&self) -> usize {
-        let now = Instant::now();
-        self.messages.iter().filter(|m| m.visible_at > now).count()
+    pub fn stats(&self) -> QueueStats {
+        self.stats
     }
 
-    /// Remove a message from the queue by id.
-    pub fn remove(&mut self, id: &str) {
+    /// Seconds since the oldest visible message was sent, or 0 if none is visible.
+    pub fn approximate_age_of_oldest_message_secs(&self) -> u64 {
+        let now = self.now();
         self.messages
+            .values()
+            .filter(|msg| is_receivable(msg, now))
+            .map(|msg| now.saturating_duration_since(msg.sent_at).as_secs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Remove a message from the queue by id, whatever its visibility.
+    /// Returns false if no message has that id.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let key = self
+            .messages
             .iter()
-            .position(|message| message.id == id)
-            .map(|index| self.messages.remove(index));
+            .find(|(_, message)| message.id == id)
+            .map(|(&key, _)| key);
+        match key {
+            Some(key) => {
+                self.remove_key(key);
+                // As with a delete, this may unblock a FIFO group
+                self.notify.notify_waiters();
+                true
+            }
+            None => false,
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::clock::MockClock;
+
+    /// A queue whose clock only moves when the test advances it.
+    fn queue_with_mock_clock(name: &str) -> (Queue, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::default());
+        let mut queue = Queue::new(name, vec![], None);
+        queue.clock = clock.clone();
+        (queue, clock)
+    }
+
+    #[test]
+    fn test_message_size_counts_utf8_bytes_and_attributes() {
+        // Two bytes per character in UTF-8
+        let mut message = Message::new("1".to_owned(), "é".repeat(10));
+        assert_eq!(message.size(), 20);
+
+        message.message_attributes = vec![
+            MessageAttribute {
+                name: "city".to_owned(),
+                data_type: "String".to_owned(),
+                value: MessageAttributeValue::String("Zürich".to_owned()),
+            },
+            MessageAttribute {
+                name: "img".to_owned(),
+                data_type: "Binary.gif".to_owned(),
+                value: MessageAttributeValue::Binary(vec![0; 5]),
+            },
+        ];
+        message.message_system_attributes = vec![MessageAttribute {
+            name: "AWSTraceHeader".to_owned(),
+            data_type: "String".to_owned(),
+            value: MessageAttributeValue::String("Root=1".to_owned()),
+        }];
+        assert_eq!(message.size(), 20 + (4 + 6 + 7) + (3 + 10 + 5));
+    }
 
     #[test]
     fn test_push() {
@@ -141,7 +824,18 @@ mod test {
         let message = Message::new("id".to_owned(), "message_body".to_owned());
         queue.push(message);
         assert_eq!(queue.messages.len(), 1);
-        assert_eq!(queue.messages[0].id, "id");
+        assert_eq!(queue.messages().next().unwrap().id, "id");
+    }
+
+    #[test]
+    fn test_push_rejects_when_full() {
+        let mut queue = Queue::new("test", vec![], None);
+        queue.max_messages = Some(2);
+        assert!(queue.push(Message::new("1".to_owned(), "body1".to_owned())));
+        assert!(queue.push(Message::new("2".to_owned(), "body2".to_owned())));
+        assert!(queue.is_full());
+        assert!(!queue.push(Message::new("3".to_owned(), "body3".to_owned())));
+        assert_eq!(queue.messages.len(), 2);
     }
 
     #[test]
@@ -149,8 +843,24 @@ mod test {
         let mut queue = Queue::new("test", vec![], None);
         let message = Message::new("id".to_owned(), "message_body".to_owned());
         queue.push(message);
-        queue.remove("id");
+        assert!(queue.remove("id"));
         assert_eq!(queue.messages.len(), 0);
+        assert!(!queue.remove("id"));
+    }
+
+    #[test]
+    fn test_remove_in_flight_message() {
+        let mut queue = Queue::new("test", vec![], None);
+        queue.push(Message::new("id".to_owned(), "message_body".to_owned()));
+        let handle = queue.receive(1, None)[0].receipt_handle.clone().unwrap();
+
+        assert!(queue.remove("id"));
+        // Its receipt handle goes with it
+        assert!(!queue.delete_by_receipt_handle(&handle));
+        assert_eq!(
+            queue.change_visibility(&handle, 0),
+            Err(ChangeVisibilityError::NotFound)
+        );
     }
 
     #[test]
@@ -182,14 +892,95 @@ mod test {
     }
 
     #[test]
-    fn test_delete_by_receipt_handle() {
+    fn test_shuffled_receive_is_seeded() {
+        use rand::SeedableRng;
+
+        let mut queue = Queue::new("test", vec![], None);
+        queue.shuffle_rng = Some(StdRng::seed_from_u64(7));
+        for i in 0..5 {
+            queue.push(Message::new(i.to_string(), format!("body{}", i)));
+        }
+        let ids: Vec<String> = queue.receive(5, None).into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, ["1", "4", "3", "0", "2"]);
+
+        // FIFO queues keep their order regardless
+        let mut queue = Queue::new("test.fifo", vec![], None);
+        queue.is_fifo = true;
+        queue.shuffle_rng = Some(StdRng::seed_from_u64(7));
+        for i in 0..5 {
+            let mut message = Message::new(i.to_string(), format!("body{}", i));
+            message.message_group_id = Some(i.to_string());
+            queue.push(message);
+        }
+        let ids: Vec<String> = queue.receive(5, None).into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, ["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_age_of_oldest_message() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        assert_eq!(queue.approximate_age_of_oldest_message_secs(), 0);
+
+        queue.push(Message::new("old".to_owned(), "body".to_owned()));
+        clock.advance(Duration::from_secs(120));
+        queue.push(Message::new("new".to_owned(), "body".to_owned()));
+        assert_eq!(queue.approximate_age_of_oldest_message_secs(), 120);
+
+        // In-flight messages don't count
+        queue.receive(1, Some(60));
+        queue.receive(1, Some(60));
+        assert_eq!(queue.approximate_age_of_oldest_message_secs(), 0);
+    }
+
+    #[test]
+    fn test_message_visible_again_keeps_its_place() {
+        let mut queue = Queue::new("test", vec![], None);
+        for id in ["a", "b", "c"] {
+            queue.push(Message::new(id.to_owned(), "body".to_owned()));
+        }
+        let handle = queue.receive(1, Some(60))[0]
+            .receipt_handle
+            .clone()
+            .unwrap();
+        assert_eq!(queue.receive(1, Some(60))[0].id, "b");
+
+        queue.change_visibility(&handle, 0).unwrap();
+        let ids: Vec<String> = queue
+            .receive(10, Some(60))
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(ids, ["a", "c"]);
+        // The handle was replaced by the new delivery's
+        assert!(!queue.delete_by_receipt_handle(&handle));
+    }
+
+    #[test]
+    fn test_stale_index_entries_are_dropped() {
         let mut queue = Queue::new("test", vec![], None);
+        for i in 0..1000 {
+            queue.push(Message::new(i.to_string(), "body".to_owned()));
+            let received = queue.receive(1, Some(600));
+            assert!(queue.delete_by_receipt_handle(received[0].receipt_handle.as_ref().unwrap()));
+        }
+        assert!(queue.pending.len() <= 64);
+        assert!(queue.handles.is_empty() && queue.visible.is_empty());
+    }
+
+    #[test]
+    fn test_delete_by_receipt_handle() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
         queue.push(Message::new("1".to_owned(), "body".to_owned()));
         let received = queue.receive(1, None);
         let handle = received[0].receipt_handle.as_ref().unwrap().clone();
 
         assert!(queue.delete_by_receipt_handle(&handle));
         assert_eq!(queue.messages.len(), 0);
+
+        // Deleting again succeeds for a while, as in AWS
+        assert!(queue.delete_by_receipt_handle(&handle));
+        clock.advance(DELETED_HANDLE_TTL);
+        assert!(!queue.delete_by_receipt_handle(&handle));
     }
 
     #[test]
@@ -206,7 +997,7 @@ mod test {
         let handle = received[0].receipt_handle.as_ref().unwrap().clone();
 
         // Set visibility to 0 — should make it immediately visible again
-        assert!(queue.change_visibility(&handle, 0));
+        assert!(queue.change_visibility(&handle, 0).is_ok());
 
         let received2 = queue.receive(1, None);
         assert_eq!(received2.len(), 1);
@@ -214,18 +1005,358 @@ mod test {
         assert_eq!(received2[0].receive_count, 2);
     }
 
+    #[test]
+    fn test_change_visibility_capped_at_twelve_hours() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.push(Message::new("1".to_owned(), "body".to_owned()));
+        let received = queue.receive(1, None);
+        let handle = received[0].receipt_handle.clone().unwrap();
+
+        // Keep extending by an hour as a slow consumer would
+        let mut hours = 0;
+        let result = loop {
+            match queue.change_visibility(&handle, 3600) {
+                Ok(()) => hours += 1,
+                Err(e) => break e,
+            }
+            clock.advance(Duration::from_secs(3600));
+        };
+        assert_eq!(result, ChangeVisibilityError::LimitExceeded);
+        assert_eq!(hours, 12);
+
+        assert_eq!(
+            queue.change_visibility(&handle, MAX_VISIBILITY_TIMEOUT + 1),
+            Err(ChangeVisibilityError::LimitExceeded)
+        );
+        assert_eq!(
+            queue.change_visibility("unknown", 0),
+            Err(ChangeVisibilityError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_visibility_cap_counts_from_first_receive() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.push(Message::new("1".to_owned(), "body".to_owned()));
+        queue.receive(1, Some(60));
+
+        // Received again eleven and a half hours after the first receive
+        clock.advance(Duration::from_secs(11 * 3600 + 1800));
+        let received = queue.receive(1, None);
+        assert_eq!(received[0].receive_count, 2);
+        let handle = received[0].receipt_handle.clone().unwrap();
+
+        // The new delivery doesn't restart the twelve hours
+        assert_eq!(
+            queue.change_visibility(&handle, 3600),
+            Err(ChangeVisibilityError::LimitExceeded)
+        );
+        assert_eq!(queue.change_visibility(&handle, 1800), Ok(()));
+    }
+
+    #[test]
+    fn test_receipt_handle_ttl() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.receipt_handle_ttl = Some(Duration::from_secs(30));
+        queue.push(Message::new("1".to_owned(), "body".to_owned()));
+        let stale = queue.receive(1, Some(0))[0].receipt_handle.clone().unwrap();
+
+        clock.advance(Duration::from_secs(31));
+        assert_eq!(
+            queue.change_visibility(&stale, 0),
+            Err(ChangeVisibilityError::NotFound)
+        );
+        assert!(!queue.delete_by_receipt_handle(&stale));
+        assert_eq!(queue.counts().visible, 1);
+
+        // Receiving again issues a handle with a fresh TTL
+        let fresh = queue.receive(1, Some(0))[0].receipt_handle.clone().unwrap();
+        assert_eq!(queue.change_visibility(&fresh, 0), Ok(()));
+        assert!(queue.delete_by_receipt_handle(&fresh));
+        assert_eq!(queue.counts(), QueueCounts::default());
+    }
+
+    #[test]
+    fn test_purge_cooldown() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.push(Message::new("1".to_owned(), "body".to_owned()));
+        queue.push(Message::new("2".to_owned(), "body".to_owned()));
+        queue.receive(1, None);
+
+        assert!(queue.purge());
+        assert_eq!(queue.counts(), QueueCounts::default());
+
+        queue.push(Message::new("3".to_owned(), "body".to_owned()));
+        assert!(!queue.purge());
+        assert_eq!(queue.counts().visible, 1);
+
+        clock.advance(PURGE_COOLDOWN);
+        assert!(queue.purge());
+        assert_eq!(queue.counts().visible, 0);
+    }
+
+    #[test]
+    fn test_receive_with_zero_visibility_timeout_peeks() {
+        let mut queue = Queue::new("test", vec![], None);
+        queue.push(Message::new("1".to_owned(), "body".to_owned()));
+
+        let first = queue.receive(1, Some(0));
+        assert_eq!(first[0].receive_count, 1);
+        assert_eq!(queue.counts().visible, 1);
+        assert_eq!(queue.counts().not_visible, 0);
+
+        // Receivable again straight away, with no time passing in between
+        let second = queue.receive(1, Some(0));
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].receive_count, 2);
+        assert_ne!(second[0].receipt_handle, first[0].receipt_handle);
+    }
+
     #[test]
     fn test_approximate_counts() {
         let mut queue = Queue::new("test", vec![], None);
         queue.push(Message::new("1".to_owned(), "body1".to_owned()));
         queue.push(Message::new("2".to_owned(), "body2".to_owned()));
 
-        assert_eq!(queue.approximate_number_of_messages(), 2);
-        assert_eq!(queue.approximate_number_of_messages_not_visible(), 0);
+        assert_eq!(queue.counts().visible, 2);
+        assert_eq!(queue.counts().not_visible, 0);
 
         queue.receive(1, None);
 
-        assert_eq!(queue.approximate_number_of_messages(), 1);
-        assert_eq!(queue.approximate_number_of_messages_not_visible(), 1);
+        assert_eq!(queue.counts().visible, 1);
+        assert_eq!(queue.counts().not_visible, 1);
+    }
+
+    #[test]
+    fn test_delayed_message_is_not_receivable() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.push_with_delay(Message::new("1".to_owned(), "body".to_owned()), 60);
+
+        assert_eq!(queue.counts().visible, 0);
+        assert_eq!(queue.counts().delayed, 1);
+        assert_eq!(queue.counts().not_visible, 0);
+        assert!(queue.receive(10, None).is_empty());
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(queue.counts().visible, 1);
+        assert_eq!(queue.receive(10, None).len(), 1);
+    }
+
+    #[test]
+    fn test_lapsed_visibility_is_receivable_again() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.push(Message::new("1".to_owned(), "body".to_owned()));
+
+        // A zero visibility timeout lapses immediately
+        queue.receive(1, Some(0));
+        assert_eq!(queue.counts().visible, 1);
+        assert_eq!(queue.counts().delayed, 0);
+        assert_eq!(queue.counts().not_visible, 0);
+
+        queue.receive(1, None);
+        assert_eq!(queue.counts().not_visible, 1);
+        assert_eq!(queue.counts().delayed, 0);
+
+        clock.advance(Duration::from_secs(DEFAULT_VISIBILITY_TIMEOUT as u64));
+        assert_eq!(queue.counts().visible, 1);
+        assert_eq!(queue.receive(1, None)[0].receive_count, 3);
+    }
+
+    #[test]
+    fn test_max_receive_count_drops_message() {
+        let mut queue = Queue::new("test", vec![], None);
+        queue.max_receive_count = Some(2);
+        queue.push(Message::new("1".to_string(), "poison".to_string()));
+        queue.push(Message::new("2".to_string(), "fine".to_string()));
+
+        assert_eq!(queue.receive(1, Some(0))[0].id, "1");
+        assert_eq!(queue.receive(1, Some(0))[0].id, "1");
+        // Received twice already, so the third attempt drops it and moves on
+        let received = queue.receive(1, Some(0));
+        assert_eq!(received[0].id, "2");
+        assert_eq!(queue.messages().count(), 1);
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let mut queue = Queue::new("test", vec![], None);
+        queue.in_flight_limit = 2;
+        for i in 0..4 {
+            queue.push(Message::new(i.to_string(), "body".to_string()));
+        }
+
+        assert_eq!(queue.receive(1, None).len(), 1);
+        assert!(!queue.is_at_in_flight_limit());
+        // Only room for one more
+        assert_eq!(queue.receive(10, None).len(), 1);
+        assert!(queue.is_at_in_flight_limit());
+        assert!(queue.receive(10, None).is_empty());
+        // Peeking doesn't put anything in flight
+        assert_eq!(queue.receive(10, Some(0)).len(), 2);
+
+        let handle = queue
+            .messages()
+            .find(|m| m.id == "0")
+            .unwrap()
+            .receipt_handle
+            .clone();
+        assert!(queue.delete_by_receipt_handle(&handle.unwrap()));
+        assert_eq!(queue.receive(10, None).len(), 1);
+    }
+
+    #[test]
+    fn test_per_message_ttl() {
+        let with_ttl = |id: &str, ttl: &str| {
+            let mut msg = Message::new(id.to_string(), "body".to_string());
+            msg.message_attributes.push(MessageAttribute {
+                name: MESSAGE_TTL_ATTRIBUTE.to_string(),
+                data_type: "Number".to_string(),
+                value: MessageAttributeValue::String(ttl.to_string()),
+            });
+            msg
+        };
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.honor_message_ttl = true;
+        queue.push(with_ttl("short", "5"));
+        queue.push(with_ttl("long", "60"));
+        queue.push(Message::new("none".to_string(), "body".to_string()));
+
+        // Going in flight doesn't save a message from expiring
+        assert_eq!(queue.receive(1, None)[0].id, "short");
+        clock.advance(Duration::from_secs(6));
+        let ids: Vec<String> = queue.receive(10, None).into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, ["long", "none"]);
+        assert_eq!(queue.messages().count(), 2);
+
+        // Without the flag the attribute is just an attribute
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.push(with_ttl("short", "5"));
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(queue.receive(10, None).len(), 1);
+    }
+
+    #[test]
+    fn test_at_most_once_never_redelivers() {
+        let (mut queue, clock) = queue_with_mock_clock("test");
+        queue.at_most_once = true;
+        for id in ["1", "2"] {
+            queue.push(Message::new(id.to_owned(), "hi".to_owned()));
+        }
+
+        let first = queue.receive(1, Some(1));
+        assert_eq!(first[0].id, "1");
+        // Neither the visibility timeout running out nor a change of it brings it back
+        clock.advance(Duration::from_secs(5));
+        let handle = first[0].receipt_handle.clone().unwrap();
+        queue.change_visibility(&handle, 0).unwrap();
+        let second = queue.receive(10, Some(0));
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].id, "2");
+        clock.advance(Duration::from_secs(60));
+        assert!(queue.receive(10, None).is_empty());
+
+        let counts = queue.counts();
+        assert_eq!((counts.visible, counts.not_visible), (0, 2));
+        assert_eq!(queue.approximate_age_of_oldest_message_secs(), 0);
+        // Still deletable, and gone for good once purged
+        assert!(queue.delete_by_receipt_handle(&handle));
+        assert!(queue.purge());
+        assert_eq!(queue.messages().count(), 0);
+    }
+
+    #[test]
+    fn test_receive_with_attempt_id_replays_result() {
+        let (mut queue, clock) = queue_with_mock_clock("test.fifo");
+        queue.is_fifo = true;
+        queue.push(Message::new("1".to_owned(), "body1".to_owned()));
+        queue.push(Message::new("2".to_owned(), "body2".to_owned()));
+
+        let first = queue.receive_with_attempt_id("attempt-1", 1, None);
+        let retry = queue.receive_with_attempt_id("attempt-1", 1, None);
+        assert_eq!(first.len(), 1);
+        assert_eq!(retry.len(), 1);
+        assert_eq!(first[0].id, retry[0].id);
+        assert_eq!(first[0].receipt_handle, retry[0].receipt_handle);
+
+        let other = queue.receive_with_attempt_id("attempt-2", 1, None);
+        assert_eq!(other[0].id, "2");
+
+        // Once the attempt id expires, a retry is a new receive
+        clock.advance(RECEIVE_ATTEMPT_TTL);
+        let expired = queue.receive_with_attempt_id("attempt-1", 1, None);
+        assert_eq!(expired[0].id, "1");
+        assert_ne!(expired[0].receipt_handle, first[0].receipt_handle);
+    }
+
+    #[test]
+    fn test_fifo_push_assigns_sequence_numbers() {
+        let mut queue = Queue::new("test.fifo", vec![], None);
+        queue.is_fifo = true;
+        queue.push(Message::new("1".to_owned(), "body1".to_owned()));
+        queue.push(Message::new("2".to_owned(), "body2".to_owned()));
+
+        let received = queue.receive(10, None);
+        assert_eq!(
+            received[0].sequence_number.as_deref(),
+            Some("00000000000000000001")
+        );
+        assert_eq!(
+            received[1].sequence_number.as_deref(),
+            Some("00000000000000000002")
+        );
+    }
+
+    #[test]
+    fn test_counts_cover_every_message() {
+        let mut queue = Queue::new("test", vec![], None);
+        for i in 0..5 {
+            queue.push(Message::new(i.to_string(), format!("body{}", i)));
+        }
+        queue.receive(2, None);
+
+        let counts = queue.counts();
+        assert_eq!(counts.visible, 3);
+        assert_eq!(counts.not_visible, 2);
+        assert_eq!(counts.delayed, 0);
+        assert_eq!(counts.visible + counts.not_visible, queue.messages.len());
+    }
+
+    #[test]
+    fn test_fifo_group_blocked_while_in_flight() {
+        let mut queue = Queue::new("test.fifo", vec![], None);
+        queue.is_fifo = true;
+        for (id, group) in [("1", "a"), ("2", "a"), ("3", "b")] {
+            let mut msg = Message::new(id.to_owned(), "body".to_owned());
+            msg.message_group_id = Some(group.to_owned());
+            queue.push(msg);
+        }
+
+        let first = queue.receive(1, None);
+        assert_eq!(first[0].id, "1");
+
+        // Group "a" is blocked behind message 1, so only group "b" is available
+        let second = queue.receive(10, None);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].id, "3");
+
+        let handle = first[0].receipt_handle.clone().unwrap();
+        assert!(queue.delete_by_receipt_handle(&handle));
+        let third = queue.receive(10, None);
+        assert_eq!(third[0].id, "2");
+    }
+
+    #[test]
+    fn test_fifo_receive_returns_one_message_per_group() {
+        let mut queue = Queue::new("test.fifo", vec![], None);
+        queue.is_fifo = true;
+        for (id, group) in [("1", "a"), ("2", "a"), ("3", "b"), ("4", "b"), ("5", "a")] {
+            let mut msg = Message::new(id.to_owned(), "body".to_owned());
+            msg.message_group_id = Some(group.to_owned());
+            queue.push(msg);
+        }
+
+        let received = queue.receive(10, None);
+        let ids: Vec<&str> = received.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["1", "3"]);
     }
 }
@@ -0,0 +1,61 @@
+use std::time::Instant;
+
+/// Token bucket limiting the request rate on a single queue.
+/// The bucket holds up to one second's worth of requests, so short bursts
+/// up to the limit are allowed before throttling kicks in.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    tokens: f64,
+    /// Unset until the first request, which finds the bucket full
+    last_refill: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        RateLimiter {
+            requests_per_second: requests_per_second as f64,
+            tokens: requests_per_second as f64,
+            last_refill: None,
+        }
+    }
+
+    /// Take a token if one is available at `now`, as told by the queue's clock.
+    /// Returns false if the request should be throttled.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = self.last_refill.map_or(0.0, |last| {
+            now.saturating_duration_since(last).as_secs_f64()
+        });
+        self.tokens =
+            (self.tokens + elapsed * self.requests_per_second).min(self.requests_per_second);
+        self.last_refill = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_throttles_burst_then_recovers() {
+        let mut limiter = RateLimiter::new(5);
+        let start = Instant::now();
+        for _ in 0..5 {
+            assert!(limiter.try_acquire(start));
+        }
+        assert!(!limiter.try_acquire(start));
+
+        // 5 rps refills a token every 200ms
+        let later = start + Duration::from_millis(250);
+        assert!(limiter.try_acquire(later));
+        assert!(!limiter.try_acquire(later));
+    }
+}
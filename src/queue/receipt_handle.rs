@@ -0,0 +1,60 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+
+/// The parts of a receipt handle this server issued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptHandle {
+    pub queue_name: String,
+    pub message_id: String,
+}
+
+/// Build a receipt handle for a message: base64 of `<queue name>:<message id>:<nonce>`.
+/// The nonce keeps every delivery's handle unique.
+pub fn encode_receipt_handle(queue_name: &str, message_id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(format!(
+        "{}:{}:{}",
+        queue_name,
+        message_id,
+        uuid::Uuid::new_v4()
+    ))
+}
+
+/// Split a receipt handle into the queue and message it was issued for.
+/// Returns None for handles this server could not have issued.
+pub fn decode_receipt_handle(handle: &str) -> Option<ReceiptHandle> {
+    let decoded = URL_SAFE_NO_PAD.decode(handle).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (rest, nonce) = decoded.rsplit_once(':')?;
+    // Queue names can't contain ':', so the first one ends the queue name
+    let (queue_name, message_id) = rest.split_once(':')?;
+    if queue_name.is_empty() || message_id.is_empty() || uuid::Uuid::parse_str(nonce).is_err() {
+        return None;
+    }
+    Some(ReceiptHandle {
+        queue_name: queue_name.to_string(),
+        message_id: message_id.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipt_handle_round_trip() {
+        let handle = encode_receipt_handle("q", "msg-1");
+        let decoded = decode_receipt_handle(&handle).unwrap();
+        assert_eq!(decoded.queue_name, "q");
+        assert_eq!(decoded.message_id, "msg-1");
+        assert_ne!(handle, encode_receipt_handle("q", "msg-1"));
+    }
+
+    #[test]
+    fn test_decode_foreign_receipt_handle() {
+        let nonce = uuid::Uuid::new_v4().to_string();
+        assert!(decode_receipt_handle("not a handle").is_none());
+        assert!(decode_receipt_handle(&nonce).is_none());
+        assert!(decode_receipt_handle(&URL_SAFE_NO_PAD.encode("q:msg-1:nonce")).is_none());
+        let no_queue = URL_SAFE_NO_PAD.encode(format!("msg-1:{}", nonce));
+        assert!(decode_receipt_handle(&no_queue).is_none());
+    }
+}
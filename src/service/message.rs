@@ -0,0 +1,102 @@
+use sqlx::SqlitePool;
+
+pub struct Message<'a> {
+    db_pool: &'a SqlitePool,
+}
+
+/// A message as stored in the `messages` table; times are unix epoch millis.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct MessageEntity {
+    pub message_id: String,
+    pub body: String,
+    pub receipt_handle: Option<String>,
+    pub receive_count: i64,
+    pub sent_at: Option<i64>,
+    pub visible_at: i64,
+    pub first_received_at: Option<i64>,
+    pub message_group_id: Option<String>,
+    pub message_deduplication_id: Option<String>,
+    pub sequence_number: Option<String>,
+    /// JSON array
+    pub message_attributes: String,
+    /// JSON array
+    pub message_system_attributes: String,
+}
+
+impl<'a> Message<'a> {
+    pub fn new(db_pool: &'a SqlitePool) -> Self {
+        Message { db_pool }
+    }
+
+    /// Replace every stored message of a queue, keeping the given order.
+    pub async fn replace_queue_messages(
+        &self,
+        queue_name: &str,
+        messages: &[MessageEntity],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db_pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM messages
+            WHERE queue_id = (SELECT MIN(id) FROM queues WHERE name = ?)
+            "#,
+        )
+        .bind(queue_name)
+        .execute(&mut *tx)
+        .await?;
+
+        for msg in messages {
+            sqlx::query(
+                r#"
+                INSERT INTO messages (
+                    queue_id, message_id, body, receipt_handle, receive_count, sent_at, visible_at,
+                    first_received_at, message_group_id, message_deduplication_id, sequence_number,
+                    message_attributes, message_system_attributes
+                )
+                VALUES ((SELECT MIN(id) FROM queues WHERE name = ?), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(queue_name)
+            .bind(&msg.message_id)
+            .bind(&msg.body)
+            .bind(&msg.receipt_handle)
+            .bind(msg.receive_count)
+            .bind(msg.sent_at)
+            .bind(msg.visible_at)
+            .bind(msg.first_received_at)
+            .bind(&msg.message_group_id)
+            .bind(&msg.message_deduplication_id)
+            .bind(&msg.sequence_number)
+            .bind(&msg.message_attributes)
+            .bind(&msg.message_system_attributes)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Get the stored messages of a queue in the order they were queued.
+    pub async fn list_queue_messages(
+        &self,
+        queue_name: &str,
+    ) -> anyhow::Result<Vec<MessageEntity>> {
+        let rows = sqlx::query_as::<_, MessageEntity>(
+            r#"
+            SELECT message_id, body, receipt_handle, receive_count, sent_at, visible_at,
+                first_received_at, message_group_id, message_deduplication_id, sequence_number,
+                message_attributes, message_system_attributes
+            FROM messages
+            WHERE queue_id = (SELECT MIN(id) FROM queues WHERE name = ?)
+            ORDER BY id
+            "#,
+        )
+        .bind(queue_name)
+        .fetch_all(self.db_pool)
+        .await?;
+
+        Ok(rows)
+    }
+}
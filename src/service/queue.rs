@@ -1,9 +1,10 @@
 use sqlx::SqlitePool;
 use std::collections::HashMap;
 
+/// Queue queries against the pool. Only the pool is borrowed: each query checks out a
+/// connection and returns it when done, so this is cheap to build per request.
 pub struct Queue<'a> {
     db_pool: &'a SqlitePool,
-    hostname: &'a str,
 }
 
 #[derive(Debug, Clone)]
@@ -22,7 +23,7 @@ pub struct QueueEntity {
 
 impl QueueEntity {
     fn get_type(&self) -> String {
-        if self.queue_type.contains(".fifo") {
+        if self.queue_type == "Fifo" || self.name.ends_with(".fifo") {
             "Fifo".to_string()
         } else {
             "Standard".to_string()
@@ -31,8 +32,8 @@ impl QueueEntity {
 }
 
 impl<'a> Queue<'a> {
-    pub fn new(db_pool: &'a SqlitePool, hostname: &'a str) -> Self {
-        Queue { db_pool, hostname }
+    pub fn new(db_pool: &'a SqlitePool) -> Self {
+        Queue { db_pool }
     }
 
     /// Create queue attributes in the database
@@ -111,22 +112,38 @@ impl<'a> Queue<'a> {
         Ok(inserted_id.to_string())
     }
 
+    /// List queue names in creation order, at most `max_results` of them, starting after
+    /// the queue with id `after_id`. Also returns the id of the last queue listed if more
+    /// queues follow it.
     pub async fn list_queue(
         &self,
         max_results: u32,
         _queue_name_prefix: Option<String>,
-        _next_token: Option<String>,
-    ) -> anyhow::Result<Vec<String>> {
-        let rows = sqlx::query!(r#"SELECT name FROM queues LIMIT $1"#, max_results)
-            .fetch_all(self.db_pool)
-            .await?;
+        after_id: Option<i64>,
+    ) -> anyhow::Result<(Vec<String>, Option<i64>)> {
+        // One extra row tells us whether there is another page
+        let mut rows: Vec<(i64, String)> =
+            sqlx::query_as(r#"SELECT id, name FROM queues WHERE id > ? ORDER BY id LIMIT ?"#)
+                .bind(after_id.unwrap_or(0))
+                .bind(max_results as i64 + 1)
+                .fetch_all(self.db_pool)
+                .await?;
 
-        let mut queue_urls: Vec<String> = Vec::new();
-        rows.iter().for_each(|row| {
-            queue_urls.push(format!("{}/{}", self.hostname, &row.name));
-        });
+        let truncated = rows.len() > max_results as usize;
+        rows.truncate(max_results as usize);
+        let last_id = rows.last().map(|(id, _)| *id).filter(|_| truncated);
 
-        Ok(queue_urls)
+        let names = rows.into_iter().map(|(_, name)| name).collect();
+        Ok((names, last_id))
+    }
+
+    /// Get the name and type ("Fifo" or "Standard") of every queue, oldest first.
+    pub async fn list_queue_types(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let rows: Vec<(String, String)> =
+            sqlx::query_as(r#"SELECT name, type FROM queues ORDER BY id"#)
+                .fetch_all(self.db_pool)
+                .await?;
+        Ok(rows)
     }
 
     /// Check if a queue exists in the database by name.
@@ -162,6 +179,26 @@ impl<'a> Queue<'a> {
         Ok(map)
     }
 
+    /// Tags of a queue by key.
+    pub async fn get_queue_tags(
+        &self,
+        queue_name: &str,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT t.name, t.value
+            FROM tags t
+            JOIN queues q ON q.id = t.queue_id
+            WHERE q.name = ?
+            "#,
+        )
+        .bind(queue_name)
+        .fetch_all(self.db_pool)
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
     /// Set (upsert) attributes for a queue in the database.
     pub async fn set_queue_attributes(
         &self,
@@ -206,6 +243,45 @@ impl<'a> Queue<'a> {
         Ok(())
     }
 
+    /// Remove attributes from a queue so they revert to their defaults.
+    pub async fn delete_queue_attributes(
+        &self,
+        queue_name: &str,
+        names: &[String],
+    ) -> anyhow::Result<()> {
+        for name in names {
+            sqlx::query(
+                r#"
+                DELETE FROM attributes
+                WHERE name = ? AND queue_id IN (SELECT id FROM queues WHERE name = ?)
+                "#,
+            )
+            .bind(name)
+            .bind(queue_name)
+            .execute(self.db_pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete every queue along with its attributes, tags and stored messages.
+    /// Returns the number of queues deleted.
+    pub async fn delete_all_queues(&self) -> anyhow::Result<u64> {
+        let mut tx = self.db_pool.begin().await?;
+        for table in ["messages", "tags", "attributes"] {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *tx)
+                .await?;
+        }
+        let deleted = sqlx::query("DELETE FROM queues")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
     #[allow(dead_code)]
     pub fn send_message(&self) {
         todo!()
@@ -0,0 +1,234 @@
+//! Structural checks of SigV4 `Authorization` headers, only made when the server runs
+//! with `--validate-signatures`. Signatures aren't verified, since the mock doesn't know
+//! any secret keys; this only catches clients that sign wrongly or not at all.
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+    web, Error,
+};
+
+use crate::error::SqsError;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SERVICE: &str = "sqs";
+const TERMINATOR: &str = "aws4_request";
+
+#[derive(Debug, Clone, Default)]
+pub struct SignatureCheck {
+    /// Region the credential scope must name; any region is accepted if unset
+    pub region: Option<String>,
+}
+
+impl SignatureCheck {
+    /// Check the `Authorization` header of a request, given its `X-Amz-Date` header.
+    pub fn check(
+        &self,
+        authorization: Option<&str>,
+        amz_date: Option<&str>,
+    ) -> Result<(), SqsError> {
+        let authorization = authorization.ok_or(SqsError::MissingAuthenticationToken)?;
+        let incomplete = |reason: &str| {
+            SqsError::IncompleteSignature(format!("{} Authorization={}", reason, authorization))
+        };
+
+        let fields = authorization
+            .strip_prefix(ALGORITHM)
+            .filter(|rest| rest.starts_with(' '))
+            .ok_or_else(|| incomplete("Unsupported or missing signing algorithm."))?;
+        let mut credential = None;
+        let mut signed_headers = None;
+        let mut signature = None;
+        for field in fields.split(',') {
+            match field.trim().split_once('=') {
+                Some(("Credential", value)) => credential = Some(value),
+                Some(("SignedHeaders", value)) => signed_headers = Some(value),
+                Some(("Signature", value)) => signature = Some(value),
+                _ => return Err(incomplete("Authorization header requires key=value pairs.")),
+            }
+        }
+        let credential =
+            credential.ok_or_else(|| incomplete("Authorization header requires 'Credential'."))?;
+        let signed_headers = signed_headers
+            .ok_or_else(|| incomplete("Authorization header requires 'SignedHeaders'."))?;
+        let signature =
+            signature.ok_or_else(|| incomplete("Authorization header requires 'Signature'."))?;
+
+        // <access key>/<yyyymmdd>/<region>/<service>/aws4_request
+        let scope: Vec<&str> = credential.split('/').collect();
+        let [access_key, date, region, service, terminator] = scope[..] else {
+            return Err(incomplete(
+                "Credential must have exactly 5 slash-delimited elements.",
+            ));
+        };
+        if access_key.is_empty() || region.is_empty() {
+            return Err(incomplete("Credential has an empty access key or region."));
+        }
+        if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(incomplete("Credential date must be in yyyyMMdd format."));
+        }
+        if terminator != TERMINATOR {
+            return Err(incomplete("Credential must end with 'aws4_request'."));
+        }
+
+        let signed: Vec<&str> = signed_headers.split(';').collect();
+        if !signed.contains(&"host") {
+            return Err(incomplete("SignedHeaders must include 'host'."));
+        }
+        if signature.len() != 64 || !signature.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(incomplete("Signature must be 64 hexadecimal characters."));
+        }
+
+        if service != SERVICE {
+            return Err(SqsError::SignatureDoesNotMatch(format!(
+                "Credential should be scoped to correct service: '{}'.",
+                SERVICE
+            )));
+        }
+        if self.region.as_deref().is_some_and(|r| r != region) {
+            return Err(SqsError::SignatureDoesNotMatch(format!(
+                "Credential should be scoped to a valid region, not '{}'.",
+                region
+            )));
+        }
+        if let Some(amz_date) = amz_date.filter(|d| !d.starts_with(date)) {
+            return Err(SqsError::SignatureDoesNotMatch(format!(
+                "Date in Credential scope does not match the X-Amz-Date header: '{}' != '{}'.",
+                date, amz_date
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Middleware rejecting API requests whose `Authorization` header is missing or malformed,
+/// when a [`SignatureCheck`] is registered as app data. Only `POST`s outside `/admin/` are
+/// checked, so the debugging endpoints, which SDKs don't sign, and CORS preflights stay open.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    if req.method() == Method::POST && !req.path().starts_with("/admin/") {
+        if let Some(check) = req.app_data::<web::Data<SignatureCheck>>() {
+            let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+            if let Err(e) = check.check(header("authorization"), header("x-amz-date")) {
+                return Ok(req.into_response(e.error_response()).map_into_right_body());
+            }
+        }
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header, middleware::from_fn, App, HttpResponse};
+
+    const SIGNATURE: &str = "fe5f80f77d5fa3beca038a248ff027d0445342fe2855ddc963176630326f1024";
+
+    fn authorization(credential: &str) -> String {
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}, SignedHeaders=content-type;host;x-amz-date, Signature={}",
+            credential, SIGNATURE
+        )
+    }
+
+    #[test]
+    fn test_well_formed() {
+        let check = SignatureCheck {
+            region: Some("us-east-1".to_string()),
+        };
+        let header = authorization("AKIDEXAMPLE/20261016/us-east-1/sqs/aws4_request");
+        assert_eq!(check.check(Some(&header), Some("20261016T120000Z")), Ok(()));
+        assert_eq!(check.check(Some(&header), None), Ok(()));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let check = SignatureCheck {
+            region: Some("us-east-1".to_string()),
+        };
+        let code = |header: &str| check.check(Some(header), None).unwrap_err().code();
+
+        assert_eq!(
+            check.check(None, None),
+            Err(SqsError::MissingAuthenticationToken)
+        );
+        assert_eq!(code("Bearer abc"), "IncompleteSignature");
+        assert_eq!(
+            code(&authorization("AKIDEXAMPLE/20261016/us-east-1/sqs")),
+            "IncompleteSignature"
+        );
+        assert_eq!(
+            code(&authorization(
+                "AKIDEXAMPLE/Oct16/us-east-1/sqs/aws4_request"
+            )),
+            "IncompleteSignature"
+        );
+        assert_eq!(
+            code("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20261016/us-east-1/sqs/aws4_request, SignedHeaders=host"),
+            "IncompleteSignature"
+        );
+        assert_eq!(
+            code(&authorization(
+                "AKIDEXAMPLE/20261016/us-east-1/s3/aws4_request"
+            )),
+            "SignatureDoesNotMatch"
+        );
+        assert_eq!(
+            code(&authorization(
+                "AKIDEXAMPLE/20261016/eu-west-1/sqs/aws4_request"
+            )),
+            "SignatureDoesNotMatch"
+        );
+
+        let header = authorization("AKIDEXAMPLE/20261016/us-east-1/sqs/aws4_request");
+        assert_eq!(
+            check
+                .check(Some(&header), Some("20261017T000000Z"))
+                .unwrap_err()
+                .code(),
+            "SignatureDoesNotMatch"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_middleware() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(SignatureCheck::default()))
+                .route("/", web::post().to(HttpResponse::Ok))
+                .route("/admin/reset", web::post().to(HttpResponse::Ok))
+                .wrap(from_fn(middleware)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/")
+            .insert_header((
+                header::AUTHORIZATION,
+                authorization("AKIDEXAMPLE/20261016/us-east-1/sqs/aws4_request"),
+            ))
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/")
+            .insert_header((header::AUTHORIZATION, "AWS4-HMAC-SHA256 Credential=AKID"))
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body = actix_web::test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("<Code>IncompleteSignature</Code>"));
+
+        // Admin endpoints are called unsigned, e.g. with curl
+        let req = actix_web::test::TestRequest::post()
+            .uri("/admin/reset")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+}
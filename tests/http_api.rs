@@ -0,0 +1,343 @@
+//! End-to-end tests of the HTTP API, through the same app the server runs: URL-encoded
+//! requests in, XML responses out, backed by a migrated in-memory SQLite database.
+
+use actix_web::{http::StatusCode, test};
+use serde::Deserialize;
+
+use sqs_navbat::{app, AppState};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CreateQueueResponse {
+    create_queue_result: QueueUrlResult,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct QueueUrlResult {
+    queue_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMessageResponse {
+    send_message_result: SendMessageResult,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMessageResult {
+    message_id: String,
+    #[serde(rename = "MD5OfMessageBody")]
+    md5_of_message_body: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReceiveMessageResponse {
+    receive_message_result: ReceiveMessageResult,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReceiveMessageResult {
+    #[serde(rename = "Message", default)]
+    messages: Vec<MessageXml>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageXml {
+    message_id: String,
+    receipt_handle: String,
+    body: String,
+    #[serde(rename = "Attribute", default)]
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetQueueAttributesResponse {
+    get_queue_attributes_result: GetQueueAttributesResult,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetQueueAttributesResult {
+    #[serde(rename = "Attribute", default)]
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Attribute {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteMessageResponse {
+    response_metadata: ResponseMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseMetadata {
+    request_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorDetail {
+    code: String,
+}
+
+async fn state() -> AppState {
+    AppState::in_memory().await.unwrap()
+}
+
+/// POST a form-encoded API request and return the status and XML body.
+macro_rules! call {
+    ($app:expr, $params:expr) => {{
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("content-type", "application/x-www-form-urlencoded"))
+            .set_payload(serde_urlencoded::to_string($params).unwrap())
+            .to_request();
+        let resp = test::call_service(&$app, req).await;
+        let status = resp.status();
+        let body = test::read_body(resp).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }};
+}
+
+fn attribute<'a>(attributes: &'a [Attribute], name: &str) -> &'a str {
+    attributes
+        .iter()
+        .find(|a| a.name == name)
+        .map(|a| a.value.as_str())
+        .unwrap_or_else(|| panic!("no attribute {}", name))
+}
+
+#[actix_web::test]
+async fn test_message_lifecycle() {
+    let app = test::init_service(app::build_app(state().await, &Default::default())).await;
+
+    let (status, body) = call!(
+        app,
+        [
+            ("Action", "CreateQueue"),
+            ("QueueName", "orders"),
+            ("Attribute.1.Name", "VisibilityTimeout"),
+            ("Attribute.1.Value", "45"),
+        ]
+    );
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let created: CreateQueueResponse = quick_xml::de::from_str(&body).unwrap();
+    let queue_url = created.create_queue_result.queue_url;
+    assert_eq!(queue_url, "http://localhost:9090/000000000000/orders");
+
+    let message_body = "order <42> & co";
+    let (status, body) = call!(
+        app,
+        [
+            ("Action", "SendMessage"),
+            ("QueueUrl", queue_url.as_str()),
+            ("MessageBody", message_body),
+        ]
+    );
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let sent: SendMessageResponse = quick_xml::de::from_str(&body).unwrap();
+    assert_eq!(
+        sent.send_message_result.md5_of_message_body,
+        format!("{:x}", md5::compute(message_body))
+    );
+
+    let (status, body) = call!(
+        app,
+        [
+            ("Action", "ReceiveMessage"),
+            ("QueueUrl", queue_url.as_str()),
+            ("AttributeName.1", "All"),
+        ]
+    );
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let received: ReceiveMessageResponse = quick_xml::de::from_str(&body).unwrap();
+    let messages = received.receive_message_result.messages;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].message_id, sent.send_message_result.message_id);
+    assert_eq!(messages[0].body, message_body);
+    assert_eq!(
+        attribute(&messages[0].attributes, "ApproximateReceiveCount"),
+        "1"
+    );
+
+    // In flight now, so a second receive comes back empty
+    let (_, body) = call!(
+        app,
+        [
+            ("Action", "ReceiveMessage"),
+            ("QueueUrl", queue_url.as_str()),
+        ]
+    );
+    let received: ReceiveMessageResponse = quick_xml::de::from_str(&body).unwrap();
+    assert!(received.receive_message_result.messages.is_empty());
+
+    let get_attributes = [
+        ("Action", "GetQueueAttributes"),
+        ("QueueUrl", queue_url.as_str()),
+        ("AttributeName.1", "All"),
+    ];
+    let (status, body) = call!(app, get_attributes);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let attributes: GetQueueAttributesResponse = quick_xml::de::from_str(&body).unwrap();
+    let attributes = attributes.get_queue_attributes_result.attributes;
+    assert_eq!(attribute(&attributes, "VisibilityTimeout"), "45");
+    assert_eq!(attribute(&attributes, "ApproximateNumberOfMessages"), "0");
+    assert_eq!(
+        attribute(&attributes, "ApproximateNumberOfMessagesNotVisible"),
+        "1"
+    );
+
+    let (status, body) = call!(
+        app,
+        [
+            ("Action", "DeleteMessage"),
+            ("QueueUrl", queue_url.as_str()),
+            ("ReceiptHandle", messages[0].receipt_handle.as_str()),
+        ]
+    );
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let deleted: DeleteMessageResponse = quick_xml::de::from_str(&body).unwrap();
+    assert!(!deleted.response_metadata.request_id.is_empty());
+
+    let (_, body) = call!(app, get_attributes);
+    let attributes: GetQueueAttributesResponse = quick_xml::de::from_str(&body).unwrap();
+    let attributes = attributes.get_queue_attributes_result.attributes;
+    assert_eq!(attribute(&attributes, "ApproximateNumberOfMessages"), "0");
+    assert_eq!(
+        attribute(&attributes, "ApproximateNumberOfMessagesNotVisible"),
+        "0"
+    );
+}
+
+#[actix_web::test]
+async fn test_queue_urls_without_account_id_still_work() {
+    let app = test::init_service(app::build_app(state().await, &Default::default())).await;
+    let (status, body) = call!(app, [("Action", "CreateQueue"), ("QueueName", "orders")]);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // The form queue URLs had before they included the account id
+    let (status, body) = call!(
+        app,
+        [
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/orders"),
+            ("MessageBody", "hi"),
+        ]
+    );
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (_, body) = call!(
+        app,
+        [
+            ("Action", "ReceiveMessage"),
+            ("QueueUrl", "http://localhost:9090/000000000000/orders"),
+        ]
+    );
+    let received: ReceiveMessageResponse = quick_xml::de::from_str(&body).unwrap();
+    assert_eq!(received.receive_message_result.messages[0].body, "hi");
+}
+
+#[actix_web::test]
+async fn test_errors_are_xml() {
+    let app = test::init_service(app::build_app(state().await, &Default::default())).await;
+
+    let (status, body) = call!(
+        app,
+        [
+            ("Action", "SendMessage"),
+            ("QueueUrl", "http://localhost:9090/000000000000/missing"),
+            ("MessageBody", "hi"),
+        ]
+    );
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: ErrorResponse = quick_xml::de::from_str(&body).unwrap();
+    assert_eq!(error.error.code, "AWS.SimpleQueueService.NonExistentQueue");
+
+    let (status, body) = call!(app, [("Action", "Frobnicate")]);
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: ErrorResponse = quick_xml::de::from_str(&body).unwrap();
+    assert_eq!(error.error.code, "InvalidAction");
+}
+
+#[actix_web::test]
+async fn test_payload_limit() {
+    let state = state().await;
+    let options = app::AppOptions {
+        max_payload_bytes: 64 * 1024,
+        ..Default::default()
+    };
+    let app = test::init_service(app::build_app(state, &options)).await;
+    let (status, body) = call!(app, [("Action", "CreateQueue"), ("QueueName", "q")]);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let send = |size: usize| {
+        [
+            ("Action", "SendMessage".to_string()),
+            (
+                "QueueUrl",
+                "http://localhost:9090/000000000000/q".to_string(),
+            ),
+            ("MessageBody", "a".repeat(size)),
+        ]
+    };
+    // Just under the limit once the other parameters are added
+    let (status, body) = call!(app, send(64 * 1024 - 200));
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = call!(app, send(64 * 1024 + 1));
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[actix_web::test]
+async fn test_default_payload_limit_fits_full_batch() {
+    let app = test::init_service(app::build_app(state().await, &Default::default())).await;
+    let (status, body) = call!(app, [("Action", "CreateQueue"), ("QueueName", "q")]);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let mut params = vec![
+        ("Action".to_string(), "SendMessageBatch".to_string()),
+        (
+            "QueueUrl".to_string(),
+            "http://localhost:9090/000000000000/q".to_string(),
+        ),
+    ];
+    for i in 1..=10 {
+        let prefix = format!("SendMessageBatchRequestEntry.{}", i);
+        params.push((format!("{}.Id", prefix), format!("m{}", i)));
+        params.push((format!("{}.MessageBody", prefix), "a".repeat(256 * 1024)));
+    }
+    let (status, body) = call!(app, &params);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.matches("<SendMessageBatchResultEntry>").count(), 10);
+}
+
+#[actix_web::test]
+async fn test_other_methods_on_root_are_not_allowed() {
+    let app = test::init_service(app::build_app(state().await, &Default::default())).await;
+    for req in [test::TestRequest::get(), test::TestRequest::put()] {
+        let resp = test::call_service(&app, req.uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(resp.headers().get("allow").unwrap(), "POST");
+        let body = test::read_body(resp).await;
+        let error: ErrorResponse =
+            quick_xml::de::from_str(std::str::from_utf8(&body).unwrap()).unwrap();
+        assert_eq!(error.error.code, "MethodNotAllowed");
+    }
+}